@@ -0,0 +1,62 @@
+use std::process;
+
+use crate::{
+    repository::Repository,
+    config::Config,
+    util,
+    error::{WitError, builder::*}
+};
+
+/// What `name` stands for when it is an alias from `alias.<name>`, followed by `args`.
+/// Shell aliases, starting with `!`, are run right away and their exit status is returned
+/// as an `exit_err`. `seen` holds the aliases expanded so far, so that loops are caught.
+pub fn expand(name: &str, args: &[String], seen: &mut Vec<String>) -> Result<Option<Vec<String>>, Box<WitError>> {
+    let repo = Repository::find(".", false)?;
+    let conf = match &repo {
+        Some(repo) => repo.conf.clone(),
+        None => Config::load(None)?
+    };
+    let value = match conf.get(&format!("alias.{}", name)) {
+        Some(value) => value,
+        None => return Ok(None)
+    };
+    if let Some(start) = seen.iter().position(|alias| alias == name) {
+        let mut message = format!("alias loop detected: expansion of '{}' does not terminate:", seen[0]);
+        for (n, alias) in seen.iter().enumerate() {
+            let marker = if n == start { " <==" } else if n == seen.len() - 1 { " ==>" } else { "" };
+            message.push_str(&format!("\n  {}{}", alias, marker));
+        }
+        Err(config_err(message))?
+    }
+    seen.push(name.to_owned());
+
+    if let Some(command) = value.strip_prefix('!') {
+        let code = self::run_shell(repo.as_ref(), command, args)?;
+        Err(exit_err(code as u8))?
+    }
+    let mut words = util::split_cmdline(&value).ok_or(config_err(format!("bad alias.{} string: unclosed quote", name)))?;
+    if words.is_empty() {
+        Err(config_err(format!("empty alias for {}", name)))?
+    }
+    words.extend(args.iter().cloned());
+    Ok(Some(words))
+}
+
+/// Run a shell alias with the remaining arguments, from the top of the worktree with
+/// `GIT_PREFIX` naming the directory it was started from.
+fn run_shell(repo: Option<&Repository>, command: &str, args: &[String]) -> Result<i32, Box<WitError>> {
+    let script = if args.is_empty() { command.to_owned() } else { format!("{} \"$@\"", command) };
+    let mut shell = process::Command::new("sh");
+    shell.arg("-c").arg(script).arg(command).args(args);
+    if let Some(repo) = repo {
+        let cwd = std::env::current_dir()?.canonicalize()?;
+        let top = repo.worktree.as_ref().unwrap_or(&repo.git_dir);
+        let prefix = match cwd.strip_prefix(top) {
+            Ok(prefix) if !prefix.as_os_str().is_empty() => format!("{}/", prefix.display()),
+            _ => String::new()
+        };
+        shell.current_dir(top).env("GIT_PREFIX", prefix);
+    }
+    let status = shell.status()?;
+    Ok(status.code().unwrap_or(1))
+}
@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{
+    repository::Repository,
+    ignore,
+    util,
+    error::WitError
+};
+
+/// The state of one attribute for a path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    Set,
+    Unset,
+    Unspecified,
+    Value(String)
+}
+
+impl State {
+    pub fn value(&self) -> Option<&str> {
+        match self {
+            State::Value(value) => Some(value),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            State::Set => write!(f, "set"),
+            State::Unset => write!(f, "unset"),
+            State::Unspecified => write!(f, "unspecified"),
+            State::Value(value) => write!(f, "{}", value)
+        }
+    }
+}
+
+type Assignments = Vec<(String, State)>;
+
+/// `attr`, `-attr`, `!attr` and `attr=value` words of an attributes line.
+fn parse_assignments<'s>(words: impl Iterator<Item = &'s str>) -> Assignments {
+    words.map(|word| {
+        if let Some(name) = word.strip_prefix('-') {
+            (name.to_owned(), State::Unset)
+        } else if let Some(name) = word.strip_prefix('!') {
+            (name.to_owned(), State::Unspecified)
+        } else if let Some((name, value)) = word.split_once('=') {
+            (name.to_owned(), State::Value(value.to_owned()))
+        } else {
+            (word.to_owned(), State::Set)
+        }
+    }).collect()
+}
+
+struct Line {
+    pattern: String,
+    anchored: bool,
+    base: String,
+    assignments: Assignments
+}
+
+/// Attribute rules of a worktree. `.gitattributes` files are loaded per directory as they
+/// are needed; `info/attributes` wins over them and `core.attributesFile` loses to them.
+pub struct Attributes<'a> {
+    repo: &'a Repository,
+    global: Vec<Line>,
+    info: Vec<Line>,
+    dirs: HashMap<String, Vec<Line>>,
+    macros: HashMap<String, Assignments>,
+    // Attribute names in the order they were first seen, for listing all of them.
+    names: Vec<String>
+}
+
+impl<'a> Attributes<'a> {
+    pub fn new(repo: &'a Repository) -> Result<Attributes<'a>, Box<WitError>> {
+        let mut ret = Attributes {
+            repo,
+            global: Vec::new(),
+            info: Vec::new(),
+            dirs: HashMap::new(),
+            macros: HashMap::from([
+                (String::from("binary"), parse_assignments(["-diff", "-merge", "-text"].into_iter()))
+            ]),
+            names: vec![String::from("binary"), String::from("diff"), String::from("merge"), String::from("text")]
+        };
+        let global = match repo.conf.get("core.attributesfile") {
+            Some(path) => Some(util::expand_user(&path)),
+            None => util::xdg_config("attributes")
+        };
+        if let Some(path) = global {
+            ret.global = ret.read(&path, "", true)?;
+        }
+        let top = match &repo.worktree {
+            Some(top) => ret.read(&top.join(".gitattributes"), "", true)?,
+            None => Vec::new()
+        };
+        ret.dirs.insert(String::new(), top);
+        ret.info = ret.read(&Repository::path(repo, vec!["info", "attributes"]), "", true)?;
+        Ok(ret)
+    }
+
+    fn read(&mut self, path: &PathBuf, base: &str, allow_macros: bool) -> Result<Vec<Line>, Box<WitError>> {
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let mut ret = Vec::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let pattern = words.next().unwrap_or("");
+            let assignments = parse_assignments(words);
+            let macro_name = pattern.strip_prefix("[attr]").map(|name| (name.to_owned(), State::Set));
+            for (name, _) in macro_name.iter().chain(&assignments) {
+                if !self.names.contains(name) {
+                    self.names.push(name.clone());
+                }
+            }
+            // Macros can only be defined at the top level.
+            if let Some(name) = pattern.strip_prefix("[attr]") {
+                if allow_macros {
+                    self.macros.insert(name.to_owned(), assignments);
+                }
+                continue;
+            }
+            if pattern.starts_with('!') || pattern.ends_with('/') {
+                // Negative and directory patterns are not allowed in attribute files.
+                continue;
+            }
+            let stripped = pattern.strip_prefix('/').unwrap_or(pattern);
+            ret.push(Line {
+                pattern: stripped.to_owned(),
+                anchored: pattern.contains('/'),
+                base: base.to_owned(),
+                assignments
+            });
+        }
+        Ok(ret)
+    }
+
+    fn load_dir(&mut self, dir: &str) -> Result<(), Box<WitError>> {
+        if !self.dirs.contains_key(dir) {
+            let base = format!("{}/", dir);
+            // A bare repository has no `.gitattributes` files.
+            let lines = match &self.repo.worktree {
+                Some(top) => self.read(&top.join(&base).join(".gitattributes"), &base, false)?,
+                None => Vec::new()
+            };
+            self.dirs.insert(dir.to_owned(), lines);
+        }
+        Ok(())
+    }
+
+    /// Every attribute that is not unspecified for `path`.
+    pub fn check(&mut self, path: &str) -> Result<BTreeMap<String, State>, Box<WitError>> {
+        let parts = path.split('/').collect::<Vec<&str>>();
+        let dirs = (0..parts.len()).map(|i| parts[..i].join("/")).collect::<Vec<String>>();
+        for dir in &dirs {
+            self.load_dir(dir)?;
+        }
+
+        // From the highest precedence down; the first assignment of an attribute wins,
+        // so files and lines are walked backwards.
+        let mut sources: Vec<&Vec<Line>> = vec![&self.info];
+        sources.extend(dirs.iter().rev().map(|dir| &self.dirs[dir]));
+        sources.push(&self.global);
+        let mut ret = BTreeMap::new();
+        for line in sources.into_iter().flat_map(|lines| lines.iter().rev()) {
+            if ignore::path_matches(&line.pattern, line.anchored, &line.base, path) {
+                self.assign(&line.assignments, &mut ret, 0);
+            }
+        }
+        ret.retain(|_, state| *state != State::Unspecified);
+        Ok(ret)
+    }
+
+    fn assign(&self, assignments: &Assignments, out: &mut BTreeMap<String, State>, depth: usize) {
+        for (name, state) in assignments.iter().rev() {
+            if out.contains_key(name) {
+                continue;
+            }
+            out.insert(name.clone(), state.clone());
+            // A set macro expands to its attributes; guard against macros using themselves.
+            if *state == State::Set && depth < 16 {
+                if let Some(expansion) = self.macros.get(name) {
+                    self.assign(expansion, out, depth + 1);
+                }
+            }
+        }
+    }
+
+    /// The state of attribute `name` for `path`.
+    pub fn get(&mut self, path: &str, name: &str) -> Result<State, Box<WitError>> {
+        Ok(self.check(path)?.remove(name).unwrap_or(State::Unspecified))
+    }
+
+    /// Names of the attributes that are not unspecified for `path`, in the order
+    /// they first appear in the attribute files.
+    pub fn all(&mut self, path: &str) -> Result<Vec<(String, State)>, Box<WitError>> {
+        let mut states = self.check(path)?;
+        Ok(self.names.iter().filter_map(|name| states.remove(name).map(|state| (name.clone(), state))).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A repository with the given files in its worktree.
+    fn repo(name: &str, files: &[(&str, &str)]) -> Repository {
+        let repo = Repository::temp(&format!("attr-{}", name));
+        for (file, text) in files {
+            let file = repo.worktree().unwrap().join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, text).unwrap();
+        }
+        repo
+    }
+
+    fn all(attrs: &mut Attributes, path: &str) -> Vec<String> {
+        attrs.all(path).unwrap().iter().map(|(name, state)| format!("{}: {}", name, state)).collect()
+    }
+
+    #[test]
+    fn macros() {
+        let repo = repo("macros", &[
+            (".gitattributes", "[attr]mine text eol=lf\n*.png binary\nboth.png diff\n*.c mine\n"),
+            ("sub/.gitattributes", "[attr]sub -text\n*.c sub\n")
+        ]);
+        let mut attrs = Attributes::new(&repo).unwrap();
+        assert_eq!(all(&mut attrs, "a.png"), ["binary: set", "diff: unset", "merge: unset", "text: unset"]);
+        // A later line overrides part of the expansion.
+        assert_eq!(all(&mut attrs, "both.png"), ["binary: set", "diff: set", "merge: unset", "text: unset"]);
+        assert_eq!(all(&mut attrs, "x.c"), ["text: set", "mine: set", "eol: lf"]);
+        // Macros can only be defined at the top level.
+        assert_eq!(attrs.get("sub/x.c", "sub").unwrap(), State::Set);
+        assert_eq!(attrs.get("sub/x.c", "text").unwrap(), State::Set);
+    }
+
+    #[test]
+    fn last_match_wins() {
+        let repo = repo("last-match", &[
+            (".gitattributes", "*.txt text eol=crlf\nfoo.txt -text eol=lf\n*.md text\n*.md !text\n"),
+            ("sub/.gitattributes", "*.txt -text\n"),
+            (".git/info/attributes", "info.txt -text\n")
+        ]);
+        let mut attrs = Attributes::new(&repo).unwrap();
+        assert_eq!(all(&mut attrs, "foo.txt"), ["text: unset", "eol: lf"]);
+        assert_eq!(all(&mut attrs, "bar.txt"), ["text: set", "eol: crlf"]);
+        assert!(all(&mut attrs, "a.md").is_empty());
+        // Deeper files win over the top level, and info/attributes over both.
+        assert_eq!(all(&mut attrs, "sub/bar.txt"), ["text: unset", "eol: crlf"]);
+        assert_eq!(attrs.get("info.txt", "text").unwrap(), State::Unset);
+    }
+}
@@ -1,9 +1,11 @@
-
-use clap::{Command, arg};
+use std::path::Path;
+use clap::{Command, Arg, ArgMatches, arg};
 use crate::error::{
-    builder::{ cli_unknown_command_err, cli_no_command_err },
+    builder::{ cli_unknown_command_err, cli_no_command_err, io_err, exit_err },
     WitError
 };
+use crate::sequencer::Action;
+use crate::{ alias, external };
 
 pub trait CliExecute<'a> {
     fn execute(self) -> Result<(), Box<WitError>>;
@@ -11,24 +13,78 @@ pub trait CliExecute<'a> {
 
 impl<'a> CliExecute<'a> for Command<'a> {
     fn execute(self) -> Result<(), Box<WitError>> {
-        match self.get_matches().subcommand() {
-            Some(("init", args)) => commands::init(args),
-            Some(("cat-file", args)) => commands::cat_file(args),
-            Some(("hash-object", args)) => commands::hash_object(args),
-            Some(("log", args)) => commands::log(args),
-            Some(("ls-tree", args)) => commands::ls_tree(args),
-            Some(("checkout", args)) => commands::checkout(args),
-            Some(("show-ref", _)) => commands::show_ref(),
-            Some(("tag", args)) => commands::tag(args),
-            Some(("rev-parse", args)) => commands::rev_parse(args),
-            Some((invalid_cmd, _)) => {
-                Err(cli_unknown_command_err(invalid_cmd))
+        let matches = self.clone().get_matches();
+        self::dispatch(self, &matches, &mut Vec::new())
+    }
+}
+
+/// Apply the global options and run the subcommand. Subcommands we do not know are
+/// `wit-<name>` programs on `PATH` or else aliases, which are parsed again once expanded.
+fn dispatch(app: Command, matches: &ArgMatches, aliases: &mut Vec<String>) -> Result<(), Box<WitError>> {
+    self::global_options(matches)?;
+    match matches.subcommand() {
+        Some(("init", args)) => commands::init(args),
+        Some(("cat-file", args)) => commands::cat_file(args),
+        Some(("hash-object", args)) => commands::hash_object(args),
+        Some(("log", args)) => commands::log(args),
+        Some(("ls-tree", args)) => commands::ls_tree(args),
+        Some(("checkout", args)) => commands::checkout(args),
+        Some(("show-ref", args)) => commands::show_ref(args),
+        Some(("tag", args)) => commands::tag(args),
+        Some(("rev-parse", args)) => commands::rev_parse(args),
+        Some(("pack-refs", args)) => commands::pack_refs(args),
+        Some(("reflog", args)) => commands::reflog(args),
+        Some(("update-ref", args)) => commands::update_ref(args),
+        Some(("symbolic-ref", args)) => commands::symbolic_ref(args),
+        Some(("for-each-ref", args)) => commands::for_each_ref(args),
+        Some(("merge-base", args)) => commands::merge_base(args),
+        Some(("merge", args)) => commands::merge(args),
+        Some(("cherry-pick", args)) => commands::sequence(Action::Pick, args),
+        Some(("revert", args)) => commands::sequence(Action::Revert, args),
+        Some(("rebase", args)) => commands::rebase(args),
+        Some(("reset", args)) => commands::reset(args),
+        Some(("restore", args)) => commands::restore(args),
+        Some(("stash", args)) => commands::stash(args),
+        Some(("check-ignore", args)) => commands::check_ignore(args),
+        Some(("check-attr", args)) => commands::check_attr(args),
+        Some(("config", args)) => commands::config(args),
+        Some(("clone", args)) => commands::clone(args),
+        Some(("remote", args)) => commands::remote(args),
+        Some((name, args)) => {
+            let rest = args.values_of_lossy("").unwrap_or_default();
+            if external::find(name).is_some() {
+                return match external::run(name, &rest)? {
+                    0 => Ok(()),
+                    code => Err(exit_err(code as u8))
+                };
             }
-            None => {
-                Err(cli_no_command_err())
+            match alias::expand(name, &rest, aliases)? {
+                Some(expanded) => {
+                    let matches = app.clone().get_matches_from(std::iter::once(String::from("wit")).chain(expanded));
+                    self::dispatch(app, &matches, aliases)
+                },
+                None => Err(cli_unknown_command_err(name))
             }
         }
+        None => {
+            Err(cli_no_command_err())
+        }
+    }
+}
+
+/// `-C` changes directory, in order, before anything else happens; `--git-dir` and
+/// `--work-tree` stand in for `GIT_DIR` and `GIT_WORK_TREE`.
+fn global_options(matches: &ArgMatches) -> Result<(), Box<WitError>> {
+    for dir in matches.values_of("chdir").into_iter().flatten().filter(|dir| !dir.is_empty()) {
+        std::env::set_current_dir(dir).map_err(|err| io_err(format!("cannot change to '{}': {}", dir, err)))?;
     }
+    if let Some(git_dir) = matches.value_of("git_dir") {
+        std::env::set_var("GIT_DIR", Path::new(git_dir));
+    }
+    if let Some(work_tree) = matches.value_of("work_tree") {
+        std::env::set_var("GIT_WORK_TREE", Path::new(work_tree));
+    }
+    Ok(())
 }
 
 pub fn setup<'a>() -> Command<'a> {
@@ -39,16 +95,74 @@ pub fn setup<'a>() -> Command<'a> {
     .propagate_version(true)
     .subcommand_required(true)
     .arg_required_else_help(true)
+    .allow_external_subcommands(true)
+    .allow_invalid_utf8_for_external_subcommands(true)
+    .arg(
+        arg!(-C <path>)
+        .id("chdir")
+        .required(false)
+        .multiple_occurrences(true)
+        .help("Run as if wit was started in <path>")
+        .display_order(0)
+    )
+    .arg(
+        arg!(--"git-dir" <path>)
+        .id("git_dir")
+        .required(false)
+        .help("Path to the repository, instead of searching for it")
+        .display_order(1)
+    )
+    .arg(
+        arg!(--"work-tree" <path>)
+        .id("work_tree")
+        .required(false)
+        .help("Path to the working tree")
+        .display_order(2)
+    )
     .subcommands(vec![
         // init
         Command::new("init")
         .display_order(0)
         .about("Create a new git repository")
+        .arg(
+            arg!(--bare)
+            .required(false)
+            .help("Create a bare repository, without a worktree")
+            .display_order(0)
+        )
+        .arg(
+            arg!(-b --"initial-branch" <name>)
+            .id("initial_branch")
+            .required(false)
+            .help("Name of the initial branch, instead of init.defaultBranch or main")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--template <dir>)
+            .required(false)
+            .help("Directory whose files are copied into the new git directory")
+            .display_order(2)
+        )
+        .arg(
+            arg!(--shared [permissions])
+            .required(false)
+            .min_values(0)
+            .require_equals(true)
+            .default_missing_value("group")
+            .help("Share the repository with the group, everybody (all) or by octal permissions")
+            .display_order(3)
+        )
+        .arg(
+            arg!(-q --quiet)
+            .required(false)
+            .help("Only print errors and warnings")
+            .display_order(4)
+        )
         .arg(
             arg!([path])
             .required(false)
             .help("Directory location for the new repo. If omitted, defaults to pwd.")
-            .display_order(0)
+            .display_order(5)
         ),
         // cat-file
         Command::new("cat-file")
@@ -97,11 +211,25 @@ pub fn setup<'a>() -> Command<'a> {
             .help("Actually write the object into the database")
             .display_order(1)
         )
+        .arg(
+            arg!(--path <file>)
+            .required(false)
+            .conflicts_with("no_filters")
+            .help("Apply the attributes and filters of this path instead of <file>'s")
+            .display_order(2)
+        )
+        .arg(
+            arg!(--"no-filters")
+            .id("no_filters")
+            .required(false)
+            .help("Hash the contents as they are, without eol conversion or filters")
+            .display_order(3)
+        )
         .arg(
             arg!([file])
             .required(true)
             .help("Read the object from <file>")
-            .display_order(2)
+            .display_order(4)
         ),
         // log
         Command::new("log")
@@ -133,14 +261,12 @@ pub fn setup<'a>() -> Command<'a> {
         .about("Checkout a commit, a branch, or a tag")
         .arg(
             arg!([commit])
-            .required(true)
             .default_value("HEAD")
             .display_order(0)
             .help("The commit or tree to checkout.")
         )
         .arg(
             arg!([path])
-            .required(true)
             .default_value("master")
             .display_order(1)
             .help("The EMPTY directory to checkout on.")
@@ -148,7 +274,66 @@ pub fn setup<'a>() -> Command<'a> {
         // show-ref
         Command::new("show-ref")
         .display_order(6)
-        .about("List references"),
+        .about("List references")
+        .arg(
+            arg!(--head)
+            .required(false)
+            .help("Show the HEAD reference, even if it would normally be filtered out")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--heads)
+            .required(false)
+            .help("Limit to refs/heads")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--tags)
+            .required(false)
+            .help("Limit to refs/tags")
+            .display_order(2)
+        )
+        .arg(
+            arg!(-d --dereference)
+            .required(false)
+            .help("Also show the objects annotated tags point to, as <tag>^{}")
+            .display_order(3)
+        )
+        .arg(
+            arg!(-s --hash [n])
+            .required(false)
+            .require_equals(true)
+            .default_missing_value("40")
+            .validator(|n| n.parse::<usize>())
+            .help("Only show the object name, abbreviated to <n> characters if given")
+            .display_order(4)
+        )
+        .arg(
+            arg!(--abbrev [n])
+            .required(false)
+            .require_equals(true)
+            .default_missing_value("7")
+            .validator(|n| n.parse::<usize>())
+            .help("Abbreviate object names to <n> characters")
+            .display_order(5)
+        )
+        .arg(
+            arg!(--verify)
+            .required(false)
+            .help("Require an exact ref path for each argument")
+            .display_order(6)
+        )
+        .arg(
+            arg!(-q --quiet)
+            .required(false)
+            .help("Do not print anything, only set the exit status")
+            .display_order(7)
+        )
+        .arg(
+            arg!([pattern] ...)
+            .help("Show refs whose name ends with a pattern, or the refs given with --verify")
+            .display_order(8)
+        ),
         // tag
         Command::new("tag")
         .display_order(7)
@@ -161,6 +346,14 @@ pub fn setup<'a>() -> Command<'a> {
             .help("Create a tag object")
             .display_order(0)
         )
+        .arg(
+            arg!(-d --delete)
+            .required(false)
+            .requires("name")
+            .conflicts_with("create_tag_object")
+            .help("Delete the named tag")
+            .display_order(0)
+        )
         .arg(
             arg!([name])
             .required(false)
@@ -198,203 +391,1099 @@ pub fn setup<'a>() -> Command<'a> {
             .help("The object to parse")
             .display_order(1)
         ),
-    ])
-}
-
-mod commands {
-    use std::{
-        env::current_dir,
-        io::{ stdout, Write },
-        fs,
-        str::from_utf8,
-        path::PathBuf
-    };
-    use clap::ArgMatches;
-    use crate::{
-        error::{ builder::*, WitError },
-        repository::Repository,
-        object::{ self, WitObject },
-        tree::Tree,
-        reference::{ self, Ref::* },
-        tag
-    };
-
-    pub fn init(sub_matches: &ArgMatches) -> Result<(), Box<WitError>> {
-        let pwd = match current_dir() {
-            Ok(dir) => dir,
-            Err(_) => Err(io_err(String::from("Could not find pwd")))?
-        };
-        let pwd = match pwd.to_str() {
-            Some(string) => {
-                String::from(string)
-            },
-            None => Err(io_err(String::from("Could not read pwd")))?
-        };
-
-        if let Err(e) = Repository::create(sub_matches.value_of("path").unwrap_or(pwd.as_str())) {
-            println!("{}", e);
-            eprintln!("Could not create repo.");
-        }
-        Ok(())
-    }
-
-    pub fn cat_file(args: &ArgMatches) -> Result<(), Box<WitError>> {
-        let repo: Repository = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
-
-        let data = object::find(
-            &repo,
-            args.value_of("object").ok_or(io_err(format!("No object specified")))?,
-            Some(args.value_of("file_type").ok_or(io_err(format!("No file type specified")))?),
-            true
-        )?;
-        let obj = object::read(
-            &repo,
-            data.as_str()
-        )?;
-
-        let mut out = stdout();
-        out.write(obj.serialize()?.as_slice())?;
-        out.flush()?;
-        Ok(())
-    }
-
-    pub fn hash_object(args: &ArgMatches) -> Result<(), Box<WitError>> {
-        let r = Repository::new(".", false)?;
-        let repo = if args.is_present("write") {
-            Some(&r)
-        } else {
-            None
-        };
-
-        let sha = object::hash(
-            from_utf8(
-                &fs::read(
-                    args.value_of("file").ok_or(
-                        cli_argument_err("file")
-                    )?
-                )?[..]
-            )?,
-            args.value_of("type").ok_or(
-                cli_argument_err("type")
-            )?,
-            repo
-        );
-        println!("{sha:?}");
-        Ok(())
-    }
-
-    pub fn log(args: &ArgMatches) -> Result<(), Box<WitError>> {
-        let commit = args.value_of("commit").ok_or(cli_argument_err("commit"))?;
-        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
-        println!("digraph log {{\n");
-        object::graphviz(
-            &repo,
-            object::find(&repo, commit, None, true)?,
-            &mut Vec::new()
-        )?;
-        println!("}}");
-        Ok(())
-    }
-
-    pub fn ls_tree(args: &ArgMatches) -> Result<(), Box<WitError>> {
-        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
-        let obj_name = args.value_of("object").ok_or(
-            cli_argument_err("object")
-        )?;
-        let obj = match object::read(&repo, &object::find(&repo, obj_name, Some("tree"), true)?)? {
-            WitObject::TreeObject(tree) => tree,
-            _ => Err(malformed_object_err(format!("Object {} is not a tree", obj_name)))?
-        };
-
-        let mut mode_str = String::new();
-        for leaf in obj.leaves() {
-            mode_str.clear();
-            for _ in 0..(6 - leaf.mode().len()) {
-                mode_str.push('0');
-            }
-            mode_str.push_str(leaf.mode());
-
-            println!(
-                "{} {} {}\t{}",
-                mode_str,
-                String::from_utf8(object::read(&repo, leaf.sha())?.serialize()?)?,
-                leaf.sha(),
-                leaf.path().to_str().unwrap()
-            );
-        }
-        Ok(())
-    }
-
-    pub fn checkout(args: &ArgMatches) -> Result<(), Box<WitError>> {
-        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
-        let obj_name = args.value_of("commit").ok_or(cli_argument_err("commit"))?;
-        let obj: Tree = match object::read(&repo, &object::find(&repo, obj_name, Some("tree"), true)?)? {
-            WitObject::CommitObject(commit) => {
-                match object::read(
-                    &repo,
-                    &commit.kvlm().get("tree").ok_or(
-                        missing_data_err(format!("No tree in commit {}", obj_name))
-                    )?[0]
-                )? {
-                    WitObject::TreeObject(tree) => tree,
-                    _ => Err(malformed_object_err(format!("Could not find tree from object {}", obj_name)))?
-                }
-            },
-            WitObject::TreeObject(tree) => tree,
-            other => Err(malformed_object_err(format!("Expected a commit or tree object, got {}", from_utf8(&other.fmt()).unwrap_or("?"))))?
-        };
-
-        let path = PathBuf::from(args.value_of("path").ok_or(
-            cli_argument_err("path")
-        )?);
-        if path.exists() {
-            if !path.is_dir() {
-                Err(not_a_directory_err(&path))?
-            }
-            if !path.read_dir()?.next().is_none() {
-                Err(dir_not_empty_err(&path))?
-            }
-        } else {
-            fs::create_dir_all(&path)?;
-        }
-
-        object::checkout(&repo, &obj, &path.canonicalize()?)?;
-        Ok(())
-    }
-
-    pub fn show_ref() -> Result<(), Box<WitError>> {
-        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
-        let refs = reference::list(&repo, None)?;
-        reference::show(&repo, &refs, true, "refs")?;
-        Ok(())
-    }
-
-    pub fn tag(args: &ArgMatches) -> Result<(), Box<WitError>> {
-        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
-        if args.is_present("name") {
-            tag::create(
-                &repo,
-                args.value_of("name").ok_or(
-                    cli_argument_err("name")
-                )?,
-                args.value_of("object").ok_or(
-                    cli_argument_err("object")
-                )?,
-                args.is_present("create_tag_object")
+        // pack-refs
+        Command::new("pack-refs")
+        .display_order(9)
+        .about("Pack heads and tags for efficient repository access")
+        .arg(
+            arg!(--all)
+            .required(false)
+            .help("Pack all refs, not only tags and already packed refs")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--"no-prune")
+            .id("no_prune")
+            .required(false)
+            .help("Keep the loose refs after packing them")
+            .display_order(1)
+        ),
+        // reflog
+        Command::new("reflog")
+        .display_order(10)
+        .about("Manage reflog information")
+        .args_conflicts_with_subcommands(true)
+        .arg(
+            arg!([ref])
+            .default_value("HEAD")
+            .help("The ref whose log to show")
+            .display_order(0)
+        )
+        .subcommands(vec![
+            Command::new("show")
+            .about("Show the log of a ref")
+            .arg(
+                arg!([ref])
+                .default_value("HEAD")
+                .help("The ref whose log to show")
+            ),
+            Command::new("expire")
+            .about("Prune entries older than a given time")
+            .arg(
+                arg!(--expire <time>)
+                .required(false)
+                .default_value("90.days.ago")
+                .help("Prune entries older than this time")
             )
-        } else {
-            let refs = reference::list(&repo, None)?;
-            let tags = match refs.get("tags").unwrap() {
-                Indirect(refs) => refs,
-                Direct(direct) => Err(
-                    unknown_reference_err(
-                        format!("Expected an indirect reference, got direct reference {}", direct)
-                    )
-                )?
-            };
-            reference::show(&repo, tags, true, "")
-        }
-    }
+            .arg(
+                arg!(--all)
+                .required(false)
+                .help("Process the reflogs of all refs")
+            )
+            .arg(
+                arg!([refs] ...)
+                .required_unless_present("all")
+                .help("The refs whose logs to prune")
+            ),
+            Command::new("delete")
+            .about("Delete single entries from the reflog")
+            .arg_required_else_help(true)
+            .arg(
+                arg!(<entries> ...)
+                .help("Entries to delete, as <ref>@{<n>}")
+            ),
+        ]),
+        // update-ref
+        Command::new("update-ref")
+        .display_order(11)
+        .about("Update the object name stored in a ref safely")
+        .arg_required_else_help(true)
+        .arg(
+            arg!(message: -m <reason>)
+            .required(false)
+            .help("Reason for the update, recorded in the reflog")
+            .display_order(0)
+        )
+        .arg(
+            arg!(delete: -d)
+            .required(false)
+            .help("Delete the ref after verifying it still contains <oldvalue>")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--"no-deref")
+            .id("no_deref")
+            .required(false)
+            .help("Update the ref itself rather than the ref it points to")
+            .display_order(2)
+        )
+        .arg(
+            arg!(--stdin)
+            .required(false)
+            .conflicts_with_all(&["ref", "delete"])
+            .help("Read update, create, delete and verify instructions from stdin")
+            .display_order(3)
+        )
+        .arg(
+            arg!([ref])
+            .required_unless_present("stdin")
+            .help("The ref to update")
+            .display_order(4)
+        )
+        .arg(
+            arg!([newvalue])
+            .help("The new value of the ref, or <oldvalue> with -d")
+            .display_order(5)
+        )
+        .arg(
+            arg!([oldvalue])
+            .help("Only update the ref if it currently holds this value")
+            .display_order(6)
+        ),
+        // symbolic-ref
+        Command::new("symbolic-ref")
+        .display_order(12)
+        .about("Read, modify and delete symbolic refs")
+        .arg_required_else_help(true)
+        .arg(
+            arg!(-q --quiet)
+            .required(false)
+            .help("Do not print an error if <name> is not a symbolic ref")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--short)
+            .required(false)
+            .help("Shorten the printed ref, e.g. refs/heads/main to main")
+            .display_order(1)
+        )
+        .arg(
+            arg!(-d --delete)
+            .required(false)
+            .conflicts_with("ref")
+            .help("Delete the symbolic ref <name>")
+            .display_order(2)
+        )
+        .arg(
+            arg!(message: -m <reason>)
+            .required(false)
+            .help("Reason for the update, recorded in the reflog")
+            .display_order(3)
+        )
+        .arg(
+            arg!(<name>)
+            .help("The symbolic ref, e.g. HEAD")
+            .display_order(4)
+        )
+        .arg(
+            arg!([ref])
+            .help("The ref <name> should point to")
+            .display_order(5)
+        ),
+        // for-each-ref
+        Command::new("for-each-ref")
+        .display_order(13)
+        .about("Output information on each ref")
+        .arg(
+            arg!(--sort <key>)
+            .required(false)
+            .multiple_occurrences(true)
+            .help("Field to sort on, prefix with - for descending order. May be repeated")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--count <n>)
+            .required(false)
+            .validator(|n| n.parse::<usize>())
+            .help("Stop after showing <n> refs")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--format <format>)
+            .required(false)
+            .help("Format string with %(fieldname) placeholders")
+            .display_order(2)
+        )
+        .arg(
+            arg!(--contains <commit>)
+            .required(false)
+            .help("Only list refs which contain <commit>")
+            .display_order(3)
+        )
+        .arg(
+            arg!(--merged [commit])
+            .required(false)
+            .default_missing_value("HEAD")
+            .help("Only list refs reachable from <commit>, HEAD by default")
+            .display_order(4)
+        )
+        .arg(
+            arg!([pattern] ...)
+            .help("Only list refs matching a pattern")
+            .display_order(5)
+        ),
+        // merge-base
+        Command::new("merge-base")
+        .display_order(14)
+        .about("Find as good common ancestors as possible for a merge")
+        .arg_required_else_help(true)
+        .arg(
+            arg!(-a --all)
+            .required(false)
+            .help("Output all merge bases instead of just one")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--octopus)
+            .required(false)
+            .conflicts_with_all(&["is_ancestor", "fork_point"])
+            .help("Compute the best common ancestors of all supplied commits")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--"is-ancestor")
+            .id("is_ancestor")
+            .required(false)
+            .conflicts_with_all(&["all", "fork_point"])
+            .help("Exit with status 0 if the first commit is an ancestor of the second, 1 otherwise")
+            .display_order(2)
+        )
+        .arg(
+            arg!(--"fork-point")
+            .id("fork_point")
+            .required(false)
+            .conflicts_with("all")
+            .help("Find where <commit> forked from the reflog of <ref>")
+            .display_order(3)
+        )
+        .arg(
+            arg!(<commits> ...)
+            .help("The commits to compare; <ref> [<commit>] with --fork-point")
+            .display_order(4)
+        ),
+        // merge
+        Command::new("merge")
+        .display_order(15)
+        .about("Join another branch into the current one")
+        .arg(
+            arg!(message: -m <message>)
+            .required(false)
+            .help("Message for the merge commit")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--"ff-only")
+            .id("ff_only")
+            .required(false)
+            .conflicts_with("no_ff")
+            .help("Refuse to merge unless the current HEAD can be fast-forwarded")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--"no-ff")
+            .id("no_ff")
+            .required(false)
+            .help("Create a merge commit even when the merge could be a fast-forward")
+            .display_order(2)
+        )
+        .arg(
+            arg!(--conflict <style>)
+            .required(false)
+            .possible_values(["merge", "diff3"])
+            .help("Conflict marker style, overriding merge.conflictStyle")
+            .display_order(3)
+        )
+        .arg(
+            arg!(--abort)
+            .required(false)
+            .conflicts_with_all(&["continue", "commit"])
+            .help("Abort the current merge and go back to the state before it")
+            .display_order(4)
+        )
+        .arg(
+            arg!(--continue)
+            .required(false)
+            .conflicts_with("commit")
+            .help("Conclude a merge once its conflicts are resolved")
+            .display_order(5)
+        )
+        .arg(
+            arg!([commit])
+            .required_unless_present_any(["abort", "continue"])
+            .help("The commit to merge into the current branch")
+            .display_order(6)
+        ),
+        // cherry-pick
+        Command::new("cherry-pick")
+        .display_order(16)
+        .about("Apply the changes introduced by some existing commits")
+        .args(sequencer_args())
+        .arg(
+            arg!(record_origin: -x)
+            .required(false)
+            .help("Append a line that says which commit this one was cherry-picked from")
+            .display_order(2)
+        ),
+        // revert
+        Command::new("revert")
+        .display_order(17)
+        .about("Revert some existing commits")
+        .args(sequencer_args()),
+        // rebase
+        Command::new("rebase")
+        .display_order(18)
+        .about("Reapply commits on top of another base commit")
+        .arg(
+            arg!(-i --interactive)
+            .required(false)
+            .help("Edit the list of commits to rebase before starting")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--onto <newbase>)
+            .required(false)
+            .help("Starting point for the new commits instead of <upstream>")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--autosquash)
+            .required(false)
+            .help("Move fixup! and squash! commits next to the commits they amend")
+            .display_order(2)
+        )
+        .arg(
+            arg!(--continue)
+            .required(false)
+            .conflicts_with_all(&["skip", "abort", "upstream"])
+            .help("Continue after resolving conflicts or editing a commit")
+            .display_order(3)
+        )
+        .arg(
+            arg!(--skip)
+            .required(false)
+            .conflicts_with_all(&["abort", "upstream"])
+            .help("Skip the current commit and continue with the rest")
+            .display_order(4)
+        )
+        .arg(
+            arg!(--abort)
+            .required(false)
+            .conflicts_with("upstream")
+            .help("Stop rebasing and return to the original branch")
+            .display_order(5)
+        )
+        .arg(
+            arg!([upstream])
+            .required_unless_present_any(["continue", "skip", "abort"])
+            .help("Commit whose descendants on the current branch are left out")
+            .display_order(6)
+        )
+        .arg(
+            arg!([branch])
+            .help("Branch to check out and rebase instead of the current one")
+            .display_order(7)
+        ),
+        // reset
+        Command::new("reset")
+        .display_order(19)
+        .about("Reset the current HEAD, or index entries, to a commit")
+        .arg(
+            arg!(--soft)
+            .required(false)
+            .conflicts_with_all(&["mixed", "hard"])
+            .help("Only move the current branch; the index and worktree are left alone")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--mixed)
+            .required(false)
+            .conflicts_with("hard")
+            .help("Move the branch and reset the index, but not the worktree (default)")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--hard)
+            .required(false)
+            .help("Move the branch and reset both the index and the worktree")
+            .display_order(2)
+        )
+        .arg(
+            arg!([commit])
+            .help("The commit to reset to, HEAD by default")
+            .display_order(3)
+        )
+        .arg(
+            arg!([paths] ...)
+            .last(true)
+            .help("Only reset the index entries of these paths")
+            .display_order(4)
+        ),
+        // restore
+        Command::new("restore")
+        .display_order(20)
+        .about("Restore worktree files or index entries from the index or a tree")
+        .arg(
+            arg!(-s --source <tree>)
+            .required(false)
+            .help("Tree-ish to restore from; the index for the worktree and HEAD for --staged by default")
+            .display_order(0)
+        )
+        .arg(
+            arg!(-S --staged)
+            .required(false)
+            .help("Restore the index")
+            .display_order(1)
+        )
+        .arg(
+            arg!(-W --worktree)
+            .required(false)
+            .help("Restore the worktree (default unless --staged is given)")
+            .display_order(2)
+        )
+        .arg(
+            arg!(<pathspec> ...)
+            .help("Paths to restore")
+            .display_order(3)
+        ),
+        // stash
+        Command::new("stash")
+        .display_order(21)
+        .about("Stash away local changes and reapply them later")
+        .subcommands(vec![
+            Command::new("push")
+            .about("Save local changes as a new stash entry and revert them (default)")
+            .arg(
+                arg!(-m --message <message>)
+                .required(false)
+                .help("Description of the stash entry")
+            )
+            .arg(
+                arg!(-k --"keep-index")
+                .id("keep_index")
+                .required(false)
+                .help("Leave staged changes in the index and worktree")
+            )
+            .arg(
+                arg!(-u --"include-untracked")
+                .id("include_untracked")
+                .required(false)
+                .help("Stash untracked files too, and remove them")
+            ),
+            Command::new("pop")
+            .about("Apply a stash entry and drop it unless there were conflicts")
+            .arg(arg!([stash]).help("The entry, as stash@{<n>} or <n>; the latest by default")),
+            Command::new("apply")
+            .about("Apply a stash entry and keep it")
+            .arg(arg!([stash]).help("The entry, as stash@{<n>} or <n>; the latest by default")),
+            Command::new("list")
+            .about("List the stash entries"),
+            Command::new("show")
+            .about("Show the changes recorded in a stash entry as a diffstat")
+            .arg(arg!([stash]).help("The entry, as stash@{<n>} or <n>; the latest by default")),
+            Command::new("drop")
+            .about("Remove a stash entry")
+            .arg(arg!([stash]).help("The entry, as stash@{<n>} or <n>; the latest by default")),
+            Command::new("clear")
+            .about("Remove all stash entries"),
+        ]),
+        // check-ignore
+        Command::new("check-ignore")
+        .display_order(22)
+        .about("Debug gitignore and exclude files")
+        .arg(
+            arg!(-q --quiet)
+            .required(false)
+            .conflicts_with("verbose")
+            .help("Print nothing, only set the exit status")
+            .display_order(0)
+        )
+        .arg(
+            arg!(-v --verbose)
+            .required(false)
+            .help("Show the source file, line number and pattern that matched")
+            .display_order(1)
+        )
+        .arg(
+            arg!(-n --"non-matching")
+            .id("non_matching")
+            .required(false)
+            .requires("verbose")
+            .help("Also show paths that match no pattern")
+            .display_order(2)
+        )
+        .arg(
+            arg!(--"no-index")
+            .id("no_index")
+            .required(false)
+            .help("Check tracked files too")
+            .display_order(3)
+        )
+        .arg(
+            arg!(<pathname> ...)
+            .help("Paths to check")
+            .display_order(4)
+        ),
+        // check-attr
+        Command::new("check-attr")
+        .display_order(23)
+        .about("Display gitattributes information")
+        .arg(
+            arg!(-a --all)
+            .required(false)
+            .help("List all attributes that are set on each path")
+            .display_order(0)
+        )
+        .arg(
+            arg!(<args> ...)
+            .help("Attributes to check, then paths; without -- the first is an attribute")
+            .display_order(1)
+        )
+        .arg(
+            arg!([paths] ...)
+            .last(true)
+            .help("Paths to check")
+            .display_order(2)
+        ),
+        // config
+        Command::new("config")
+        .display_order(24)
+        .about("Get and set repository or global options")
+        .arg(
+            arg!(--global)
+            .required(false)
+            .conflicts_with_all(&["system", "local", "file"])
+            .help("Use the global config file")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--system)
+            .required(false)
+            .conflicts_with_all(&["local", "file"])
+            .help("Use the system-wide config file")
+            .display_order(1)
+        )
+        .arg(
+            arg!(--local)
+            .required(false)
+            .conflicts_with("file")
+            .help("Use the repository config file")
+            .display_order(2)
+        )
+        .arg(
+            arg!(-f --file <file>)
+            .required(false)
+            .help("Use the given config file")
+            .display_order(3)
+        )
+        .arg(
+            arg!(--get)
+            .required(false)
+            .conflicts_with_all(&["get_all", "set", "unset", "unset_all", "list"])
+            .help("Get the last value of a key")
+            .display_order(4)
+        )
+        .arg(
+            arg!(--"get-all")
+            .id("get_all")
+            .required(false)
+            .conflicts_with_all(&["set", "unset", "unset_all", "list"])
+            .help("Get all values of a multi-valued key")
+            .display_order(5)
+        )
+        .arg(
+            arg!(--set)
+            .required(false)
+            .conflicts_with_all(&["unset", "unset_all", "list"])
+            .requires("value")
+            .help("Set a key, which must not have several values")
+            .display_order(6)
+        )
+        .arg(
+            arg!(--add)
+            .required(false)
+            .conflicts_with_all(&["set", "unset", "unset_all", "list"])
+            .requires("value")
+            .help("Add a value to a key without replacing the existing ones")
+            .display_order(7)
+        )
+        .arg(
+            arg!(--unset)
+            .required(false)
+            .conflicts_with_all(&["unset_all", "list"])
+            .help("Remove a key, which must not have several values")
+            .display_order(8)
+        )
+        .arg(
+            arg!(--"unset-all")
+            .id("unset_all")
+            .required(false)
+            .conflicts_with("list")
+            .help("Remove all values of a key")
+            .display_order(9)
+        )
+        .arg(
+            arg!(-l --list)
+            .required(false)
+            .conflicts_with_all(&["name", "value"])
+            .help("List all variables with their values")
+            .display_order(10)
+        )
+        .arg(
+            arg!(--"show-origin")
+            .id("show_origin")
+            .required(false)
+            .help("Show the file each value comes from")
+            .display_order(11)
+        )
+        .arg(
+            arg!(--includes)
+            .required(false)
+            .help("Follow include directives when reading a single config file")
+            .display_order(12)
+        )
+        .arg(
+            arg!(--type <type>)
+            .required(false)
+            .possible_values(["bool", "int", "path"])
+            .help("Check values are of the given type and show them in canonical form")
+            .display_order(13)
+        )
+        .arg(
+            arg!([name])
+            .help("Key as section.name or section.subsection.name")
+            .display_order(14)
+        )
+        .arg(
+            arg!([value])
+            .help("Value to set")
+            .display_order(15)
+        ),
+        // clone
+        Command::new("clone")
+        .display_order(25)
+        .about("Clone a local repository into a new directory")
+        .arg(
+            arg!(--bare)
+            .required(false)
+            .help("Make a bare repository with the remote branches as local ones")
+            .display_order(0)
+        )
+        .arg(
+            arg!(--mirror)
+            .required(false)
+            .conflicts_with("bare")
+            .help("Make a bare repository with every ref of the source copied as it is")
+            .display_order(1)
+        )
+        .arg(
+            arg!(-b --branch <name>)
+            .required(false)
+            .help("Check out this branch, or tag, instead of the source's HEAD")
+            .display_order(2)
+        )
+        .arg(
+            arg!(-n --"no-checkout")
+            .id("no_checkout")
+            .required(false)
+            .help("Do not check out HEAD after cloning")
+            .display_order(3)
+        )
+        .arg(
+            arg!(-q --quiet)
+            .required(false)
+            .help("Do not report progress")
+            .display_order(4)
+        )
+        .arg(
+            arg!(<source>)
+            .help("Path or file:// URL of the repository to clone")
+            .display_order(5)
+        )
+        .arg(
+            arg!([directory])
+            .help("Directory to clone into, by default named after the source")
+            .display_order(6)
+        ),
+        // remote
+        Command::new("remote")
+        .display_order(26)
+        .about("Manage the set of remote repositories")
+        .arg(
+            arg!(-v --verbose)
+            .required(false)
+            .help("Show the URLs of the remotes too")
+        )
+        .subcommands(vec![
+            Command::new("add")
+            .about("Add a remote named <name> for the repository at <url>")
+            .arg(
+                arg!(-t --track <branch>)
+                .required(false)
+                .multiple_occurrences(true)
+                .help("Only fetch this branch instead of all of them; can be given more than once")
+            )
+            .arg(
+                arg!(-m --master <branch>)
+                .required(false)
+                .help("Point refs/remotes/<name>/HEAD at this branch")
+            )
+            .arg(
+                arg!(--mirror <kind>)
+                .required(false)
+                .require_equals(true)
+                .min_values(0)
+                .default_missing_value("fetch")
+                .possible_values(["fetch", "push"])
+                .help("Mirror every ref of the remote when fetching, or of ours when pushing")
+            )
+            .arg(arg!(<name>).help("Name of the new remote"))
+            .arg(arg!(<url>).help("URL of the repository")),
+            Command::new("remove")
+            .visible_alias("rm")
+            .about("Remove the remote <name> with its remote-tracking branches and configuration")
+            .arg(arg!(<name>).help("Name of the remote")),
+            Command::new("rename")
+            .about("Rename the remote <old> to <new> along with its remote-tracking branches")
+            .arg(arg!(<old>).help("Current name of the remote"))
+            .arg(arg!(<new>).help("New name of the remote")),
+            Command::new("set-url")
+            .about("Change the URLs of a remote")
+            .arg(
+                arg!(--push)
+                .required(false)
+                .help("Change the push URLs instead")
+            )
+            .arg(
+                arg!(--add)
+                .required(false)
+                .conflicts_with("delete")
+                .help("Add <newurl> instead of changing a URL")
+            )
+            .arg(
+                arg!(--delete)
+                .required(false)
+                .help("Remove the URLs matching the regular expression <newurl>")
+            )
+            .arg(arg!(<name>).help("Name of the remote"))
+            .arg(arg!(<newurl>).help("The new URL"))
+            .arg(arg!([oldurl]).help("Regular expression for the URL to replace")),
+            Command::new("show")
+            .about("Show information about the given remotes")
+            .arg(
+                arg!(no_query: -n)
+                .required(false)
+                .help("Do not look at the remote repositories")
+            )
+            .arg(arg!([name] ...).help("Names of the remotes")),
+        ]),
+    ])
+}
+
+/// Arguments shared by cherry-pick and revert.
+fn sequencer_args<'a>() -> Vec<Arg<'a>> {
+    vec![
+        arg!(-n --"no-commit")
+        .id("no_commit")
+        .required(false)
+        .help("Apply the changes to the index and worktree without committing")
+        .display_order(0),
+        arg!(-m --mainline <parent>)
+        .required(false)
+        .validator(|parent| match parent.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("mainline must be a positive parent number"))
+        })
+        .help("Parent number (starting at 1) of a merge commit to replay the changes against")
+        .display_order(1),
+        arg!(--continue)
+        .required(false)
+        .conflicts_with_all(&["skip", "abort", "commits"])
+        .help("Continue after resolving conflicts")
+        .display_order(3),
+        arg!(--skip)
+        .required(false)
+        .conflicts_with_all(&["abort", "commits"])
+        .help("Skip the current commit and continue with the rest")
+        .display_order(4),
+        arg!(--abort)
+        .required(false)
+        .conflicts_with("commits")
+        .help("Cancel the operation and return to the pre-sequence state")
+        .display_order(5),
+        arg!(<commits> ...)
+        .required_unless_present_any(["continue", "skip", "abort"])
+        .help("Commits or ranges such as A..B to apply")
+        .display_order(6)
+    ]
+}
+
+mod commands {
+    use std::{
+        env::current_dir,
+        io::{ stdout, Write },
+        fs,
+        str::from_utf8,
+        path::PathBuf
+    };
+    use clap::ArgMatches;
+    use regex::Regex;
+    use crate::{
+        error::{ builder::*, WitError },
+        repository::{ Repository, InitOptions, Shared },
+        object::{ self, WitObject },
+        tree::Tree,
+        reference::{ self, Ref::* },
+        reflog,
+        ref_filter,
+        graph,
+        util,
+        tag,
+        commit,
+        tree,
+        index::{ self, Index },
+        worktree,
+        merge::{ self, ConflictStyle, Labels },
+        sequencer::{ self, Action },
+        rebase,
+        stash,
+        ignore,
+        attr,
+        convert,
+        config::{ self, Config },
+        clone,
+        remote::{ self, Remote }
+    };
+
+    pub fn init(sub_matches: &ArgMatches) -> Result<(), Box<WitError>> {
+        let pwd = match current_dir() {
+            Ok(dir) => dir,
+            Err(_) => Err(io_err(String::from("Could not find pwd")))?
+        };
+        let pwd = match pwd.to_str() {
+            Some(string) => {
+                String::from(string)
+            },
+            None => Err(io_err(String::from("Could not read pwd")))?
+        };
+
+        let shared = match sub_matches.value_of("shared") {
+            Some(value) => Some(Shared::parse(value).ok_or(cli_usage_err(format!("invalid value for --shared: {}", value)))?),
+            None => None
+        };
+        let opts = InitOptions {
+            bare: sub_matches.is_present("bare"),
+            initial_branch: sub_matches.value_of("initial_branch").map(String::from),
+            template: sub_matches.value_of("template").map(PathBuf::from),
+            shared
+        };
+        match Repository::create(sub_matches.value_of("path").unwrap_or(pwd.as_str()), &opts) {
+            Ok((repo, reinit)) if !sub_matches.is_present("quiet") => println!(
+                "{} Git repository in {}/",
+                if reinit { "Reinitialized existing" } else { "Initialized empty" },
+                repo.git_dir.canonicalize()?.display()
+            ),
+            Ok(_) => (),
+            Err(e) => {
+                println!("{}", e);
+                eprintln!("Could not create repo.");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cat_file(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo: Repository = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+
+        let data = object::find(
+            &repo,
+            args.value_of("object").ok_or(io_err(format!("No object specified")))?,
+            Some(args.value_of("file_type").ok_or(io_err(format!("No file type specified")))?),
+            true
+        )?;
+        let obj = object::read(
+            &repo,
+            data.as_str()
+        )?;
+
+        let mut out = stdout();
+        out.write(obj.serialize()?.as_slice())?;
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn hash_object(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let file = args.value_of("file").ok_or(cli_argument_err("file"))?;
+        let fmt = args.value_of("type").ok_or(cli_argument_err("type"))?;
+        let found = Repository::find(".", false)?;
+
+        // Blobs go through the same conversion as when they are staged from the worktree,
+        // except that a symlink named here is followed like git does.
+        let mut data = fs::read(file)?;
+        if let (Some(repo), "blob", false) = (&found, fmt, args.is_present("no_filters")) {
+            // Files outside the worktree have no attributes.
+            if let Ok(path) = worktree::pathspec(repo, args.value_of("path").unwrap_or(file)) {
+                data = convert::to_git(repo, &mut attr::Attributes::new(repo)?, &path, data)?;
+            }
+        }
+        let repo = if args.is_present("write") {
+            Some(found.as_ref().ok_or(pwd_not_repo_err())?)
+        } else {
+            None
+        };
+        println!("{}", object::hash_data(data, fmt, repo)?);
+        Ok(())
+    }
+
+    pub fn log(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let commit = args.value_of("commit").ok_or(cli_argument_err("commit"))?;
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        println!("digraph log {{\n");
+        object::graphviz(
+            &repo,
+            object::find(&repo, commit, None, true)?,
+            &mut Vec::new()
+        )?;
+        println!("}}");
+        Ok(())
+    }
+
+    pub fn ls_tree(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let obj_name = args.value_of("object").ok_or(
+            cli_argument_err("object")
+        )?;
+        let obj = match object::read(&repo, &object::find(&repo, obj_name, Some("tree"), true)?)? {
+            WitObject::TreeObject(tree) => tree,
+            _ => Err(malformed_object_err(format!("Object {} is not a tree", obj_name)))?
+        };
+
+        let mut mode_str = String::new();
+        for leaf in obj.leaves() {
+            mode_str.clear();
+            for _ in 0..(6 - leaf.mode().len()) {
+                mode_str.push('0');
+            }
+            mode_str.push_str(leaf.mode());
+
+            println!(
+                "{} {} {}\t{}",
+                mode_str,
+                if leaf.mode() == "160000" {
+                    String::from("commit")
+                } else {
+                    String::from_utf8(object::read(&repo, leaf.sha())?.fmt())?
+                },
+                leaf.sha(),
+                leaf.path().to_str().unwrap()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn checkout(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let obj_name = args.value_of("commit").ok_or(cli_argument_err("commit"))?;
+        let obj: Tree = match object::read(&repo, &object::find(&repo, obj_name, Some("tree"), true)?)? {
+            WitObject::CommitObject(commit) => {
+                match object::read(
+                    &repo,
+                    &commit.kvlm().get("tree").ok_or(
+                        missing_data_err(format!("No tree in commit {}", obj_name))
+                    )?[0]
+                )? {
+                    WitObject::TreeObject(tree) => tree,
+                    _ => Err(malformed_object_err(format!("Could not find tree from object {}", obj_name)))?
+                }
+            },
+            WitObject::TreeObject(tree) => tree,
+            other => Err(malformed_object_err(format!("Expected a commit or tree object, got {}", from_utf8(&other.fmt()).unwrap_or("?"))))?
+        };
+
+        let path = PathBuf::from(args.value_of("path").ok_or(
+            cli_argument_err("path")
+        )?);
+        if path.exists() {
+            if !path.is_dir() {
+                Err(not_a_directory_err(&path))?
+            }
+            if !path.read_dir()?.next().is_none() {
+                Err(dir_not_empty_err(&path))?
+            }
+        } else {
+            fs::create_dir_all(&path)?;
+        }
+
+        object::checkout(&repo, &obj, &path.canonicalize()?)?;
+        Ok(())
+    }
+
+    pub fn show_ref(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let patterns = args.values_of("pattern").map(|p| p.collect::<Vec<&str>>()).unwrap_or_default();
+        let quiet = args.is_present("quiet");
+        let width = args.value_of("hash").or(args.value_of("abbrev"))
+            .map(|n| n.parse::<usize>())
+            .transpose()?
+            .unwrap_or(40)
+            .clamp(4, 40);
+
+        let show = |name: &str, sha: &str| {
+            if quiet {
+                return;
+            }
+            let peeled = match args.is_present("dereference") {
+                true => reference::peel(&repo, sha),
+                false => None
+            };
+            for (name, sha) in [(name.to_owned(), sha), (name.to_owned() + "^{}", peeled.as_deref().unwrap_or(""))] {
+                if sha.is_empty() {
+                    continue;
+                }
+                let sha = sha.get(..width).unwrap_or(sha);
+                if args.is_present("hash") {
+                    println!("{}", sha);
+                } else {
+                    println!("{} {}", sha, name);
+                }
+            }
+        };
+
+        if args.is_present("verify") {
+            for name in patterns {
+                let sha = match name.starts_with("refs/") || name == "HEAD" {
+                    true => reference::resolve(&repo, name).ok(),
+                    false => None
+                };
+                match sha {
+                    Some(sha) => show(name, &sha),
+                    None if quiet => Err(exit_err(1))?,
+                    None => Err(unknown_reference_err(format!("'{}' - not a valid ref", name)).with_code(128))?
+                }
+            }
+            return Ok(());
+        }
+
+        let mut found = false;
+        if args.is_present("head") {
+            if let Ok(sha) = reference::resolve(&repo, "HEAD") {
+                show("HEAD", &sha);
+                found = true;
+            }
+        }
+        for (name, sha) in reference::all(&repo)? {
+            let heads = args.is_present("heads") && name.starts_with("refs/heads/");
+            let tags = args.is_present("tags") && name.starts_with("refs/tags/");
+            if (args.is_present("heads") || args.is_present("tags")) && !(heads || tags) {
+                continue;
+            }
+            // Patterns match whole path components at the end of the ref name.
+            if !patterns.is_empty() && !patterns.iter().any(|pattern| {
+                name == *pattern || name.ends_with(&format!("/{}", pattern))
+            }) {
+                continue;
+            }
+            show(&name, &sha);
+            found = true;
+        }
+
+        if !found {
+            Err(exit_err(1))?
+        }
+        Ok(())
+    }
+
+    pub fn tag(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        if args.is_present("delete") {
+            let name = args.value_of("name").ok_or(cli_argument_err("name"))?;
+            let old = reference::delete(&repo, &format!("refs/tags/{}", name))?;
+            println!("Deleted tag '{}' (was {})", name, util::abbrev(&old));
+            Ok(())
+        } else if args.is_present("name") {
+            tag::create(
+                &repo,
+                args.value_of("name").ok_or(
+                    cli_argument_err("name")
+                )?,
+                args.value_of("object").ok_or(
+                    cli_argument_err("object")
+                )?,
+                args.is_present("create_tag_object")
+            )
+        } else {
+            let refs = reference::list(&repo, Some("refs/tags"))?;
+            let tags = match refs.get("tags") {
+                Some(Indirect(refs)) => refs,
+                Some(Direct(direct)) => Err(
+                    unknown_reference_err(
+                        format!("Expected an indirect reference, got direct reference {}", direct)
+                    )
+                )?,
+                None => return Ok(())
+            };
+            reference::show(&repo, tags, true, "")
+        }
+    }
 
     pub fn rev_parse(args: &ArgMatches) -> Result<(), Box<WitError>> {
         let mut fmt = None;
@@ -412,4 +1501,876 @@ mod commands {
         );
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn pack_refs(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        reference::pack(&repo, args.is_present("all"), !args.is_present("no_prune"))
+    }
+
+    pub fn reflog(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let dwim = |name: &str| -> Result<String, Box<WitError>> {
+            if name == "HEAD" {
+                return Ok(name.to_owned());
+            }
+            reference::dwim(&repo, name)?.ok_or(
+                unknown_reference_err(format!("Unknown reference {}.", name))
+            )
+        };
+
+        match args.subcommand() {
+            Some(("expire", sub)) => {
+                let expire = sub.value_of("expire").ok_or(cli_argument_err("expire"))?;
+                let cutoff = match expire {
+                    "all" => i64::MAX,
+                    date => util::parse_date(date, util::now().0).ok_or(
+                        cli_argument_err("expire")
+                    )?
+                };
+                let refs = if sub.is_present("all") {
+                    reflog::list(&repo)?
+                } else {
+                    sub.values_of("refs").ok_or(cli_argument_err("refs"))?
+                        .map(dwim)
+                        .collect::<Result<Vec<String>, Box<WitError>>>()?
+                };
+                for name in refs {
+                    reflog::expire(&repo, &name, cutoff)?;
+                }
+                Ok(())
+            },
+            Some(("delete", sub)) => {
+                let selector = Regex::new(r"^(.+)@\{(\d+)\}$")?;
+                for entry in sub.values_of("entries").ok_or(cli_argument_err("entries"))? {
+                    let caps = selector.captures(entry).ok_or(
+                        unknown_reference_err(format!("Not a reflog entry: {}", entry))
+                    )?;
+                    reflog::delete(&repo, &dwim(&caps[1])?, caps[2].parse::<usize>()?)?;
+                }
+                Ok(())
+            },
+            sub => {
+                let name = match sub {
+                    Some(("show", sub)) => sub.value_of("ref"),
+                    _ => args.value_of("ref")
+                }.ok_or(cli_argument_err("ref"))?;
+                let refname = dwim(name)?;
+                for (i, entry) in reflog::read(&repo, &refname)?.iter().rev().enumerate() {
+                    println!("{} {}@{{{}}}: {}", util::abbrev(&entry.new), name, i, entry.message);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn update_ref(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let message = args.value_of("message").unwrap_or("");
+        let resolve = |value: &str| -> Result<String, Box<WitError>> {
+            if value.is_empty() || value == reflog::ZERO_SHA {
+                Ok(reflog::ZERO_SHA.to_owned())
+            } else {
+                object::find(&repo, value, None, true)
+            }
+        };
+
+        let mut transaction = reference::Transaction::new(&repo);
+        if args.is_present("stdin") {
+            let mut deref = !args.is_present("no_deref");
+            for line in std::io::stdin().lines() {
+                let line = line?;
+                let words = line.split_whitespace().collect::<Vec<&str>>();
+                let old = |idx: usize| words.get(idx).map(|value| resolve(value)).transpose();
+                match words.as_slice() {
+                    [] => (),
+                    ["option", "no-deref"] => deref = false,
+                    ["update", name, new, ..] if words.len() <= 4 => {
+                        transaction.update(name, &resolve(new)?, old(3)?.as_deref(), deref, message);
+                    },
+                    ["create", name, new] => {
+                        transaction.update(name, &resolve(new)?, Some(reflog::ZERO_SHA), deref, message);
+                    },
+                    ["delete", name, ..] if words.len() <= 3 => {
+                        transaction.delete(name, old(2)?.as_deref(), deref, message);
+                    },
+                    ["verify", name, ..] if words.len() <= 3 => {
+                        transaction.verify(name, old(2)?.as_deref(), deref);
+                    },
+                    _ => Err(cli_argument_err(&format!("valid instruction (got '{}')", line)))?
+                }
+                if !words.is_empty() && words[0] != "option" {
+                    deref = !args.is_present("no_deref");
+                }
+            }
+        } else {
+            let name = args.value_of("ref").ok_or(cli_argument_err("ref"))?;
+            let deref = !args.is_present("no_deref");
+            if args.is_present("delete") {
+                if args.is_present("oldvalue") {
+                    Err(cli_argument_err("at most one <oldvalue> with -d"))?
+                }
+                let old = args.value_of("newvalue").map(resolve).transpose()?;
+                transaction.delete(name, old.as_deref(), deref, message);
+            } else {
+                let new = resolve(args.value_of("newvalue").ok_or(cli_argument_err("newvalue"))?)?;
+                let old = args.value_of("oldvalue").map(resolve).transpose()?;
+                if new == reflog::ZERO_SHA {
+                    transaction.delete(name, old.as_deref(), deref, message);
+                } else {
+                    transaction.update(name, &new, old.as_deref(), deref, message);
+                }
+            }
+        }
+        transaction.commit()
+    }
+
+    pub fn symbolic_ref(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let name = args.value_of("name").ok_or(cli_argument_err("name"))?;
+
+        if args.is_present("delete") {
+            return reference::delete_symbolic(&repo, name);
+        }
+        if let Some(target) = args.value_of("ref") {
+            return reference::set_symbolic(&repo, name, target, args.value_of("message").unwrap_or(""));
+        }
+
+        match reference::read_symbolic(&repo, name)? {
+            Some(target) => {
+                reference::target(&repo, name)?;
+                if args.is_present("short") {
+                    println!("{}", reference::shorten(&target));
+                } else {
+                    println!("{}", target);
+                }
+                Ok(())
+            },
+            None if args.is_present("quiet") => Err(exit_err(1)),
+            None => Err(unknown_reference_err(format!("ref {} is not a symbolic ref", name)))
+        }
+    }
+
+    pub fn for_each_ref(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let patterns = args.values_of("pattern").map(|p| p.collect::<Vec<&str>>()).unwrap_or_default();
+        let contains = args.value_of("contains")
+            .map(|commit| object::find(&repo, commit, Some("commit"), true))
+            .transpose()?;
+        let merged = match args.value_of("merged") {
+            Some(commit) => Some(graph::ancestors(&repo, &object::find(&repo, commit, Some("commit"), true)?)?),
+            None => None
+        };
+
+        let mut items = Vec::new();
+        for (name, sha) in reference::all(&repo)? {
+            if !ref_filter::matches(&name, &patterns) {
+                continue;
+            }
+            if contains.is_some() || merged.is_some() {
+                let commit = match graph::commit_of(&repo, &sha) {
+                    Some(commit) => commit,
+                    None => continue
+                };
+                if let Some(merged) = &merged {
+                    if !merged.contains(&commit) {
+                        continue;
+                    }
+                }
+                if let Some(contains) = &contains {
+                    if !graph::is_ancestor(&repo, contains, &commit)? {
+                        continue;
+                    }
+                }
+            }
+            items.push(ref_filter::RefItem { name, sha });
+        }
+
+        let keys = args.values_of("sort").map(|k| k.collect()).unwrap_or(vec!["refname"]);
+        ref_filter::sort(&repo, &mut items, &keys)?;
+        if let Some(count) = args.value_of("count") {
+            items.truncate(count.parse::<usize>()?);
+        }
+
+        let format = args.value_of("format").unwrap_or(ref_filter::DEFAULT_FORMAT);
+        for item in items {
+            println!("{}", ref_filter::format(&repo, &item, format)?);
+        }
+        Ok(())
+    }
+
+    pub fn merge_base(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let names = args.values_of("commits").ok_or(cli_argument_err("commits"))?.collect::<Vec<&str>>();
+
+        if args.is_present("fork_point") {
+            if names.len() > 2 {
+                Err(cli_argument_err("<ref> [<commit>]"))?
+            }
+            let refname = reference::dwim(&repo, names[0])?.ok_or(
+                unknown_reference_err(format!("Unknown reference {}.", names[0]))
+            )?;
+            let commit = object::find(&repo, names.get(1).unwrap_or(&"HEAD"), Some("commit"), true)?;
+            match graph::fork_point(&repo, &refname, &commit)? {
+                Some(base) => println!("{}", base),
+                None => Err(exit_err(1))?
+            }
+            return Ok(());
+        }
+
+        let commits = names.iter()
+            .map(|name| object::find(&repo, name, Some("commit"), true))
+            .collect::<Result<Vec<String>, Box<WitError>>>()?;
+
+        if args.is_present("is_ancestor") {
+            if commits.len() != 2 {
+                Err(cli_argument_err("exactly two commits"))?
+            }
+            if !graph::is_ancestor(&repo, &commits[0], &commits[1])? {
+                Err(exit_err(1))?
+            }
+            return Ok(());
+        }
+
+        let bases = if args.is_present("octopus") {
+            graph::octopus_bases(&repo, &commits)?
+        } else {
+            if commits.len() < 2 {
+                Err(cli_argument_err("at least two commits"))?
+            }
+            graph::merge_bases(&repo, &commits[0], &commits[1..])?
+        };
+        if bases.is_empty() {
+            Err(exit_err(1))?
+        }
+        for base in bases.iter().take(if args.is_present("all") { bases.len() } else { 1 }) {
+            println!("{}", base);
+        }
+        Ok(())
+    }
+
+    pub fn merge(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        repo.worktree()?;
+        let head = reference::resolve(&repo, "HEAD").ok();
+        let current = Index::read(&repo)?;
+
+        if args.is_present("abort") {
+            if merge::state(&repo)?.is_none() {
+                Err(merge_conflict_err(String::from("There is no merge to abort (MERGE_HEAD missing).")))?
+            }
+            let head = head.ok_or(unknown_reference_err(String::from("HEAD does not point to a commit.")))?;
+            worktree::reset(&repo, &commit::tree_of(&repo, &head)?)?;
+            return merge::clear_state(&repo);
+        }
+
+        if args.is_present("continue") {
+            let (heads, message) = merge::state(&repo)?.ok_or(
+                merge_conflict_err(String::from("There is no merge in progress (MERGE_HEAD missing)."))
+            )?;
+            if !current.conflicts().is_empty() {
+                Err(merge_conflict_err(String::from(
+                    "Committing is not possible because you have unmerged files.\nFix them up in the work tree, and then stage them."
+                )))?
+            }
+            let head = head.ok_or(unknown_reference_err(String::from("HEAD does not point to a commit.")))?;
+            let message = merge::clean_message(&message);
+            let parents = [vec![head], heads].concat();
+            let sha = commit::create(&repo, &current.write_tree(&repo)?, &parents, &message, None)?;
+            let subject = message.lines().next().unwrap_or("");
+            reference::update(&repo, "HEAD", &sha, &format!("commit (merge): {}", subject))?;
+            merge::clear_state(&repo)?;
+            println!("[{} {}] {}", reference::shorten(&reference::target(&repo, "HEAD")?), util::abbrev(&sha), subject);
+            return Ok(());
+        }
+
+        if merge::state(&repo)?.is_some() {
+            Err(merge_conflict_err(String::from(
+                "You have not concluded your merge (MERGE_HEAD exists).\nPlease, commit your changes before you merge."
+            )))?
+        }
+        if !current.conflicts().is_empty() {
+            Err(merge_conflict_err(String::from("Merging is not possible because you have unmerged files.")))?
+        }
+
+        let name = args.value_of("commit").ok_or(cli_argument_err("commit"))?;
+        let theirs = object::find(&repo, name, Some("commit"), true)?;
+        let reflog_message = |what: &str| format!("merge {}: {}", name, what);
+
+        // Merging into an unborn branch just checks out the other commit.
+        let fast_forward = match &head {
+            Some(head) if graph::is_ancestor(&repo, &theirs, head)? => {
+                println!("Already up to date.");
+                return Ok(());
+            },
+            Some(head) => graph::is_ancestor(&repo, head, &theirs)? && !args.is_present("no_ff"),
+            None => true
+        };
+
+        // Merges start from a clean index; staged changes would be lost.
+        if let Some(head) = &head {
+            let committed = tree::flatten(&repo, &commit::tree_of(&repo, head)?)?;
+            let staged = current.tree_entries();
+            let changed = committed.keys()
+                .chain(staged.keys())
+                .filter(|path| committed.get(*path) != staged.get(*path))
+                .map(|path| format!("\t{}", path))
+                .collect::<std::collections::BTreeSet<String>>();
+            if !changed.is_empty() {
+                Err(dirty_worktree_err(format!(
+                    "Your local changes to the following files would be overwritten by merge:\n{}\nPlease commit your changes or stash them before you merge.",
+                    changed.into_iter().collect::<Vec<String>>().join("\n")
+                )))?
+            }
+        }
+
+        if fast_forward {
+            let tree = commit::tree_of(&repo, &theirs)?;
+            let mut index = Index::from_tree(&repo, &tree)?;
+            worktree::switch(&repo, &current, &tree::flatten(&repo, &tree)?, &mut index, false, "merge")?;
+            index.write(&repo)?;
+            if let Some(head) = &head {
+                println!("Updating {}..{}\nFast-forward", util::abbrev(head), util::abbrev(&theirs));
+                reference::update(&repo, "ORIG_HEAD", head, "")?;
+            }
+            return reference::update(&repo, "HEAD", &theirs, &reflog_message("Fast-forward"));
+        }
+        if args.is_present("ff_only") {
+            Err(merge_conflict_err(String::from("Not possible to fast-forward, aborting.")))?
+        }
+
+        let head = head.unwrap();
+        let style = match args.value_of("conflict") {
+            Some(style) => ConflictStyle::parse(style)?,
+            None => ConflictStyle::configured(&repo)?
+        };
+        let mut result = merge::merge_commits(&repo, &head, &theirs, &Labels { base: "", ours: "HEAD", theirs: name }, style)?;
+        worktree::switch(&repo, &current, &result.worktree, &mut result.index, false, "merge")?;
+        result.index.write(&repo)?;
+        reference::update(&repo, "ORIG_HEAD", &head, "")?;
+        for message in &result.messages {
+            println!("{}", message);
+        }
+
+        let message = match args.value_of("message") {
+            Some(message) => format!("{}\n", message),
+            None => merge::default_message(&repo, name)?
+        };
+        if !result.clean() {
+            let conflicts = result.conflicts.iter().map(|path| format!("#\t{}\n", path)).collect::<String>();
+            merge::write_state(&repo, &[theirs], &format!("{}\n# Conflicts:\n{}", message, conflicts), args.is_present("no_ff"))?;
+            println!("Automatic merge failed; fix conflicts and then commit the result.");
+            return Err(exit_err(1));
+        }
+
+        let sha = commit::create(&repo, &result.index.write_tree(&repo)?, &[head, theirs], &message, None)?;
+        reference::update(&repo, "HEAD", &sha, &reflog_message("Merge made by the 'recursive' strategy."))?;
+        println!("Merge made by the 'recursive' strategy.");
+        Ok(())
+    }
+
+    pub fn sequence(action: Action, args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        repo.worktree()?;
+        let done = if args.is_present("abort") {
+            sequencer::abort(&repo)?;
+            true
+        } else if args.is_present("continue") || args.is_present("skip") {
+            sequencer::resume(&repo, args.is_present("skip"))?
+        } else {
+            let specs = args.values_of("commits").ok_or(cli_argument_err("commits"))?.collect::<Vec<&str>>();
+            let opts = sequencer::Options {
+                no_commit: args.is_present("no_commit"),
+                // Only cherry-pick knows -x.
+                record_origin: action == Action::Pick && args.is_present("record_origin"),
+                mainline: args.value_of("mainline").map(|m| m.parse::<usize>()).transpose()?
+            };
+            sequencer::start(&repo, action, &sequencer::revisions(&repo, &specs, action)?, &opts)?
+        };
+        if !done {
+            return Err(exit_err(1));
+        }
+        Ok(())
+    }
+
+    pub fn rebase(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        repo.worktree()?;
+        let done = if args.is_present("abort") {
+            rebase::abort(&repo)?;
+            true
+        } else if args.is_present("continue") {
+            rebase::resume(&repo)?
+        } else if args.is_present("skip") {
+            rebase::skip(&repo)?
+        } else {
+            rebase::start(&repo, &rebase::Options {
+                upstream: args.value_of("upstream").ok_or(cli_argument_err("upstream"))?,
+                onto: args.value_of("onto"),
+                branch: args.value_of("branch"),
+                interactive: args.is_present("interactive"),
+                autosquash: args.is_present("autosquash")
+            })?
+        };
+        if !done {
+            return Err(exit_err(1));
+        }
+        Ok(())
+    }
+
+    pub fn reset(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        if !args.is_present("soft") {
+            repo.worktree()?;
+        }
+        let mut name = args.value_of("commit").unwrap_or("HEAD");
+        let mut paths = args.values_of("paths").map(|paths| paths.collect::<Vec<&str>>()).unwrap_or_default();
+        // Like git, `reset <path>` works without `--` when <path> is not a revision.
+        if paths.is_empty() && object::find(&repo, name, Some("commit"), true).is_err() && current_dir()?.join(name).exists() {
+            paths.push(name);
+            name = "HEAD";
+        }
+        let target = object::find(&repo, name, Some("commit"), true)?;
+        let tree = commit::tree_of(&repo, &target)?;
+
+        if !paths.is_empty() {
+            if args.is_present("soft") || args.is_present("hard") {
+                Err(cli_usage_err(format!(
+                    "Cannot do {} reset with paths.", if args.is_present("soft") { "soft" } else { "hard" }
+                )))?
+            }
+            let specs = paths.iter().map(|path| worktree::pathspec(&repo, path)).collect::<Result<Vec<String>, _>>()?;
+            let entries = tree::flatten(&repo, &tree)?;
+            let mut index = Index::read(&repo)?;
+            let staged = index.entries().iter().map(|entry| entry.file_path.clone()).collect::<Vec<String>>();
+            for path in staged.iter().chain(entries.keys()) {
+                if !specs.iter().any(|spec| worktree::in_pathspec(path, spec)) {
+                    continue;
+                }
+                let old = index.entry(path, 0).map(|entry| (entry.mode_str(), entry.hash.clone()));
+                match entries.get(path) {
+                    Some((mode, sha)) if old.as_ref() != Some(&(mode.clone(), sha.clone())) => {
+                        index.add(index::IndexEntry::new(path, mode, sha, 0)?)
+                    },
+                    Some(_) => (),
+                    None => index.remove(path)
+                }
+            }
+            index.write(&repo)?;
+            return self::print_unstaged(&repo, &index);
+        }
+
+        let head = reference::resolve(&repo, "HEAD")?;
+        if args.is_present("soft") {
+            if merge::state(&repo)?.is_some() {
+                Err(merge_conflict_err(String::from("Cannot do a soft reset in the middle of a merge.")))?
+            }
+        } else {
+            if args.is_present("hard") {
+                worktree::hard_reset(&repo, &tree)?;
+            } else {
+                let current = Index::read(&repo)?;
+                let mut index = Index::from_tree(&repo, &tree)?;
+                // Entries that stay the same keep their stat data, so their files do not show as modified.
+                for entry in index.entries_mut() {
+                    if let Some(old) = current.entry(&entry.file_path, 0).filter(|old| old.hash == entry.hash && old.mode == entry.mode) {
+                        entry.copy_stat(old);
+                    }
+                }
+                index.write(&repo)?;
+            }
+            merge::clear_state(&repo)?;
+            sequencer::clear_stopped(&repo)?;
+        }
+
+        reference::update(&repo, "ORIG_HEAD", &head, "")?;
+        reference::update(&repo, "HEAD", &target, &format!("reset: moving to {}", name))?;
+        if args.is_present("hard") {
+            println!("HEAD is now at {} {}", util::abbrev(&target), commit::read(&repo, &target)?.subject());
+        } else if !args.is_present("soft") {
+            self::print_unstaged(&repo, &Index::read(&repo)?)?;
+        }
+        Ok(())
+    }
+
+    /// Summary of worktree changes that git prints after a mixed reset.
+    fn print_unstaged(repo: &Repository, index: &Index) -> Result<(), Box<WitError>> {
+        let mut attrs = attr::Attributes::new(repo)?;
+        let mut lines = Vec::new();
+        for entry in index.entries().iter().filter(|entry| entry.stage() == 0) {
+            if !repo.worktree()?.join(&entry.file_path).exists() {
+                lines.push(format!("D\t{}", entry.file_path));
+            } else if worktree::is_modified(repo, &mut attrs, entry)? {
+                lines.push(format!("M\t{}", entry.file_path));
+            }
+        }
+        if !lines.is_empty() {
+            println!("Unstaged changes after reset:\n{}", lines.join("\n"));
+        }
+        Ok(())
+    }
+
+    pub fn restore(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        repo.worktree()?;
+        let staged = args.is_present("staged");
+        let to_worktree = args.is_present("worktree") || !staged;
+        let args_paths = args.values_of("pathspec").ok_or(cli_argument_err("pathspec"))?.collect::<Vec<&str>>();
+        let specs = args_paths.iter().map(|path| worktree::pathspec(&repo, path)).collect::<Result<Vec<String>, _>>()?;
+        let wanted = |path: &str| specs.iter().any(|spec| worktree::in_pathspec(path, spec));
+
+        let mut index = Index::read(&repo)?;
+        // Without --source, --staged restores from HEAD and the worktree alone from the index.
+        let source = match args.value_of("source") {
+            Some(name) => Some(tree::flatten(&repo, &object::find(&repo, name, Some("tree"), true)?)?),
+            None if staged => Some(tree::flatten(&repo, &commit::tree_of(&repo, &reference::resolve(&repo, "HEAD")?)?)?),
+            None => None
+        };
+        let entries = source.clone().unwrap_or(index.tree_entries());
+
+        let mut paths = index.entries().iter().map(|entry| entry.file_path.clone()).collect::<Vec<String>>();
+        paths.extend(entries.keys().cloned());
+        paths.retain(|path| wanted(path));
+        paths.sort();
+        paths.dedup();
+        for (arg, spec) in args_paths.iter().zip(&specs) {
+            if !paths.iter().any(|path| worktree::in_pathspec(path, spec)) {
+                Err(missing_data_err(format!("pathspec '{}' did not match any file(s) known to wit", arg)))?
+            }
+        }
+        if source.is_none() {
+            let conflicts = index.conflicts().into_iter().filter(|path| wanted(path)).collect::<Vec<String>>();
+            if let Some(path) = conflicts.first() {
+                Err(merge_conflict_err(format!("path '{}' is unmerged", path)))?
+            }
+        }
+
+        let mut attrs = attr::Attributes::new(&repo)?;
+        for path in &paths {
+            let found = entries.get(path);
+            if staged {
+                match found {
+                    Some((mode, sha)) => index.add(index::IndexEntry::new(path, mode, sha, 0)?),
+                    None => index.remove(path)
+                }
+            }
+            if to_worktree {
+                match found {
+                    Some((mode, sha)) => {
+                        worktree::checkout_file(&repo, &mut attrs, path, mode, sha)?;
+                        // Files that now match the index need not be rehashed later.
+                        if let Some(entry) = index.entries_mut().iter_mut().find(
+                            |entry| &entry.file_path == path && entry.stage() == 0 && entry.hash == *sha && entry.mode_str() == *mode
+                        ) {
+                            entry.refresh(&repo.worktree()?.join(path))?;
+                        }
+                    },
+                    None => worktree::remove_file(&repo, path)?
+                }
+            }
+        }
+        index.write(&repo)
+    }
+
+    pub fn stash(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        repo.worktree()?;
+        let selector = |sub: &ArgMatches| stash::parse_selector(sub.value_of("stash"));
+        let clean = match args.subcommand() {
+            Some(("pop", sub)) => stash::pop(&repo, selector(sub)?)?,
+            Some(("apply", sub)) => stash::apply(&repo, selector(sub)?)?,
+            Some(("list", _)) => { stash::list(&repo)?; true },
+            Some(("show", sub)) => { stash::show(&repo, selector(sub)?)?; true },
+            Some(("drop", sub)) => { stash::drop(&repo, selector(sub)?)?; true },
+            Some(("clear", _)) => { stash::clear(&repo)?; true },
+            Some(("push", sub)) => {
+                stash::push(&repo, &stash::Options {
+                    message: sub.value_of("message"),
+                    keep_index: sub.is_present("keep_index"),
+                    include_untracked: sub.is_present("include_untracked")
+                })?;
+                true
+            },
+            _ => {
+                stash::push(&repo, &stash::Options { message: None, keep_index: false, include_untracked: false })?;
+                true
+            }
+        };
+        if !clean {
+            return Err(exit_err(1));
+        }
+        Ok(())
+    }
+
+    pub fn check_ignore(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        repo.worktree()?;
+        let verbose = args.is_present("verbose");
+        let index = if args.is_present("no_index") { Index::new() } else { Index::read(&repo)? };
+        let mut rules = ignore::Ignore::new(&repo)?;
+        let mut found = false;
+        for arg in args.values_of("pathname").ok_or(cli_argument_err("pathname"))? {
+            let path = worktree::pathspec(&repo, arg)?;
+            // Tracked files are not subject to ignore rules.
+            let pattern = if index.entry(&path, 0).is_some() {
+                None
+            } else {
+                let is_dir = arg.ends_with('/') || repo.worktree()?.join(&path).is_dir();
+                rules.matched(&path, is_dir)?.filter(|pattern| verbose || !pattern.negated())
+            };
+            found |= pattern.is_some();
+            if args.is_present("quiet") {
+                continue;
+            }
+            match pattern {
+                Some(pattern) if verbose => println!("{}:{}:{}\t{}", pattern.source, pattern.line, pattern.text, arg),
+                Some(_) => println!("{}", arg),
+                None if args.is_present("non_matching") => println!("::\t{}", arg),
+                None => ()
+            }
+        }
+        if !found {
+            return Err(exit_err(1));
+        }
+        Ok(())
+    }
+
+    pub fn check_attr(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        let mut words = args.values_of("args").map(|words| words.collect::<Vec<&str>>()).unwrap_or_default();
+        let all = args.is_present("all");
+        let paths = match args.values_of("paths") {
+            Some(paths) => paths.collect::<Vec<&str>>(),
+            None if all => std::mem::take(&mut words),
+            None => words.split_off(1.min(words.len()))
+        };
+        if all && !words.is_empty() {
+            Err(cli_usage_err(String::from("Attributes and --all both specified")))?
+        }
+        if paths.is_empty() {
+            Err(cli_argument_err("paths"))?
+        }
+
+        let mut attributes = attr::Attributes::new(&repo)?;
+        for arg in paths {
+            let path = worktree::pathspec(&repo, arg)?;
+            if all {
+                for (name, state) in attributes.all(&path)? {
+                    println!("{}: {}: {}", arg, name, state);
+                }
+            } else {
+                for name in &words {
+                    println!("{}: {}: {}", arg, name, attributes.get(&path, name)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn config(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", false)?;
+        let local = || repo.as_ref().map(|repo| repo.git_dir.join("config")).ok_or(pwd_not_repo_err());
+        // Files read, and the file written to.
+        let (files, target) = if let Some(file) = args.value_of("file") {
+            (Some(vec![PathBuf::from(file)]), PathBuf::from(file))
+        } else if args.is_present("global") {
+            (Some(config::global_files()), config::global_file()?)
+        } else if args.is_present("system") {
+            (Some(vec![config::system_file()]), config::system_file())
+        } else if args.is_present("local") {
+            (Some(vec![local()?]), local()?)
+        } else {
+            (None, local().unwrap_or_default())
+        };
+        let git_dir = repo.as_ref().map(|repo| repo.git_dir.as_path());
+        let conf = match &files {
+            Some(files) => Config::from_files(files, git_dir, args.is_present("includes"))?,
+            None => Config::load(git_dir)?
+        };
+        let kind = args.value_of("type");
+        let origin = |entry: &config::Entry| match &repo {
+            Some(repo) if args.is_present("show_origin") => {
+                let top = repo.worktree.as_ref().unwrap_or(&repo.git_dir);
+                format!("file:{}\t", entry.origin.strip_prefix(top).unwrap_or(&entry.origin).display())
+            },
+            None if args.is_present("show_origin") => format!("file:{}\t", entry.origin.display()),
+            _ => String::new()
+        };
+
+        if args.is_present("list") {
+            for entry in conf.entries() {
+                match &entry.value {
+                    Some(value) => println!("{}{}={}", origin(entry), entry.key, value),
+                    None => println!("{}{}", origin(entry), entry.key)
+                }
+            }
+            return Ok(());
+        }
+
+        let name = args.value_of("name").ok_or(cli_argument_err("name"))?;
+        let key = config::canonical_key(name)?;
+        let existing = conf.entries().iter().filter(|entry| entry.key == key).collect::<Vec<&config::Entry>>();
+        if args.is_present("unset") || args.is_present("unset_all") {
+            if target.as_os_str().is_empty() {
+                Err(pwd_not_repo_err())?
+            }
+            let count = Config::from_files(std::slice::from_ref(&target), git_dir, false)?.entries().iter().filter(|entry| entry.key == key).count();
+            if count == 0 {
+                return Err(exit_err(5));
+            }
+            if count > 1 && !args.is_present("unset_all") {
+                eprintln!("warning: {} has multiple values", name);
+                return Err(exit_err(5));
+            }
+            config::unset(&target, name, args.is_present("unset_all"))
+        } else if let Some(value) = args.value_of("value") {
+            if target.as_os_str().is_empty() {
+                Err(pwd_not_repo_err())?
+            }
+            let count = Config::from_files(std::slice::from_ref(&target), git_dir, false)?.entries().iter().filter(|entry| entry.key == key).count();
+            if count > 1 && !args.is_present("add") {
+                eprintln!("warning: {} has multiple values", name);
+                eprintln!("error: cannot overwrite multiple values with a single value");
+                eprintln!("       Use a regexp, --add or --replace-all to change {}.", name);
+                return Err(exit_err(5));
+            }
+            let value = match kind {
+                Some(kind) => self::typed_value(Some(value), kind, &key)?,
+                None => value.to_owned()
+            };
+            if args.is_present("add") {
+                config::add(&target, name, &value)
+            } else {
+                config::set(&target, name, &value)
+            }
+        } else {
+            if existing.is_empty() {
+                return Err(exit_err(1));
+            }
+            let shown = if args.is_present("get_all") { &existing[..] } else { &existing[existing.len() - 1..] };
+            for entry in shown {
+                let value = match kind {
+                    Some(kind) => self::typed_value(entry.value.as_deref(), kind, &key)?,
+                    None => entry.value.clone().unwrap_or_default()
+                };
+                println!("{}{}", origin(entry), value);
+            }
+            Ok(())
+        }
+    }
+
+    /// A config value in the canonical form of `kind`.
+    fn typed_value(value: Option<&str>, kind: &str, key: &str) -> Result<String, Box<WitError>> {
+        match kind {
+            "bool" => config::parse_bool(value)
+                .map(|value| value.to_string())
+                .ok_or(config_err(format!("bad boolean config value '{}' for '{}'", value.unwrap_or(""), key))),
+            "int" => config::parse_int(value.unwrap_or(""))
+                .map(|value| value.to_string())
+                .ok_or(config_err(format!("bad numeric config value '{}' for '{}'", value.unwrap_or(""), key))),
+            _ => Ok(util::expand_user(value.unwrap_or("")).display().to_string())
+        }
+    }
+
+    pub fn clone(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let source = args.value_of("source").ok_or(cli_argument_err("source"))?;
+        let opts = clone::Options {
+            bare: args.is_present("bare"),
+            mirror: args.is_present("mirror"),
+            branch: args.value_of("branch").map(String::from),
+            no_checkout: args.is_present("no_checkout")
+        };
+        let bare = opts.bare || opts.mirror;
+        let dest = args.value_of("directory").map(String::from).unwrap_or(clone::default_dir(source, bare));
+        let quiet = args.is_present("quiet");
+        if !quiet {
+            eprintln!("Cloning into {}'{}'...", if bare { "bare repository " } else { "" }, dest);
+        }
+        let (_, empty) = clone::clone(source, &dest, &opts)?;
+        if empty {
+            eprintln!("warning: You appear to have cloned an empty repository.");
+        } else if !quiet {
+            eprintln!("done.");
+        }
+        Ok(())
+    }
+
+    pub fn remote(args: &ArgMatches) -> Result<(), Box<WitError>> {
+        let repo = Repository::find(".", true)?.ok_or(pwd_not_repo_err())?;
+        match args.subcommand() {
+            Some(("add", sub)) => {
+                let opts = remote::AddOptions {
+                    branches: sub.values_of("track").map(|branches| branches.map(String::from).collect()).unwrap_or_default(),
+                    master: sub.value_of("master").map(String::from),
+                    mirror: match sub.value_of("mirror") {
+                        Some("push") => Some(remote::Mirror::Push),
+                        Some(_) => Some(remote::Mirror::Fetch),
+                        None => None
+                    }
+                };
+                let name = sub.value_of("name").ok_or(cli_argument_err("name"))?;
+                let url = sub.value_of("url").ok_or(cli_argument_err("url"))?;
+                remote::add(&repo, name, url, &opts)
+            },
+            Some(("remove", sub)) => {
+                let kept = remote::remove(&repo, sub.value_of("name").ok_or(cli_argument_err("name"))?)?;
+                if !kept.is_empty() {
+                    match kept.len() {
+                        1 => eprintln!("Note: A branch outside the refs/remotes/ hierarchy was not removed;\nto delete it, use:"),
+                        _ => eprintln!("Note: Some branches outside the refs/remotes/ hierarchy were not removed;\nto delete them, use:")
+                    }
+                    for branch in kept {
+                        eprintln!("  wit update-ref -d refs/heads/{}", branch);
+                    }
+                }
+                Ok(())
+            },
+            Some(("rename", sub)) => {
+                let old = sub.value_of("old").ok_or(cli_argument_err("old"))?;
+                let new = sub.value_of("new").ok_or(cli_argument_err("new"))?;
+                for spec in remote::rename(&repo, old, new)? {
+                    eprintln!("Not updating non-default fetch refspec\n\t{}\n\tPlease update the configuration manually if necessary.", spec);
+                }
+                Ok(())
+            },
+            Some(("set-url", sub)) => {
+                let change = if sub.is_present("add") {
+                    remote::UrlChange::Add
+                } else if sub.is_present("delete") {
+                    remote::UrlChange::Delete
+                } else {
+                    remote::UrlChange::Replace
+                };
+                remote::set_url(
+                    &repo,
+                    sub.value_of("name").ok_or(cli_argument_err("name"))?,
+                    sub.value_of("newurl").ok_or(cli_argument_err("newurl"))?,
+                    sub.value_of("oldurl"),
+                    sub.is_present("push"),
+                    change
+                )
+            },
+            Some(("show", sub)) if sub.is_present("name") => {
+                for name in sub.values_of("name").ok_or(cli_argument_err("name"))? {
+                    remote::show(&repo, name, !sub.is_present("no_query"))?;
+                }
+                Ok(())
+            },
+            _ => {
+                for name in remote::names(&repo.conf) {
+                    if !args.is_present("verbose") {
+                        println!("{}", name);
+                        continue;
+                    }
+                    let remote = Remote::get(&repo.conf, &name)?.unwrap_or_default();
+                    if let Some(url) = remote.urls.first() {
+                        println!("{}\t{} (fetch)", name, url);
+                    }
+                    for url in remote.push_targets() {
+                        println!("{}\t{} (push)", name, url);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    repository::{Repository, InitOptions},
+    reference::{self, Transaction},
+    reflog::ZERO_SHA,
+    commit,
+    config,
+    worktree,
+    error::{WitError, builder::*}
+};
+
+/// How `clone` copies a repository.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub bare: bool,
+    // Copy every ref as it is and remember to keep doing so; implies `bare`.
+    pub mirror: bool,
+    pub branch: Option<String>,
+    pub no_checkout: bool
+}
+
+/// Where a clone of `source` goes when no directory is given: the last component
+/// of its path without `.git`, with `.git` added back for bare clones.
+pub fn default_dir(source: &str, bare: bool) -> String {
+    let path = source.strip_prefix("file://").unwrap_or(source).trim_end_matches('/');
+    let path = path.strip_suffix("/.git").unwrap_or(path);
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    if bare { format!("{}.git", name) } else { name.to_owned() }
+}
+
+/// Whether `url` names a local repository: a path or a `file://` URL, but not
+/// `host:path` or any other `scheme://`.
+pub fn is_local(url: &str) -> bool {
+    match (url.find(':'), url.find('/')) {
+        (None, _) => true,
+        (Some(colon), Some(slash)) => slash < colon || url.starts_with("file://"),
+        (Some(_), None) => false
+    }
+}
+
+/// The git directory of the repository at `source`, a local path or `file://` URL.
+pub fn source_git_dir(source: &str) -> Result<PathBuf, Box<WitError>> {
+    let missing = || repo_not_found_err(format!("repository '{}' does not exist", source));
+    let path = fs::canonicalize(source.strip_prefix("file://").unwrap_or(source)).map_err(|_| missing())?;
+    [path.join(".git"), path.clone(), PathBuf::from(format!("{}.git", path.display()))]
+        .into_iter()
+        .find(|dir| Repository::is_git_dir(dir))
+        .ok_or_else(missing)
+}
+
+/// Clone the local repository at `source` into `dest`, which must not exist or be empty.
+/// A directory we created is removed again if the clone fails.
+pub fn clone(source: &str, dest: &str, opts: &Options) -> Result<(Repository, bool), Box<WitError>> {
+    let target = Path::new(dest);
+    if target.exists() && !target.read_dir().map(|mut entries| entries.next().is_none()).unwrap_or(false) {
+        Err(repo_creation_err(format!("destination path '{}' already exists and is not an empty directory.", dest)))?
+    }
+    let created = !target.exists();
+    let result = self::run(source, dest, opts);
+    if result.is_err() {
+        if created {
+            let _ = fs::remove_dir_all(target);
+        } else if let Ok(entries) = target.read_dir() {
+            for entry in entries.flatten() {
+                let _ = fs::remove_dir_all(entry.path()).or_else(|_| fs::remove_file(entry.path()));
+            }
+        }
+    }
+    result
+}
+
+/// Returns the new repository and whether the source had no commits.
+fn run(source: &str, dest: &str, opts: &Options) -> Result<(Repository, bool), Box<WitError>> {
+    let src = Repository::open(None, source_git_dir(source)?, false)?;
+    let bare = opts.bare || opts.mirror;
+    let (repo, _) = Repository::create(dest, &InitOptions { bare, ..Default::default() })?;
+    self::copy_objects(&src.git_dir.join("objects"), &repo.git_dir.join("objects"))?;
+
+    // Local paths are recorded absolute, URLs as they are.
+    let url = if source.starts_with("file://") {
+        source.to_owned()
+    } else {
+        fs::canonicalize(source)?.display().to_string()
+    };
+    let config_path = repo.git_dir.join("config");
+    config::set(&config_path, "remote.origin.url", &url)?;
+    if opts.mirror {
+        config::set(&config_path, "remote.origin.fetch", "+refs/*:refs/*")?;
+        config::set(&config_path, "remote.origin.mirror", "true")?;
+    } else if !bare {
+        config::set(&config_path, "remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+    }
+
+    let message = format!("clone: from {}", url);
+    let refs = reference::all(&src)?;
+    let mut transaction = Transaction::new(&repo);
+    for (name, sha) in &refs {
+        // Mirrors copy every ref; other clones keep tags, and bare ones branches too.
+        let local = if opts.mirror || name.starts_with("refs/tags/") || (bare && name.starts_with("refs/heads/")) {
+            Some(name.clone())
+        } else {
+            name.strip_prefix("refs/heads/").map(|branch| format!("refs/remotes/origin/{}", branch))
+        };
+        if let Some(local) = local {
+            transaction.update(&local, sha, Some(ZERO_SHA), false, &message);
+        }
+    }
+    transaction.commit()?;
+
+    // The branch to check out: the one asked for, or the one the source has checked out.
+    let head = reference::read_symbolic(&src, "HEAD")?.unwrap_or(String::from("refs/heads/main"));
+    let sha_of = |name: &str| refs.iter().find(|(refname, _)| refname == name).map(|(_, sha)| sha.clone());
+    let (branch, detached) = match &opts.branch {
+        Some(name) if sha_of(&format!("refs/heads/{}", name)).is_some() => (format!("refs/heads/{}", name), None),
+        Some(name) => match sha_of(&format!("refs/tags/{}", name)) {
+            Some(sha) => (String::new(), Some(reference::peel(&src, &sha).unwrap_or(sha))),
+            None => Err(unknown_reference_err(format!("Remote branch {} not found in upstream origin", name)))?
+        },
+        None => (head.clone(), None)
+    };
+    let empty = refs.is_empty();
+    if !bare && sha_of(&head).is_some() {
+        let target = format!("refs/remotes/origin/{}", reference::shorten(&head));
+        reference::set_symbolic(&repo, "refs/remotes/origin/HEAD", &target, "")?;
+    }
+
+    let checkout = match detached {
+        Some(sha) => {
+            let mut transaction = Transaction::new(&repo);
+            transaction.update("HEAD", &sha, None, false, &message);
+            transaction.commit()?;
+            Some(sha)
+        },
+        None if bare => {
+            reference::set_symbolic(&repo, "HEAD", &branch, &message)?;
+            None
+        },
+        None => {
+            let short = reference::shorten(&branch).to_owned();
+            let sha = sha_of(&branch);
+            if let Some(sha) = &sha {
+                reference::update(&repo, &branch, sha, &message)?;
+                config::set(&config_path, &format!("branch.{}.remote", short), "origin")?;
+                config::set(&config_path, &format!("branch.{}.merge", short), &branch)?;
+            }
+            reference::set_symbolic(&repo, "HEAD", &branch, &message)?;
+            sha
+        }
+    };
+
+    let repo = if bare { Repository::open(None, repo.git_dir, false)? } else { Repository::new(dest, false)? };
+    if let (Some(sha), false, false) = (checkout, bare, opts.no_checkout) {
+        worktree::hard_reset(&repo, &commit::tree_of(&repo, &sha)?)?;
+    }
+    Ok((repo, empty))
+}
+
+/// Hard link every file under `from` into `to`, copying where links are not possible.
+fn copy_objects(from: &Path, to: &Path) -> Result<(), Box<WitError>> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            self::copy_objects(&entry.path(), &dest)?;
+        } else if !dest.exists() && fs::hard_link(entry.path(), &dest).is_err() {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
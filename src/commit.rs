@@ -1,7 +1,8 @@
 use crate::{
-    object::Object, 
-    repository::Repository, 
-    error::WitError
+    object::{self, Object, WitObject},
+    repository::Repository,
+    error::{WitError, builder::malformed_object_err},
+    util
 };
 use crate::kvlm::{ KVLMExt, KVLM };
 
@@ -21,6 +22,27 @@ impl<'a> Commit<'a> {
     pub fn kvlm(&self) -> &KVLM {
         &self.kvlm
     }
+
+    pub fn kvlm_mut(&mut self) -> &mut KVLM {
+        &mut self.kvlm
+    }
+
+    pub fn message(&self) -> &str {
+        self.kvlm.get("").and_then(|values| values.first()).map(String::as_str).unwrap_or("")
+    }
+
+    /// First line of the message.
+    pub fn subject(&self) -> &str {
+        self.message().lines().next().unwrap_or("")
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.kvlm.get(key).and_then(|values| values.first()).map(String::as_str)
+    }
+
+    pub fn parents(&self) -> Vec<String> {
+        self.kvlm.get("parent").cloned().unwrap_or_default()
+    }
 }
 
 impl<'a> Object for Commit<'a> {
@@ -41,3 +63,41 @@ impl<'a> Object for Commit<'a> {
         self.repo
     }
 }
+
+/// Write a commit of `tree` with the given parents, committed by the repository
+/// identity now. `author` keeps the authorship of a commit being replayed and
+/// defaults to the committer. Returns the new commit's sha.
+pub fn create(
+    repo: &Repository,
+    tree: &str,
+    parents: &[String],
+    message: &str,
+    author: Option<&str>
+) -> Result<String, Box<WitError>> {
+    let mut commit = Commit::new(Some(repo));
+    let (timestamp, tz) = util::now();
+    let signature = format!("{} {} {}", repo.identity(), timestamp, tz);
+
+    let kvlm = commit.kvlm_mut();
+    kvlm.insert("tree".to_owned(), vec![tree.to_owned()]);
+    if !parents.is_empty() {
+        kvlm.insert("parent".to_owned(), parents.to_vec());
+    }
+    kvlm.insert("author".to_owned(), vec![author.map(str::to_owned).unwrap_or(signature.clone())]);
+    kvlm.insert("committer".to_owned(), vec![signature]);
+    kvlm.insert("".to_owned(), vec![if message.ends_with('\n') { message.to_owned() } else { format!("{}\n", message) }]);
+
+    object::write(WitObject::CommitObject(commit), true)
+}
+
+pub fn read<'a>(repo: &'a Repository, sha: &str) -> Result<Commit<'a>, Box<WitError>> {
+    match object::read(repo, sha)? {
+        WitObject::CommitObject(commit) => Ok(commit),
+        _ => Err(malformed_object_err(format!("Object {} is not a commit", sha)))
+    }
+}
+
+/// The tree a commit points to.
+pub fn tree_of(repo: &Repository, sha: &str) -> Result<String, Box<WitError>> {
+    object::find(repo, sha, Some("tree"), true)
+}
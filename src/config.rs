@@ -0,0 +1,631 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    lockfile::LockFile,
+    util,
+    error::{WitError, builder::*}
+};
+
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// One `key = value` line. Keys are canonical: section and variable name lower case,
+/// subsection as written. A variable without `=` has no value and means true.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub value: Option<String>,
+    pub origin: PathBuf
+}
+
+/// Settings from the system, global and repository config files, in increasing precedence.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    entries: Vec<Entry>
+}
+
+/// The byte ranges of each entry and section header in a file, for editing them in
+/// place. An entry's range runs to the end of its value, comment included.
+struct Parsed {
+    entries: Vec<(Entry, usize, usize)>,
+    sections: Vec<(String, usize, usize)>
+}
+
+/// `GIT_CONFIG_SYSTEM`, defaulting to `/etc/gitconfig`.
+pub fn system_file() -> PathBuf {
+    std::env::var_os("GIT_CONFIG_SYSTEM").map(PathBuf::from).unwrap_or(PathBuf::from("/etc/gitconfig"))
+}
+
+/// The files that make up the system and global layers, lowest precedence first.
+pub fn system_files() -> Vec<PathBuf> {
+    if std::env::var_os("GIT_CONFIG_NOSYSTEM").is_some() {
+        return Vec::new();
+    }
+    vec![self::system_file()]
+}
+
+pub fn global_files() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(path)];
+    }
+    let mut ret = Vec::new();
+    ret.extend(util::xdg_config("config"));
+    if let Ok(home) = std::env::var("HOME") {
+        ret.push(Path::new(&home).join(".gitconfig"));
+    }
+    ret
+}
+
+/// The global file that `--global` writes to: `~/.gitconfig`, unless only the XDG one exists.
+pub fn global_file() -> Result<PathBuf, Box<WitError>> {
+    let files = self::global_files();
+    let home = files.last().cloned().ok_or(config_err(String::from("$HOME not set")))?;
+    match files.first() {
+        Some(xdg) if !home.is_file() && xdg.is_file() => Ok(xdg.clone()),
+        _ => Ok(home)
+    }
+}
+
+impl Config {
+    /// System, global and, with `git_dir`, repository settings.
+    pub fn load(git_dir: Option<&Path>) -> Result<Config, Box<WitError>> {
+        let mut files = self::system_files();
+        files.extend(self::global_files());
+        files.extend(git_dir.map(|dir| dir.join("config")));
+        Self::from_files(&files, git_dir, true)
+    }
+
+    /// Settings of the given files in increasing precedence, with what they include if `includes`.
+    pub fn from_files(paths: &[PathBuf], git_dir: Option<&Path>, includes: bool) -> Result<Config, Box<WitError>> {
+        let mut config = Config::default();
+        for path in paths {
+            config.read(path, git_dir, if includes { Some(0) } else { None })?;
+        }
+        Ok(config)
+    }
+
+    /// Read one file; includes are followed unless `depth` is `None`.
+    fn read(&mut self, path: &Path, git_dir: Option<&Path>, depth: Option<usize>) -> Result<(), Box<WitError>> {
+        if depth > Some(MAX_INCLUDE_DEPTH) {
+            Err(config_err(format!("exceeded maximum include depth ({}) while including {}", MAX_INCLUDE_DEPTH, path.display())))?
+        }
+        if !path.is_file() {
+            return Ok(());
+        }
+        let parsed = self::parse(&fs::read_to_string(path)?, path)?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        for (entry, ..) in parsed.entries {
+            let include = match (entry.key.as_str(), &entry.value) {
+                ("include.path", Some(value)) => Some(value.clone()),
+                (key, Some(value)) if key.starts_with("includeif.") && key.ends_with(".path") => {
+                    let condition = &key["includeif.".len()..key.len() - ".path".len()];
+                    if self::condition_holds(condition, &dir, git_dir) { Some(value.clone()) } else { None }
+                },
+                _ => None
+            };
+            self.entries.push(entry);
+            if let (Some(include), Some(depth)) = (include, depth) {
+                // Relative includes are relative to the including file.
+                let target = util::expand_user(&include);
+                self.read(&dir.join(target), git_dir, Some(depth + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &Vec<Entry> {
+        &self.entries
+    }
+
+    /// The last value of `key`; a variable without a value reads as an empty string.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let key = self::canonical_key(key).ok()?;
+        self.entries.iter().rev().find(|entry| entry.key == key).map(|entry| entry.value.clone().unwrap_or_default())
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, Box<WitError>> {
+        let key = self::canonical_key(key)?;
+        match self.entries.iter().rev().find(|entry| entry.key == key) {
+            Some(entry) => self::parse_bool(entry.value.as_deref()).map(Some).ok_or(
+                config_err(format!("bad boolean config value '{}' for '{}'", entry.value.as_deref().unwrap_or(""), key))
+            ),
+            None => Ok(None)
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>, Box<WitError>> {
+        match self.get(key) {
+            Some(value) => self::parse_int(&value).map(Some).ok_or(
+                config_err(format!("bad numeric config value '{}' for '{}'", value, key))
+            ),
+            None => Ok(None)
+        }
+    }
+}
+
+/// `true`, `yes`, `on`, no value or a non-zero number are true; `false`, `no`, `off`,
+/// an empty value or zero are false.
+pub fn parse_bool(value: Option<&str>) -> Option<bool> {
+    match value.map(str::to_lowercase).as_deref() {
+        None | Some("true") | Some("yes") | Some("on") => Some(true),
+        Some("false") | Some("no") | Some("off") | Some("") => Some(false),
+        Some(other) => self::parse_int(other).map(|n| n != 0)
+    }
+}
+
+/// A number with an optional `k`, `m` or `g` suffix for multiples of 1024.
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, factor) = match value.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('k') => (&value[..value.len() - 1], 1 << 10),
+        Some('m') => (&value[..value.len() - 1], 1 << 20),
+        Some('g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1)
+    };
+    digits.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// `section.name` or `section.subsection.name` with section and name lower cased.
+pub fn canonical_key(key: &str) -> Result<String, Box<WitError>> {
+    let (section, name) = key.rsplit_once('.').ok_or(config_err(format!("key does not contain a section: {}", key)))?;
+    if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        Err(config_err(format!("invalid key: {}", key)))?
+    }
+    let section = match section.split_once('.') {
+        Some((section, subsection)) => format!("{}.{}", section.to_lowercase(), subsection),
+        None => section.to_lowercase()
+    };
+    if section.is_empty() || section.starts_with('.') {
+        Err(config_err(format!("key does not contain a section: {}", key)))?
+    }
+    Ok(format!("{}.{}", section, name.to_lowercase()))
+}
+
+/// Whether an `includeIf` condition holds. Only `gitdir:` and `gitdir/i:` are known.
+fn condition_holds(condition: &str, dir: &Path, git_dir: Option<&Path>) -> bool {
+    let (pattern, fold) = if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        (pattern, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        (pattern, true)
+    } else {
+        return false;
+    };
+    let git_dir = match git_dir.and_then(|dir| dir.canonicalize().ok()) {
+        Some(dir) => dir.to_string_lossy().into_owned(),
+        None => return false
+    };
+
+    let mut pattern = if let Some(rest) = pattern.strip_prefix("./") {
+        dir.join(rest).to_string_lossy().into_owned()
+    } else {
+        util::expand_user(pattern).to_string_lossy().into_owned()
+    };
+    if !pattern.starts_with('/') {
+        pattern = format!("**/{}", pattern);
+    }
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    if fold {
+        util::wildmatch(&pattern.to_lowercase(), &git_dir.to_lowercase(), true)
+    } else {
+        util::wildmatch(&pattern, &git_dir, true)
+    }
+}
+
+fn parse(text: &str, origin: &Path) -> Result<Parsed, Box<WitError>> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let bad = |line: usize| config_err(format!("bad config line {} in file {}", line + 1, origin.display()));
+    // Byte offset of each char, and of the end.
+    let offsets = text.char_indices().map(|(at, _)| at).chain([text.len()]).collect::<Vec<usize>>();
+    let mut ret = Parsed { entries: Vec::new(), sections: Vec::new() };
+    let mut section: Option<String> = None;
+    let (mut i, mut line) = (0, 0);
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '#' || c == ';' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '[' {
+            let start = i;
+            i += 1;
+            let mut name = String::new();
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '-' || chars[i] == '.') {
+                name.push(chars[i].to_ascii_lowercase());
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == ' ' || chars[i] == '\t') {
+                // [section "subsection"], where the subsection keeps its case.
+                while i < chars.len() && (chars[i] == ' ' || chars[i] == '\t') {
+                    i += 1;
+                }
+                if chars.get(i) != Some(&'"') {
+                    Err(bad(line))?
+                }
+                i += 1;
+                let mut subsection = String::new();
+                loop {
+                    match chars.get(i) {
+                        Some('"') => break,
+                        Some('\\') if i + 1 < chars.len() && chars[i + 1] != '\n' => {
+                            subsection.push(chars[i + 1]);
+                            i += 2;
+                        },
+                        Some('\n') | Some('\\') | None => Err(bad(line))?,
+                        Some(c) => {
+                            subsection.push(*c);
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                name = format!("{}.{}", name, subsection);
+            }
+            if chars.get(i) != Some(&']') || name.is_empty() || name.starts_with('.') {
+                Err(bad(line))?
+            }
+            i += 1;
+            ret.sections.push((name.clone(), offsets[start], offsets[i]));
+            section = Some(name);
+        } else if c.is_ascii_alphabetic() {
+            let (start, start_line) = (i, line);
+            let mut name = String::new();
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '-') {
+                name.push(chars[i].to_ascii_lowercase());
+                i += 1;
+            }
+            while i < chars.len() && (chars[i] == ' ' || chars[i] == '\t') {
+                i += 1;
+            }
+            let value = match chars.get(i) {
+                Some('=') => {
+                    i += 1;
+                    Some(self::parse_value(&chars, &mut i, &mut line).ok_or_else(|| bad(start_line))?)
+                },
+                Some('#') | Some(';') => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                    None
+                },
+                None | Some('\n') | Some('\r') => None,
+                Some(_) => Err(bad(line))?
+            };
+            let section = section.as_ref().ok_or_else(|| bad(start_line))?;
+            let mut end = i;
+            while end > start && chars[end - 1] == '\r' {
+                end -= 1;
+            }
+            ret.entries.push((
+                Entry { key: format!("{}.{}", section, name), value, origin: origin.to_path_buf() },
+                offsets[start],
+                offsets[end]
+            ));
+        } else {
+            Err(bad(line))?
+        }
+    }
+    Ok(ret)
+}
+
+/// A value up to the end of its line: quotes group, backslash escapes and continues
+/// lines, comments end it and unquoted whitespace at either end is dropped.
+fn parse_value(chars: &[char], i: &mut usize, line: &mut usize) -> Option<String> {
+    let mut ret = String::new();
+    let mut quoted = false;
+    let mut spaces = String::new();
+    while *i < chars.len() {
+        let c = chars[*i];
+        *i += 1;
+        match c {
+            '\n' if quoted => return None,
+            '\n' => {
+                *i -= 1;
+                break;
+            },
+            '#' | ';' if !quoted => {
+                while *i < chars.len() && chars[*i] != '\n' {
+                    *i += 1;
+                }
+                break;
+            },
+            c if c.is_whitespace() && !quoted => {
+                if !ret.is_empty() {
+                    spaces.push(c);
+                }
+            },
+            '"' => {
+                ret.push_str(&spaces);
+                spaces.clear();
+                quoted = !quoted;
+            },
+            '\\' => {
+                let escaped = match chars.get(*i) {
+                    Some('\n') => {
+                        *line += 1;
+                        *i += 1;
+                        continue;
+                    },
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    _ => return None
+                };
+                *i += 1;
+                ret.push_str(&spaces);
+                spaces.clear();
+                ret.push(escaped);
+            },
+            c => {
+                ret.push_str(&spaces);
+                spaces.clear();
+                ret.push(c);
+            }
+        }
+    }
+    if quoted { None } else { Some(ret) }
+}
+
+/// `value` as it has to be written to keep it intact when read back.
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if value.starts_with(char::is_whitespace) || value.ends_with(char::is_whitespace) || value.contains(['#', ';']) {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// The `[section "subsection"]` header that starts `section`.
+fn header(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => format!(
+            "[{} \"{}\"]", name, subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]", section)
+    }
+}
+
+/// Replace byte ranges of `text`. A range replaced by nothing takes the whitespace
+/// before it along, and its whole line when nothing else is left there.
+fn edit(text: &str, mut edits: Vec<(usize, usize, String)>) -> String {
+    let mut ret = text.to_owned();
+    edits.sort_by_key(|(start, ..)| *start);
+    for (mut start, mut end, replacement) in edits.into_iter().rev() {
+        if replacement.is_empty() {
+            let bytes = ret.as_bytes();
+            while start > 0 && (bytes[start - 1] == b' ' || bytes[start - 1] == b'\t') {
+                start -= 1;
+            }
+            let mut after = end;
+            while after < bytes.len() && (bytes[after] == b' ' || bytes[after] == b'\t' || bytes[after] == b'\r') {
+                after += 1;
+            }
+            if after == bytes.len() || bytes[after] == b'\n' {
+                let line_start = start == 0 || bytes[start - 1] == b'\n';
+                end = if line_start { bytes.len().min(after + 1) } else { after };
+            }
+        }
+        ret.replace_range(start..end, &replacement);
+    }
+    ret
+}
+
+/// Set `key` to `value` in the file at `path`, replacing its single existing value
+/// or adding it at the end of its section.
+pub fn set(path: &Path, key: &str, value: &str) -> Result<(), Box<WitError>> {
+    self::write(path, key, value, true)
+}
+
+/// Add another value for `key` at the end of its section in the file at `path`.
+pub fn add(path: &Path, key: &str, value: &str) -> Result<(), Box<WitError>> {
+    self::write(path, key, value, false)
+}
+
+fn write(path: &Path, key: &str, value: &str, replace: bool) -> Result<(), Box<WitError>> {
+    let canonical = self::canonical_key(key)?;
+    let (section, _) = canonical.rsplit_once('.').unwrap();
+    let name = key.rsplit_once('.').map(|(_, name)| name).unwrap_or(key);
+    let text = if path.is_file() { fs::read_to_string(path)? } else { String::new() };
+    let parsed = self::parse(&text, path)?;
+    let entry = format!("{} = {}", name, self::quote(value));
+
+    let existing = parsed.entries.iter().filter(|(entry, ..)| replace && entry.key == canonical).collect::<Vec<_>>();
+    if existing.len() > 1 {
+        Err(config_err(format!("{} has multiple values", canonical)))?
+    }
+    let edited = if let Some((_, start, end)) = existing.first() {
+        self::edit(&text, vec![(*start, *end, entry)])
+    } else {
+        // After the line holding the last header or entry of the section.
+        let last = parsed.sections.iter()
+            .filter(|(name, ..)| name == section)
+            .map(|(.., end)| *end)
+            .chain(parsed.entries.iter().filter(|(entry, ..)| entry.key.rsplit_once('.').map(|(s, _)| s) == Some(section)).map(|(.., end)| *end))
+            .max();
+        let newline = if text.is_empty() || text.ends_with('\n') { "" } else { "\n" };
+        match last.map(|last| text[last..].find('\n').map(|n| last + n + 1)) {
+            Some(Some(at)) => self::edit(&text, vec![(at, at, format!("\t{}\n", entry))]),
+            Some(None) => format!("{}\n\t{}\n", text, entry),
+            None => format!("{}{}{}\n\t{}\n", text, newline, self::header(section), entry)
+        }
+    };
+    LockFile::acquire(path)?.commit(edited.as_bytes())
+}
+
+/// Remove `key` from the file at `path`. Several values are only removed with `all`.
+pub fn unset(path: &Path, key: &str, all: bool) -> Result<(), Box<WitError>> {
+    let canonical = self::canonical_key(key)?;
+    let text = if path.is_file() { fs::read_to_string(path)? } else { String::new() };
+    let parsed = self::parse(&text, path)?;
+    let existing = parsed.entries.iter().filter(|(entry, ..)| entry.key == canonical).collect::<Vec<_>>();
+    if existing.is_empty() {
+        Err(config_err(format!("{} is not set", canonical)))?
+    }
+    if existing.len() > 1 && !all {
+        Err(config_err(format!("{} has multiple values", canonical)))?
+    }
+    self::remove_entries(path, &text, &parsed, &existing)
+}
+
+/// Remove the values of `key` that `matches` picks from the file at `path`, leaving
+/// the others where they are.
+pub fn unset_matching(path: &Path, key: &str, matches: impl Fn(&str) -> bool) -> Result<(), Box<WitError>> {
+    let canonical = self::canonical_key(key)?;
+    let text = if path.is_file() { fs::read_to_string(path)? } else { String::new() };
+    let parsed = self::parse(&text, path)?;
+    let existing = parsed.entries.iter()
+        .filter(|(entry, ..)| entry.key == canonical && matches(entry.value.as_deref().unwrap_or("")))
+        .collect::<Vec<_>>();
+    self::remove_entries(path, &text, &parsed, &existing)
+}
+
+fn remove_entries(path: &Path, text: &str, parsed: &Parsed, existing: &[&(Entry, usize, usize)]) -> Result<(), Box<WitError>> {
+    let mut removed = existing.iter().map(|(_, start, end)| (*start, *end)).collect::<Vec<(usize, usize)>>();
+    removed.sort();
+
+    // A section left without entries or comments goes as well.
+    let mut headers = Vec::new();
+    for (i, (_, start, end)) in parsed.sections.iter().enumerate() {
+        let next = parsed.sections.get(i + 1).map(|(_, start, _)| *start).unwrap_or(text.len());
+        let inside = removed.iter().filter(|(from, _)| *end <= *from && *from < next).collect::<Vec<_>>();
+        let mut at = *end;
+        let mut emptied = !inside.is_empty();
+        for (from, to) in inside {
+            emptied &= text[at..*from].trim().is_empty();
+            at = *to;
+        }
+        if emptied && text[at..next].trim().is_empty() {
+            headers.push((*start, *end));
+        }
+    }
+    let edits = removed.into_iter().chain(headers).map(|(start, end)| (start, end, String::new())).collect();
+    LockFile::acquire(path)?.commit(self::edit(text, edits).as_bytes())
+}
+
+/// Rename every `old` section (e.g. `remote.origin`) in the file at `path` to `new`, or
+/// remove them with all their lines when `new` is `None`. Returns whether there were any.
+pub fn rename_section(path: &Path, old: &str, new: Option<&str>) -> Result<bool, Box<WitError>> {
+    let canonical = |section: &str| match section.split_once('.') {
+        Some((name, subsection)) => format!("{}.{}", name.to_lowercase(), subsection),
+        None => section.to_lowercase()
+    };
+    let old = canonical(old);
+    let text = if path.is_file() { fs::read_to_string(path)? } else { String::new() };
+    let parsed = self::parse(&text, path)?;
+    let mut edits = Vec::new();
+    for (i, (name, start, end)) in parsed.sections.iter().enumerate() {
+        if *name != old {
+            continue;
+        }
+        match new {
+            Some(new) => edits.push((*start, *end, self::header(&canonical(new)))),
+            None => {
+                let next = parsed.sections.get(i + 1).map(|(_, start, _)| *start).unwrap_or(text.len());
+                edits.push((*start, next, String::new()));
+            }
+        }
+    }
+    let found = !edits.is_empty();
+    if found {
+        LockFile::acquire(path)?.commit(self::edit(&text, edits).as_bytes())?;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config file holding `text` in a fresh temporary directory.
+    fn file(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wit-config-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config");
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn value(chars: &str) -> Option<String> {
+        let chars = chars.chars().collect::<Vec<char>>();
+        super::parse_value(&chars, &mut 0, &mut 0)
+    }
+
+    #[test]
+    fn parse_entries_and_sections() {
+        let text = "[Core]\n\tBare = false\n[remote \"Origin\"] url = /tmp/x\n\tflag\n";
+        let parsed = super::parse(text, Path::new("config")).unwrap();
+        let keys = parsed.entries.iter().map(|(entry, ..)| entry.key.as_str()).collect::<Vec<&str>>();
+        assert_eq!(keys, ["core.bare", "remote.Origin.url", "remote.Origin.flag"]);
+        assert_eq!(parsed.entries[1].0.value.as_deref(), Some("/tmp/x"));
+        assert_eq!(parsed.entries[2].0.value, None);
+        let (_, start, end) = &parsed.entries[1];
+        assert_eq!(&text[*start..*end], "url = /tmp/x");
+        let (name, start, end) = &parsed.sections[1];
+        assert_eq!((name.as_str(), &text[*start..*end]), ("remote.Origin", "[remote \"Origin\"]"));
+        assert!(super::parse("x = 1\n", Path::new("config")).is_err());
+        assert!(super::parse("[core\n", Path::new("config")).is_err());
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(value("  plain value  \n").as_deref(), Some("plain value"));
+        assert_eq!(value("\" padded \" # comment").as_deref(), Some(" padded "));
+        assert_eq!(value("a\\tb\\\\c\\\"d").as_deref(), Some("a\tb\\c\"d"));
+        assert_eq!(value("one \\\ntwo").as_deref(), Some("one two"));
+        assert_eq!(value("x ; y").as_deref(), Some("x"));
+        assert_eq!(value("\"open"), None);
+        assert_eq!(value("bad \\q"), None);
+    }
+
+    #[test]
+    fn set_keeps_headers_sharing_a_line() {
+        let path = file("set", "[foo] bar = 1\n[core]\n\tx = 1 # note\n");
+        super::set(&path, "foo.bar", "2").unwrap();
+        super::set(&path, "foo.baz", "a#b").unwrap();
+        super::set(&path, "core.x", "3").unwrap();
+        super::set(&path, "new.key", "v").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[foo] bar = 2\n\tbaz = \"a#b\"\n[core]\n\tx = 3\n[new]\n\tkey = v\n"
+        );
+        assert!(super::add(&path, "foo.bar", "4").is_ok());
+        assert!(super::set(&path, "foo.bar", "5").is_err());
+    }
+
+    #[test]
+    fn unset_drops_emptied_sections() {
+        let path = file("unset", "[foo] bar = 1\n\tbaz = 2\n[core]\n\t# keep\n\tx = 1\n[last]\n\ty = 1");
+        super::unset(&path, "foo.bar", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[foo]\n\tbaz = 2\n[core]\n\t# keep\n\tx = 1\n[last]\n\ty = 1");
+        super::unset(&path, "foo.baz", false).unwrap();
+        super::unset(&path, "core.x", false).unwrap();
+        super::unset(&path, "last.y", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[core]\n\t# keep\n");
+        assert!(super::unset(&path, "core.x", false).is_err());
+    }
+
+    #[test]
+    fn rename_and_remove_sections() {
+        let path = file("rename", "[remote \"a\"] url = x\n\tfetch = y\n[core]\n\tbare = false\n");
+        assert!(super::rename_section(&path, "remote.a", Some("remote.b")).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[remote \"b\"] url = x\n\tfetch = y\n[core]\n\tbare = false\n");
+        assert!(!super::rename_section(&path, "remote.a", None).unwrap());
+        assert!(super::rename_section(&path, "remote.b", None).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[core]\n\tbare = false\n");
+    }
+}
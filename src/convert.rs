@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::{
+    repository::Repository,
+    attr::{Attributes, State},
+    error::{WitError, builder::*}
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Text {
+    Binary,
+    Always,
+    Auto
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Eol {
+    Lf,
+    Crlf
+}
+
+/// Whether `data` looks binary, the way git guesses: any NUL byte, or more than
+/// one control character per 128 printable ones.
+pub fn is_binary(data: &[u8]) -> bool {
+    if data.contains(&0) {
+        return true;
+    }
+    let (mut printable, mut nonprintable) = (0usize, 0usize);
+    for byte in data {
+        match byte {
+            b'\n' | b'\r' | b'\t' | 0x08 | 0x0c | 0x1b => printable += 1,
+            0x7f => nonprintable += 1,
+            byte if *byte < 0x20 => nonprintable += 1,
+            _ => printable += 1
+        }
+    }
+    (printable >> 7) < nonprintable
+}
+
+/// How `path` is converted, from the `text` and `eol` attributes and then
+/// `core.autocrlf` and `core.eol`.
+fn action(repo: &Repository, checks: &BTreeMap<String, State>) -> (Text, Eol) {
+    let eol_attr = match checks.get("eol").and_then(State::value) {
+        Some("crlf") => Some(Eol::Crlf),
+        Some("lf") => Some(Eol::Lf),
+        _ => None
+    };
+    let autocrlf = repo.conf.get("core.autocrlf").map(|value| value.to_lowercase());
+    let text = match checks.get("text") {
+        Some(State::Set) => Text::Always,
+        Some(State::Unset) => Text::Binary,
+        Some(State::Value(value)) if value == "auto" => Text::Auto,
+        _ if eol_attr.is_some() => Text::Always,
+        _ => match autocrlf.as_deref() {
+            Some("true") | Some("input") => Text::Auto,
+            _ => Text::Binary
+        }
+    };
+    let eol = eol_attr.unwrap_or(match autocrlf.as_deref() {
+        Some("true") => Eol::Crlf,
+        Some("input") => Eol::Lf,
+        _ => match repo.conf.get("core.eol").as_deref() {
+            Some("crlf") => Eol::Crlf,
+            Some("native") if cfg!(windows) => Eol::Crlf,
+            _ => Eol::Lf
+        }
+    });
+    (text, eol)
+}
+
+/// Run the `clean` or `smudge` command of the driver the `filter` attribute names.
+/// Failures only stop us when the driver is `required`; otherwise `data` passes through.
+fn filter(repo: &Repository, checks: &BTreeMap<String, State>, path: &str, data: Vec<u8>, kind: &str) -> Result<Vec<u8>, Box<WitError>> {
+    let name = match checks.get("filter").and_then(State::value) {
+        Some(name) => name,
+        None => return Ok(data)
+    };
+    let required = repo.conf.get_bool(&format!("filter.{}.required", name))?.unwrap_or(false);
+    let failed = || io_err(format!("{}: {} filter '{}' failed", path, kind, name));
+    let command = match repo.conf.get(&format!("filter.{}.{}", name, kind)) {
+        Some(command) => command,
+        None if required => Err(failed())?,
+        None => return Ok(data)
+    };
+
+    // `%f` is replaced by the quoted path of the file being filtered.
+    let quoted = format!("'{}'", path.replace('\'', "'\\''"));
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command.replace("%f", &quoted))
+        .current_dir(repo.worktree.as_ref().unwrap_or(&repo.git_dir))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or_else(failed)?;
+    let input = data.clone();
+    // Feed the filter from another thread so that a full stdout pipe cannot deadlock us.
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    let written = writer.join().map(|result| result.is_ok()).unwrap_or(false);
+    if output.status.success() && written {
+        return Ok(output.stdout);
+    }
+    if required {
+        Err(failed())?
+    }
+    eprintln!("error: external filter '{}' failed", command);
+    Ok(data)
+}
+
+/// Worktree content of `path` as it is stored in the repository: the clean filter runs,
+/// then CRLF becomes LF for text files.
+pub fn to_git(repo: &Repository, attrs: &mut Attributes, path: &str, data: Vec<u8>) -> Result<Vec<u8>, Box<WitError>> {
+    let checks = attrs.check(path)?;
+    let data = self::filter(repo, &checks, path, data, "clean")?;
+    let (text, _) = self::action(repo, &checks);
+    if text == Text::Binary || (text == Text::Auto && self::is_binary(&data)) || !data.contains(&b'\r') {
+        return Ok(data);
+    }
+    let mut ret = Vec::with_capacity(data.len());
+    for (i, byte) in data.iter().enumerate() {
+        if !(*byte == b'\r' && data.get(i + 1) == Some(&b'\n')) {
+            ret.push(*byte);
+        }
+    }
+    Ok(ret)
+}
+
+/// Repository content of `path` as it is written to the worktree: LF becomes CRLF for
+/// text files checked out with CRLF line endings, then the smudge filter runs.
+pub fn to_worktree(repo: &Repository, attrs: &mut Attributes, path: &str, data: Vec<u8>) -> Result<Vec<u8>, Box<WitError>> {
+    let checks = attrs.check(path)?;
+    let (text, eol) = self::action(repo, &checks);
+    // Automatic conversion leaves alone files that already have CRs in the repository.
+    if text == Text::Binary || eol == Eol::Lf || (text == Text::Auto && (self::is_binary(&data) || data.contains(&b'\r'))) {
+        return self::filter(repo, &checks, path, data, "smudge");
+    }
+    let mut ret = Vec::with_capacity(data.len() + data.len() / 16);
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+            ret.push(b'\r');
+        }
+        ret.push(*byte);
+    }
+    self::filter(repo, &checks, path, ret, "smudge")
+}
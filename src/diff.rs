@@ -0,0 +1,122 @@
+/// Split `data` into lines, keeping each line's terminating newline.
+pub fn lines(data: &[u8]) -> Vec<&[u8]> {
+    let mut ret = Vec::new();
+    let mut start = 0;
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == b'\n' {
+            ret.push(&data[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < data.len() {
+        ret.push(&data[start..]);
+    }
+    ret
+}
+
+/// A changed region: `a[a_start..a_end]` was replaced by `b[b_start..b_end]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub a_start: usize,
+    pub a_end: usize,
+    pub b_start: usize,
+    pub b_end: usize
+}
+
+/// Pairs of indices of lines that `a` and `b` have in common, in order,
+/// forming a shortest edit script (Myers' O(ND) algorithm).
+pub fn matches<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let mut ret = Vec::new();
+    self::myers_into(a, b, (0, 0), &mut ret);
+    ret
+}
+
+/// Linear space Myers: find the middle snake of a shortest edit script, then
+/// solve the parts before and after it the same way. `at` is where `a` and `b`
+/// start in the sequences being compared.
+fn myers_into<T: PartialEq>(a: &[T], b: &[T], at: (usize, usize), ret: &mut Vec<(usize, usize)>) {
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (inner_a, inner_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    // Common prefix and suffix never take part in an edit, so leave them out of the search.
+    ret.extend((0..prefix).map(|i| (at.0 + i, at.1 + i)));
+    if !inner_a.is_empty() && !inner_b.is_empty() {
+        let ((x, y), (u, v)) = self::middle_snake(inner_a, inner_b);
+        let inner_at = (at.0 + prefix, at.1 + prefix);
+        self::myers_into(&inner_a[..x], &inner_b[..y], inner_at, ret);
+        ret.extend((0..u - x).map(|i| (inner_at.0 + x + i, inner_at.1 + y + i)));
+        self::myers_into(&inner_a[u..], &inner_b[v..], (inner_at.0 + u, inner_at.1 + v), ret);
+    }
+    ret.extend((0..suffix).map(|k| (at.0 + a.len() - suffix + k, at.1 + b.len() - suffix + k)));
+}
+
+/// Start and end of the snake in the middle of a shortest edit script from `a` to `b`,
+/// searching forwards from the start and backwards from the end until the paths meet.
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T]) -> ((usize, usize), (usize, usize)) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // Furthest x reached on each diagonal, forwards and, counted from the end, backwards.
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = vec![0isize; 2 * max as usize + 3];
+    let at = |k: isize| (k + offset) as usize;
+
+    for d in 0..=max {
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let start = (x as usize, y as usize);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x;
+            if delta % 2 != 0 && (delta - k).abs() < d && x + backward[at(delta - k)] >= n {
+                return (start, (x as usize, y as usize));
+            }
+            k += 2;
+        }
+
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let end = ((n - x) as usize, (m - y) as usize);
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[at(k)] = x;
+            if delta % 2 == 0 && (delta - k).abs() <= d && x + forward[at(delta - k)] >= n {
+                return (((n - x) as usize, (m - y) as usize), end);
+            }
+            k += 2;
+        }
+    }
+    unreachable!("the forward and backward searches always meet")
+}
+
+/// The regions where `a` and `b` differ, in order.
+pub fn hunks<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Hunk> {
+    let mut ret = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (x, y) in self::matches(a, b).into_iter().chain(std::iter::once((a.len(), b.len()))) {
+        if x > i || y > j {
+            ret.push(Hunk { a_start: i, a_end: x, b_start: j, b_end: y });
+        }
+        i = x + 1;
+        j = y + 1;
+    }
+    ret
+}
@@ -28,6 +28,14 @@ pub enum WitErrorType {
     MissingDataError,
     NotADirectoryError,
     DirectoryNotEmptyError,
+    LockError,
+    RefVerificationError,
+    MergeConflictError,
+    DirtyWorktreeError,
+    ConfigError,
+    BareRepositoryError,
+    // A command failing without anything more to say, e.g. a lookup that found nothing.
+    ExitError,
 }
 
 impl Display for WitErrorType {
@@ -40,6 +48,7 @@ impl Display for WitErrorType {
 pub struct WitError {
     error: WitErrorType,
     message: String,
+    code: u8,
 }
 
 impl Error for WitError {}
@@ -48,9 +57,26 @@ impl WitError {
     pub fn new(error_type: WitErrorType, message: String) -> Self {
         WitError {
             error: error_type,
-            message
+            message,
+            code: 1
         }
     }
+
+    /// Exit with `code` instead of 1 when this error ends the program.
+    pub fn with_code(mut self: Box<Self>, code: u8) -> Box<Self> {
+        self.code = code;
+        self
+    }
+
+    /// The process exit status this error ends the program with.
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    /// Whether there is a message to print at all.
+    pub fn is_silent(&self) -> bool {
+        matches!(self.error, ExitError)
+    }
 }
 
 // Conversions for WitError
@@ -144,6 +170,10 @@ pub mod builder {
         ))
     }
 
+    pub fn cli_usage_err(message: String) -> Box<WitError> {
+        Box::new(WitError::new(CLIArgumentError, message))
+    }
+
     pub fn cli_unknown_command_err(command: &str) -> Box<WitError> {
         Box::new(WitError::new(
             CLIUnknownCommandError,
@@ -200,4 +230,32 @@ pub mod builder {
     pub fn dir_not_empty_err(path: &std::path::PathBuf) -> Box<WitError> {
         Box::new(WitError::new(DirectoryNotEmptyError, path.display().to_string()))
     }
-}
\ No newline at end of file
+
+    pub fn lock_err(message: String) -> Box<WitError> {
+        Box::new(WitError::new(LockError, message))
+    }
+
+    pub fn ref_verification_err(message: String) -> Box<WitError> {
+        Box::new(WitError::new(RefVerificationError, message))
+    }
+
+    pub fn merge_conflict_err(message: String) -> Box<WitError> {
+        Box::new(WitError::new(MergeConflictError, message))
+    }
+
+    pub fn dirty_worktree_err(message: String) -> Box<WitError> {
+        Box::new(WitError::new(DirtyWorktreeError, message))
+    }
+
+    pub fn config_err(message: String) -> Box<WitError> {
+        Box::new(WitError::new(ConfigError, message))
+    }
+
+    pub fn exit_err(code: u8) -> Box<WitError> {
+        Box::new(WitError::new(ExitError, String::new())).with_code(code)
+    }
+
+    pub fn bare_repo_err() -> Box<WitError> {
+        Box::new(WitError::new(BareRepositoryError, String::from("this operation must be run in a work tree")))
+    }
+}
@@ -0,0 +1,33 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::{
+    repository::Repository,
+    object,
+    error::{WitError, builder::*}
+};
+
+/// The executable `wit-<name>` found first on `PATH`.
+pub fn find(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(format!("wit-{}", name)))
+        .find(|program| fs::metadata(program).map(|meta| meta.is_file() && object::is_executable(&meta)).unwrap_or(false))
+}
+
+/// Run `wit-<name>` for a subcommand wit does not know, with `GIT_DIR` and `GIT_WORK_TREE`
+/// describing the repository we are in. Returns its exit code.
+pub fn run(name: &str, args: &[String]) -> Result<i32, Box<WitError>> {
+    let program = self::find(name).ok_or(cli_unknown_command_err(name))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    if let Some(repo) = Repository::find(".", false)? {
+        command.env("GIT_DIR", &repo.git_dir);
+        if let Some(top) = &repo.worktree {
+            command.env("GIT_WORK_TREE", top);
+        }
+    }
+    let status = command.status()?;
+    Ok(status.code().unwrap_or(1))
+}
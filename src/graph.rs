@@ -0,0 +1,231 @@
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use crate::{
+    repository::Repository,
+    object::{self, WitObject},
+    reference,
+    reflog,
+    util,
+    error::{WitError, builder::*}
+};
+
+/// Parents of a commit, in order.
+pub fn parents(repo: &Repository, sha: &str) -> Result<Vec<String>, Box<WitError>> {
+    match object::read(repo, sha)? {
+        WitObject::CommitObject(commit) => Ok(
+            commit.kvlm().get("parent").cloned().unwrap_or_default()
+        ),
+        _ => Err(malformed_object_err(format!("Object {} is not a commit", sha)))
+    }
+}
+
+/// The commit `sha` points to, peeling annotated tags. `None` for other objects.
+pub fn commit_of(repo: &Repository, sha: &str) -> Option<String> {
+    let sha = reference::peel(repo, sha).unwrap_or(sha.to_owned());
+    match object::read(repo, &sha) {
+        Ok(WitObject::CommitObject(_)) => Some(sha),
+        _ => None
+    }
+}
+
+/// All commits reachable from `sha`, including itself.
+pub fn ancestors(repo: &Repository, sha: &str) -> Result<HashSet<String>, Box<WitError>> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([sha.to_owned()]);
+    while let Some(current) = queue.pop_front() {
+        if seen.insert(current.clone()) {
+            queue.extend(self::parents(repo, &current)?);
+        }
+    }
+    Ok(seen)
+}
+
+/// Whether `ancestor` is reachable from `descendant`.
+pub fn is_ancestor(repo: &Repository, ancestor: &str, descendant: &str) -> Result<bool, Box<WitError>> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([descendant.to_owned()]);
+    while let Some(current) = queue.pop_front() {
+        if current == ancestor {
+            return Ok(true);
+        }
+        if seen.insert(current.clone()) {
+            queue.extend(self::parents(repo, &current)?);
+        }
+    }
+    Ok(false)
+}
+
+/// Committer timestamp and parents of commits, read once per traversal.
+struct CommitCache<'a> {
+    repo: &'a Repository,
+    commits: HashMap<String, (i64, Vec<String>)>
+}
+
+impl<'a> CommitCache<'a> {
+    fn new(repo: &'a Repository) -> Self {
+        CommitCache { repo, commits: HashMap::new() }
+    }
+
+    fn get(&mut self, sha: &str) -> Result<&(i64, Vec<String>), Box<WitError>> {
+        if !self.commits.contains_key(sha) {
+            let info = match object::read(self.repo, sha)? {
+                WitObject::CommitObject(commit) => {
+                    let kvlm = commit.kvlm();
+                    let time = kvlm.get("committer")
+                        .and_then(|c| c.first())
+                        .map(|c| util::parse_signature(c).2)
+                        .unwrap_or(0);
+                    (time, kvlm.get("parent").cloned().unwrap_or_default())
+                },
+                _ => Err(malformed_object_err(format!("Object {} is not a commit", sha)))?
+            };
+            self.commits.insert(sha.to_owned(), info);
+        }
+        Ok(&self.commits[sha])
+    }
+}
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// Walk down from `one` and `others` newest-first, painting commits with the side
+/// they are reachable from. Commits reachable from both sides are common ancestors;
+/// their own ancestors are marked stale so the walk stops early.
+fn paint_down_to_common(cache: &mut CommitCache, one: &str, others: &[String]) -> Result<Vec<String>, Box<WitError>> {
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    let mut queue: BinaryHeap<(i64, String)> = BinaryHeap::new();
+    let mut result = Vec::new();
+
+    flags.insert(one.to_owned(), PARENT1);
+    queue.push((cache.get(one)?.0, one.to_owned()));
+    for other in others {
+        *flags.entry(other.clone()).or_insert(0) |= PARENT2;
+        queue.push((cache.get(other)?.0, other.clone()));
+    }
+
+    while queue.iter().any(|(_, sha)| flags[sha] & STALE == 0) {
+        let (_, sha) = queue.pop().unwrap();
+        let mut current = flags[&sha] & (PARENT1 | PARENT2 | STALE);
+        if current & (PARENT1 | PARENT2) == PARENT1 | PARENT2 {
+            if flags[&sha] & RESULT == 0 {
+                *flags.get_mut(&sha).unwrap() |= RESULT;
+                result.push(sha.clone());
+            }
+            current |= STALE;
+        }
+        for parent in cache.get(&sha)?.1.clone() {
+            let parent_flags = flags.entry(parent.clone()).or_insert(0);
+            if *parent_flags & current == current {
+                continue;
+            }
+            *parent_flags |= current;
+            queue.push((cache.get(&parent)?.0, parent));
+        }
+    }
+
+    // Results that turned out to be reachable from another result are not the best ones.
+    Ok(result.into_iter().filter(|sha| flags[sha] & STALE == 0).collect())
+}
+
+/// Drop commits that are ancestors of other commits in the list.
+pub fn reduce(repo: &Repository, commits: &[String]) -> Result<Vec<String>, Box<WitError>> {
+    let mut unique: Vec<String> = Vec::new();
+    for commit in commits {
+        if !unique.contains(commit) {
+            unique.push(commit.clone());
+        }
+    }
+
+    let mut ret = Vec::new();
+    for commit in &unique {
+        let mut redundant = false;
+        for other in &unique {
+            if other != commit && self::is_ancestor(repo, commit, other)? {
+                redundant = true;
+                break;
+            }
+        }
+        if !redundant {
+            ret.push(commit.clone());
+        }
+    }
+    Ok(ret)
+}
+
+/// Best common ancestors of `one` and any of `others`, newest first.
+/// Criss-cross histories can have more than one.
+pub fn merge_bases(repo: &Repository, one: &str, others: &[String]) -> Result<Vec<String>, Box<WitError>> {
+    let mut cache = CommitCache::new(repo);
+    if others.iter().any(|other| other == one) {
+        return Ok(vec![one.to_owned()]);
+    }
+    let candidates = self::paint_down_to_common(&mut cache, one, others)?;
+    let mut bases = self::reduce(repo, &candidates)?;
+    bases.sort_by_key(|sha| std::cmp::Reverse(cache.commits.get(sha).map(|c| c.0).unwrap_or(0)));
+    Ok(bases)
+}
+
+/// Common ancestors of all `commits`, for an octopus merge.
+pub fn octopus_bases(repo: &Repository, commits: &[String]) -> Result<Vec<String>, Box<WitError>> {
+    let mut bases = match commits.first() {
+        Some(first) => vec![first.clone()],
+        None => return Ok(Vec::new())
+    };
+    for commit in &commits[1..] {
+        let mut next = Vec::new();
+        for base in &bases {
+            next.extend(self::merge_bases(repo, base, std::slice::from_ref(commit))?);
+        }
+        bases = self::reduce(repo, &next)?;
+    }
+    Ok(bases)
+}
+
+/// Where `commit` forked from the history of `refname`, using every value the ref
+/// has had according to its reflog. Like git, there is only a fork point when there
+/// is a single merge base and it is one of those values.
+pub fn fork_point(repo: &Repository, refname: &str, commit: &str) -> Result<Option<String>, Box<WitError>> {
+    let mut entries = reflog::read(repo, refname)?
+        .into_iter()
+        .map(|entry| entry.new)
+        .filter(|sha| sha != reflog::ZERO_SHA)
+        .collect::<Vec<String>>();
+    entries.push(reference::resolve(repo, refname)?);
+    entries.dedup();
+
+    let bases = self::merge_bases(repo, commit, &entries)?;
+    match bases.as_slice() {
+        [base] if entries.contains(base) => Ok(Some(base.clone())),
+        _ => Ok(None)
+    }
+}
+
+/// Commits reachable from any of `include` but from none of `exclude`,
+/// newest first like `git rev-list`.
+pub fn range(repo: &Repository, include: &[String], exclude: &[String]) -> Result<Vec<String>, Box<WitError>> {
+    let mut hidden = HashSet::new();
+    for sha in exclude {
+        hidden.extend(self::ancestors(repo, sha)?);
+    }
+
+    let mut cache = CommitCache::new(repo);
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+    for sha in include {
+        if !hidden.contains(sha) && seen.insert(sha.clone()) {
+            queue.push((cache.get(sha)?.0, sha.clone()));
+        }
+    }
+
+    let mut ret = Vec::new();
+    while let Some((_, sha)) = queue.pop() {
+        for parent in cache.get(&sha)?.1.clone() {
+            if !hidden.contains(&parent) && seen.insert(parent.clone()) {
+                queue.push((cache.get(&parent)?.0, parent));
+            }
+        }
+        ret.push(sha);
+    }
+    Ok(ret)
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{
+    repository::Repository,
+    util,
+    error::WitError
+};
+
+/// One line of an ignore file.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    // Patterns with a slash other than a trailing one only match relative to `base`.
+    anchored: bool,
+    // Directory of the file the pattern comes from, with a trailing slash unless empty.
+    base: String,
+    pub source: String,
+    pub line: usize,
+    pub text: String
+}
+
+impl Pattern {
+    fn parse(line: &str, base: &str, source: &str, number: usize) -> Option<Pattern> {
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        // Trailing spaces are dropped unless escaped.
+        let mut end = line.len();
+        while end > 0 && line.as_bytes()[end - 1] == b' ' && !line[..end - 1].ends_with('\\') {
+            end -= 1;
+        }
+        let text = &line[..end];
+        let mut pattern = text;
+        let negated = pattern.starts_with('!');
+        if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern = &pattern[1..];
+        }
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.is_empty() {
+            return None;
+        }
+        let anchored = pattern.contains('/');
+        Some(Pattern {
+            pattern: pattern.strip_prefix('/').unwrap_or(pattern).to_owned(),
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_owned(),
+            source: source.to_owned(),
+            line: number,
+            text: text.to_owned()
+        })
+    }
+
+    pub fn negated(&self) -> bool {
+        self.negated
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        self::path_matches(&self.pattern, self.anchored, &self.base, path)
+    }
+}
+
+/// Match `path` against a pattern from a file in directory `base`, gitignore style:
+/// anchored patterns match the path below `base`, others only its last component.
+pub fn path_matches(pattern: &str, anchored: bool, base: &str, path: &str) -> bool {
+    let relative = match path.strip_prefix(base) {
+        Some(relative) => relative,
+        None => return false
+    };
+    if anchored {
+        util::wildmatch(pattern, relative, true)
+    } else {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        util::wildmatch(pattern, name, true)
+    }
+}
+
+/// Ignore rules of a worktree: `.gitignore` files, loaded per directory as they are
+/// needed, then `info/exclude`, then `core.excludesFile`, in order of precedence.
+pub struct Ignore<'a> {
+    repo: &'a Repository,
+    excludes: Vec<Pattern>,
+    dirs: HashMap<String, Vec<Pattern>>
+}
+
+impl<'a> Ignore<'a> {
+    pub fn new(repo: &'a Repository) -> Result<Ignore<'a>, Box<WitError>> {
+        let mut excludes = Vec::new();
+        if let Some(path) = self::excludes_file(repo) {
+            excludes.extend(self::read(&path, "", &path.to_string_lossy())?);
+        }
+        let info = Repository::path(repo, vec!["info", "exclude"]);
+        let top = repo.worktree.as_deref().unwrap_or(&repo.git_dir);
+        let source = info.strip_prefix(top).unwrap_or(&info).to_string_lossy().into_owned();
+        excludes.extend(self::read(&info, "", &source)?);
+        Ok(Ignore { repo, excludes, dirs: HashMap::new() })
+    }
+
+    /// The pattern that decides whether `path` is ignored, which may be a negated one.
+    /// Files inside an ignored directory are ignored by the directory's pattern.
+    pub fn matched(&mut self, path: &str, is_dir: bool) -> Result<Option<Pattern>, Box<WitError>> {
+        let parts = path.split('/').collect::<Vec<&str>>();
+        for i in 1..parts.len() {
+            if let Some(pattern) = self.last_match(&parts[..i].join("/"), true)? {
+                if !pattern.negated {
+                    return Ok(Some(pattern));
+                }
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> Result<bool, Box<WitError>> {
+        Ok(self.matched(path, is_dir)?.map(|pattern| !pattern.negated).unwrap_or(false))
+    }
+
+    /// The last pattern matching `path` in the most specific file that has one.
+    fn last_match(&mut self, path: &str, is_dir: bool) -> Result<Option<Pattern>, Box<WitError>> {
+        let parts = path.split('/').collect::<Vec<&str>>();
+        for i in (0..parts.len()).rev() {
+            let dir = parts[..i].join("/");
+            if let Some(pattern) = self.dir_patterns(&dir)?.iter().rev().find(|pattern| pattern.matches(path, is_dir)) {
+                return Ok(Some(pattern.clone()));
+            }
+        }
+        Ok(self.excludes.iter().rev().find(|pattern| pattern.matches(path, is_dir)).cloned())
+    }
+
+    fn dir_patterns(&mut self, dir: &str) -> Result<&Vec<Pattern>, Box<WitError>> {
+        if !self.dirs.contains_key(dir) {
+            let base = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+            let source = format!("{}.gitignore", base);
+            // A bare repository has no `.gitignore` files.
+            let patterns = match &self.repo.worktree {
+                Some(top) => self::read(&top.join(&source), &base, &source)?,
+                None => Vec::new()
+            };
+            self.dirs.insert(dir.to_owned(), patterns);
+        }
+        Ok(&self.dirs[dir])
+    }
+}
+
+fn read(path: &PathBuf, base: &str, source: &str) -> Result<Vec<Pattern>, Box<WitError>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, base, source, i + 1))
+        .collect())
+}
+
+/// `core.excludesFile`, defaulting to `$XDG_CONFIG_HOME/git/ignore`.
+fn excludes_file(repo: &Repository) -> Option<PathBuf> {
+    match repo.conf.get("core.excludesfile") {
+        Some(path) => Some(util::expand_user(&path)),
+        None => util::xdg_config("ignore")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A repository with the given files in its worktree.
+    fn repo(name: &str, files: &[(&str, &str)]) -> Repository {
+        let repo = Repository::temp(&format!("ignore-{}", name));
+        for (file, text) in files {
+            let file = repo.worktree().unwrap().join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, text).unwrap();
+        }
+        repo
+    }
+
+    #[test]
+    fn negation() {
+        let repo = repo("negation", &[(".gitignore", "*.log\n!keep.log\nlogs/\n!logs/x.txt\n")]);
+        let mut ignore = Ignore::new(&repo).unwrap();
+        assert!(ignore.is_ignored("a.log", false).unwrap());
+        assert!(!ignore.is_ignored("keep.log", false).unwrap());
+        assert!(ignore.matched("keep.log", false).unwrap().unwrap().negated());
+        assert!(!ignore.is_ignored("a.txt", false).unwrap());
+        // Files inside an ignored directory cannot be re-included.
+        assert!(ignore.is_ignored("logs/x.txt", false).unwrap());
+        assert_eq!(ignore.matched("logs/x.txt", false).unwrap().unwrap().text, "logs/");
+    }
+
+    #[test]
+    fn directory_only() {
+        let repo = repo("directory-only", &[(".gitignore", "build/\n")]);
+        let mut ignore = Ignore::new(&repo).unwrap();
+        assert!(ignore.is_ignored("build", true).unwrap());
+        assert!(!ignore.is_ignored("build", false).unwrap());
+        assert!(ignore.is_ignored("src/build", true).unwrap());
+        assert!(ignore.is_ignored("src/build/out.o", false).unwrap());
+    }
+
+    #[test]
+    fn anchored_and_unanchored() {
+        let repo = repo("anchored", &[(".gitignore", "/root.txt\ndoc/*.html\n*.tmp\nlib/**/gen\n")]);
+        let mut ignore = Ignore::new(&repo).unwrap();
+        assert!(ignore.is_ignored("root.txt", false).unwrap());
+        assert!(!ignore.is_ignored("sub/root.txt", false).unwrap());
+        assert!(ignore.is_ignored("doc/a.html", false).unwrap());
+        assert!(!ignore.is_ignored("sub/doc/a.html", false).unwrap());
+        assert!(!ignore.is_ignored("doc/sub/a.html", false).unwrap());
+        assert!(ignore.is_ignored("x.tmp", false).unwrap());
+        assert!(ignore.is_ignored("a/b/x.tmp", false).unwrap());
+        assert!(ignore.is_ignored("lib/gen", false).unwrap());
+        assert!(ignore.is_ignored("lib/a/b/gen", false).unwrap());
+        assert!(!ignore.is_ignored("src/lib/gen", false).unwrap());
+    }
+
+    #[test]
+    fn nested_files() {
+        let repo = repo("nested", &[
+            (".gitignore", "*.tmp\n*.o\n"),
+            ("sub/.gitignore", "!keep.tmp\n/only-here\n*.o\n!*.o\n"),
+            (".git/info/exclude", "!x.tmp\nexcluded\n")
+        ]);
+        let mut ignore = Ignore::new(&repo).unwrap();
+        // The deeper file takes precedence for paths below it.
+        assert!(!ignore.is_ignored("sub/keep.tmp", false).unwrap());
+        assert!(ignore.is_ignored("keep.tmp", false).unwrap());
+        assert!(ignore.is_ignored("sub/other.tmp", false).unwrap());
+        assert!(!ignore.is_ignored("sub/a.o", false).unwrap());
+        assert!(ignore.is_ignored("a.o", false).unwrap());
+        // Patterns are relative to the directory of their file.
+        assert!(ignore.is_ignored("sub/only-here", false).unwrap());
+        assert!(!ignore.is_ignored("only-here", false).unwrap());
+        // `.gitignore` files win over info/exclude.
+        assert!(ignore.is_ignored("x.tmp", false).unwrap());
+        assert!(ignore.is_ignored("sub/excluded", false).unwrap());
+        assert_eq!(ignore.matched("sub/other.tmp", false).unwrap().unwrap().source, ".gitignore");
+    }
+}
@@ -1,66 +1,177 @@
-use std::path::PathBuf;
+use std::fs;
+use std::path::Path;
 
-use crate::error::WitError;
-use crate::object::Find;
-use crate::util::hex;
+use crypto::{
+    sha1::Sha1,
+    digest::Digest
+};
 
+use crate::{
+    repository::Repository,
+    tree::{self, Entries},
+    util::{hex, unhex},
+    error::{WitError, builder::*}
+};
 
-pub struct Index {
-    header: [u8; 12],
-    signature: [u8; 4],
-    version: String,
-    nindex: u32,
+const SIGNATURE: &[u8; 4] = b"DIRC";
 
+pub struct Index {
     entries: Vec<IndexEntry>,
 }
 
 impl Index {
-    pub fn open(path: &PathBuf) -> Result<Index, Box<WitError>> {
-        let raw = std::fs::read(path)?;
+    pub fn new() -> Index {
+        Index { entries: Vec::new() }
+    }
+
+    pub fn open(path: &Path) -> Result<Index, Box<WitError>> {
+        let raw = fs::read(path)?;
         Self::from(raw)
     }
 
+    /// The repository's index, or an empty one when there is none yet.
+    pub fn read(repo: &Repository) -> Result<Index, Box<WitError>> {
+        let path = Repository::path(repo, vec!["index"]);
+        if path.is_file() {
+            Self::open(&path)
+        } else {
+            Ok(Self::new())
+        }
+    }
+
     pub fn from(raw: Vec<u8>) -> Result<Index, Box<WitError>> {
+        if raw.len() < 32 || &raw[..4] != SIGNATURE {
+            Err(malformed_object_err(String::from("Index file has a bad signature")))?
+        }
         let header: [u8; 12] = raw[..12].try_into()?;
+        let version = u32::from_be_bytes(header[4..8].try_into()?);
+        if version != 2 && version != 3 {
+            Err(malformed_object_err(format!("Unsupported index version {}", version)))?
+        }
         let nindex = u32::from_be_bytes(header[8..12].try_into()?);
-        
-        let content = raw[12..].to_vec();
+
         let mut entries = Vec::<IndexEntry>::new();
-        let mut curs: usize = 0;
-        for i in 0..nindex {
-            let null_idx = content.find_exact(b'\x00', curs+62);
-            entries.push(
-                IndexEntry::from(
-                    content[curs..null_idx].to_vec(),
-                )?
-            );
-            curs = null_idx + 1;
+        let mut curs: usize = 12;
+        for _ in 0..nindex {
+            let (entry, len) = IndexEntry::from(&raw[curs..])?;
+            entries.push(entry);
+            curs += len;
         }
+        // Extensions such as the cached tree are skipped; they are rebuilt by git when missing.
 
-        Ok(Index {
-            header,
-            signature: header[..4].try_into()?,
-            version: hex(&header[4..8].to_owned()),
-            nindex,
-            entries,
-        })
+        Ok(Index { entries })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+        ret.extend(SIGNATURE);
+        ret.extend(2u32.to_be_bytes());
+        ret.extend((self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            ret.extend(entry.serialize());
+        }
+        let mut sha = Sha1::new();
+        sha.input(&ret);
+        let mut digest = [0u8; 20];
+        sha.result(&mut digest);
+        ret.extend(digest);
+        ret
+    }
+
+    pub fn write(&self, repo: &Repository) -> Result<(), Box<WitError>> {
+        repo.lock(&Repository::path(repo, vec!["index"]))?.commit(&self.serialize())
+    }
+
+    /// An index holding exactly the contents of `tree`, without stat data.
+    pub fn from_tree(repo: &Repository, tree: &str) -> Result<Index, Box<WitError>> {
+        let mut index = Self::new();
+        for (path, (mode, sha)) in tree::flatten(repo, tree)? {
+            index.entries.push(IndexEntry::new(&path, &mode, &sha, 0)?);
+        }
+        index.sort();
+        Ok(index)
+    }
+
+    pub fn entries(&self) -> &Vec<IndexEntry> {
+        &self.entries
+    }
+
+    pub fn entries_mut(&mut self) -> &mut Vec<IndexEntry> {
+        &mut self.entries
+    }
+
+    pub fn entry(&self, path: &str, stage: u16) -> Option<&IndexEntry> {
+        self.entries.iter().find(|entry| entry.file_path == path && entry.stage() == stage)
+    }
+
+    /// Add or replace an entry. A resolved (stage 0) entry replaces every stage of its path.
+    pub fn add(&mut self, entry: IndexEntry) {
+        let stage = entry.stage();
+        self.entries.retain(|other| {
+            other.file_path != entry.file_path || (stage != 0 && other.stage() != stage && other.stage() != 0)
+        });
+        self.entries.push(entry);
+        self.sort();
+    }
+
+    /// Drop every stage of `path`.
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|entry| entry.file_path != path);
+    }
+
+    /// Paths with unmerged entries, sorted and without duplicates.
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for entry in self.entries.iter().filter(|entry| entry.stage() != 0) {
+            if ret.last() != Some(&entry.file_path) {
+                ret.push(entry.file_path.clone());
+            }
+        }
+        ret
+    }
+
+    /// Stage 0 entries flattened the way trees are.
+    pub fn tree_entries(&self) -> Entries {
+        self.entries
+            .iter()
+            .filter(|entry| entry.stage() == 0)
+            .map(|entry| (entry.file_path.clone(), (entry.mode_str(), entry.hash.clone())))
+            .collect()
+    }
+
+    /// Write the trees recorded in the index. Fails while there are conflicts.
+    pub fn write_tree(&self, repo: &Repository) -> Result<String, Box<WitError>> {
+        let conflicts = self.conflicts();
+        if !conflicts.is_empty() {
+            Err(merge_conflict_err(format!(
+                "Cannot write a tree with unmerged paths:\n{}",
+                conflicts.iter().map(|path| format!("\t{}", path)).collect::<Vec<String>>().join("\n")
+            )))?
+        }
+        tree::write(repo, &self.tree_entries())
+    }
+
+    fn sort(&mut self) {
+        self.entries.sort_by(|a, b| {
+            a.file_path.as_bytes().cmp(b.file_path.as_bytes()).then(a.stage().cmp(&b.stage()))
+        });
     }
 }
 
 pub struct IndexEntry {
     // The last time a file's metadata changed.
     // (seconds, nanoseconds)
-    ctime: (u32, u32),
+    pub ctime: (u32, u32),
 
-    // The last time a file's metadata changed.
+    // The last time a file's data changed.
     // (seconds, nanoseconds)
-    mtime: (u32, u32),
+    pub mtime: (u32, u32),
 
     //The ID of device containing the file.
-    dev: u32,
+    pub dev: u32,
 
     //The inode number of the file.
-    ino: u32,
+    pub ino: u32,
 
     // The object type of the file.
     // First 4 bits: (b1000 = regular file, b1010 = symlink, b1110 = gitlink)
@@ -68,36 +179,64 @@ pub struct IndexEntry {
     // 9 bits: The object permissions, an integer
     // Only 0755 and 0644 are valid for regular files.
     // Symbolic links and gitlinks have value 0 in this field.
-    mode: u32,
+    pub mode: u32,
 
     // The User ID of owner
-    uid: u32,
+    pub uid: u32,
 
     // The Group ID of owner
-    gid: u32,
+    pub gid: u32,
 
     // The file size, in bytes
-    size: u32,
+    pub size: u32,
 
     // The object's hash as a hex string
-    hash: String,
+    pub hash: String,
 
     // 4 bits for flags
     //flag_assume_valid: bool, // 1 bit
     //flag_extended: bool, // 1 bit
     //flag_stage: (bool, bool), // 2 bits
 
-    // Length of name if < 0xFFF, -1 otherwise
+    // Length of name if < 0xFFF, 0xFFF otherwise
     //flag_name_length: bool, // 12 bits
-    flags: u16, // TODO: Bit manipulation lib for u2 and u12
+    pub flags: u16,
 
     // The name of the file
-    file_path: String
+    pub file_path: String
 }
 
 impl IndexEntry {
-    pub fn from(raw: Vec<u8>) -> Result<Self, Box<WitError>> {
+    /// An entry without stat data, which makes git rehash the file on its next look.
+    pub fn new(path: &str, mode: &str, hash: &str, stage: u16) -> Result<Self, Box<WitError>> {
         Ok(Self {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode: u32::from_str_radix(mode, 8)?,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash: hash.to_owned(),
+            flags: (stage << 12) | (path.len().min(0xFFF) as u16),
+            file_path: path.to_owned(),
+        })
+    }
+
+    /// Parse one entry, returning it with the number of bytes it used.
+    pub fn from(raw: &[u8]) -> Result<(Self, usize), Box<WitError>> {
+        if raw.len() < 62 {
+            Err(malformed_object_err(String::from("Truncated index entry")))?
+        }
+        let flags = u16::from_be_bytes(raw[60..62].try_into()?);
+        // Version 3 entries with the extended bit carry two more flag bytes.
+        let name_start = if flags & 0x4000 != 0 { 64 } else { 62 };
+        let name_end = raw[name_start..].iter().position(|b| *b == 0).ok_or(
+            malformed_object_err(String::from("Unterminated index entry path"))
+        )? + name_start;
+
+        let entry = Self {
             ctime: (
                 u32::from_be_bytes(raw[0..4].try_into()?),
                 u32::from_be_bytes(raw[4..8].try_into()?),
@@ -113,8 +252,89 @@ impl IndexEntry {
             gid: u32::from_be_bytes(raw[32..36].try_into()?),
             size: u32::from_be_bytes(raw[36..40].try_into()?),
             hash: hex(&raw[40..60].to_vec()),
-            flags: u16::from_be_bytes(raw[60..62].try_into()?),
-            file_path: String::from_utf8(raw[62..].to_vec())?,
-        })
+            flags: flags & !0x4000,
+            file_path: String::from_utf8(raw[name_start..name_end].to_vec())?,
+        };
+        // Entries are NUL padded to a multiple of eight bytes.
+        Ok((entry, (name_end + 8) / 8 * 8))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+        for value in [
+            self.ctime.0, self.ctime.1, self.mtime.0, self.mtime.1,
+            self.dev, self.ino, self.mode, self.uid, self.gid, self.size
+        ] {
+            ret.extend(value.to_be_bytes());
+        }
+        ret.extend(unhex(&self.hash).unwrap_or(vec![0; 20]));
+        ret.extend(self.flags.to_be_bytes());
+        ret.extend(self.file_path.as_bytes());
+        let padded = (ret.len() + 8) / 8 * 8;
+        ret.resize(padded, 0);
+        ret
     }
-}
\ No newline at end of file
+
+    pub fn stage(&self) -> u16 {
+        (self.flags >> 12) & 0x3
+    }
+
+    /// Mode as written in trees, e.g. `100644`.
+    pub fn mode_str(&self) -> String {
+        format!("{:o}", self.mode)
+    }
+
+    /// Record the stat data of the worktree file so unchanged files need not be rehashed.
+    pub fn refresh(&mut self, path: &Path) -> Result<(), Box<WitError>> {
+        let meta = fs::symlink_metadata(path)?;
+        self.fill_stat(&meta);
+        Ok(())
+    }
+
+    /// Whether the stat data recorded for the entry still describes `path`.
+    pub fn stat_matches(&self, path: &Path) -> bool {
+        match fs::symlink_metadata(path) {
+            Ok(meta) => {
+                let mut other = Self::new(&self.file_path, "0", &self.hash, 0).unwrap();
+                other.fill_stat(&meta);
+                self.mtime != (0, 0)
+                    && (self.ctime, self.mtime, self.ino, self.size) == (other.ctime, other.mtime, other.ino, other.size)
+            },
+            Err(_) => false
+        }
+    }
+
+    /// Take over the stat data of `other`, which describes the same file.
+    pub fn copy_stat(&mut self, other: &IndexEntry) {
+        self.ctime = other.ctime;
+        self.mtime = other.mtime;
+        self.dev = other.dev;
+        self.ino = other.ino;
+        self.uid = other.uid;
+        self.gid = other.gid;
+        self.size = other.size;
+    }
+
+    #[cfg(unix)]
+    fn fill_stat(&mut self, meta: &fs::Metadata) {
+        use std::os::unix::fs::MetadataExt;
+        self.ctime = (meta.ctime() as u32, meta.ctime_nsec() as u32);
+        self.mtime = (meta.mtime() as u32, meta.mtime_nsec() as u32);
+        self.dev = meta.dev() as u32;
+        self.ino = meta.ino() as u32;
+        self.uid = meta.uid();
+        self.gid = meta.gid();
+        self.size = meta.size() as u32;
+    }
+
+    #[cfg(not(unix))]
+    fn fill_stat(&mut self, meta: &fs::Metadata) {
+        let since_epoch = |time: std::io::Result<std::time::SystemTime>| time.ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| (d.as_secs() as u32, d.subsec_nanos()))
+            .unwrap_or((0, 0));
+        self.mtime = since_epoch(meta.modified());
+        self.ctime = self.mtime;
+        self.size = meta.len() as u32;
+    }
+}
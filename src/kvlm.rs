@@ -30,7 +30,7 @@ impl KVLMExt for KVLM {
             if spc < 0 || nl < spc {
                 self.insert(
                     "".to_owned(),
-                    match from_utf8(&raw[start..]) {
+                    match from_utf8(&raw[(start + 1).min(raw.len())..]) {
                         Ok(string) => vec![string.to_owned()],
                         Err(_) => panic!("Error converting {:?} to utf8", &raw[start..])
                     }
@@ -82,7 +82,7 @@ impl KVLMExt for KVLM {
         }
 
         ret += "\n";
-        for entry in &self["\n"] {
+        for entry in self.get("").unwrap_or(&Vec::new()) {
             ret += entry.as_str();
         }
         ret
@@ -0,0 +1,63 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use crate::{
+    repository::Shared,
+    error::{WitError, builder::lock_err}
+};
+
+/// Exclusive `<path>.lock` file. Data is written to the lock and renamed
+/// over `path` on commit; a lock that is dropped uncommitted is removed.
+pub struct LockFile {
+    path: PathBuf,
+    lock: PathBuf,
+    file: Option<File>,
+    shared: Shared
+}
+
+impl LockFile {
+    pub fn acquire(path: &Path) -> Result<LockFile, Box<WitError>> {
+        let lock = PathBuf::from(format!("{}.lock", path.display()));
+        if let Some(parent) = lock.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(lock_err(format!(
+                "Unable to create '{}': File exists. Another wit process seems to be running in this repository.",
+                lock.display()
+            )))?,
+            Err(e) => Err(lock_err(format!("Unable to create '{}': {}", lock.display(), e)))?
+        };
+        Ok(LockFile { path: path.to_path_buf(), lock, file: Some(file), shared: Shared::Umask })
+    }
+
+    /// Widen the permissions of the committed file the way `shared` asks.
+    pub fn shared(mut self, shared: Shared) -> LockFile {
+        self.shared = shared;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `data` and atomically rename the lock into place.
+    pub fn commit(mut self, data: &[u8]) -> Result<(), Box<WitError>> {
+        let file = self.file.as_mut().unwrap();
+        file.write_all(data)?;
+        file.sync_all()?;
+        self.shared.apply(&self.lock)?;
+        fs::rename(&self.lock, &self.path)?;
+        self.file = None;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock);
+        }
+    }
+}
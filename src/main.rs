@@ -2,16 +2,35 @@ use std::process::ExitCode;
 use cli::CliExecute;
 
 mod repository;
+mod config;
 mod object;
 mod blob;
 mod commit;
 mod tree;
+mod index;
+mod worktree;
+mod ignore;
+mod attr;
+mod convert;
+mod diff;
+mod merge;
+mod sequencer;
+mod rebase;
+mod stash;
+mod clone;
+mod remote;
+mod refspec;
 mod reference;
+mod reflog;
+mod lockfile;
+mod ref_filter;
+mod graph;
 mod tag;
-mod index;
 mod kvlm;
 mod error;
 mod util;
+mod alias;
+mod external;
 mod cli;
 
 pub fn main() -> ExitCode {
@@ -19,8 +38,10 @@ pub fn main() -> ExitCode {
     match app.execute() {
         Ok(_) => ExitCode::SUCCESS,
         Err(e) => {
-            println!("{}", e);
-            ExitCode::FAILURE
+            if !e.is_silent() {
+                println!("{}", e);
+            }
+            ExitCode::from(e.code())
         }
     }
 }
\ No newline at end of file
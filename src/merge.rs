@@ -0,0 +1,534 @@
+use std::collections::BTreeSet;
+use std::fs;
+
+use crate::{
+    repository::Repository,
+    object::{self, WitObject},
+    blob::Blob,
+    commit,
+    diff::{self, Hunk},
+    graph,
+    reference,
+    index::{Index, IndexEntry},
+    tree::{self, Entries},
+    worktree,
+    util,
+    error::{WitError, builder::*}
+};
+
+/// How conflicting hunks are written to the worktree, from `merge.conflictStyle`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictStyle {
+    Merge,
+    Diff3
+}
+
+impl ConflictStyle {
+    pub fn parse(style: &str) -> Result<ConflictStyle, Box<WitError>> {
+        match style {
+            "merge" => Ok(ConflictStyle::Merge),
+            "diff3" => Ok(ConflictStyle::Diff3),
+            other => Err(cli_argument_err(&format!("conflict style merge or diff3 (got '{}')", other)))
+        }
+    }
+
+    pub fn configured(repo: &Repository) -> Result<ConflictStyle, Box<WitError>> {
+        Self::parse(repo.conf.get("merge.conflictstyle").as_deref().unwrap_or("merge"))
+    }
+}
+
+/// Names shown on the conflict markers of each side.
+pub struct Labels<'a> {
+    pub base: &'a str,
+    pub ours: &'a str,
+    pub theirs: &'a str
+}
+
+pub struct FileMerge {
+    pub data: Vec<u8>,
+    pub clean: bool
+}
+
+/// Three-way merge of file contents, line by line. Changes that overlap or touch
+/// each other conflict unless both sides made the same change.
+pub fn merge_file(base: &[u8], ours: &[u8], theirs: &[u8], labels: &Labels, style: ConflictStyle) -> FileMerge {
+    if ours == theirs || base == theirs {
+        return FileMerge { data: ours.to_vec(), clean: true };
+    }
+    if base == ours {
+        return FileMerge { data: theirs.to_vec(), clean: true };
+    }
+    // Binary files cannot be merged line by line; keep our side.
+    if [base, ours, theirs].iter().any(|data| data.contains(&0)) {
+        return FileMerge { data: ours.to_vec(), clean: false };
+    }
+
+    let (base, ours, theirs) = (diff::lines(base), diff::lines(ours), diff::lines(theirs));
+    let mut changes = diff::hunks(&base, &ours).into_iter().map(|hunk| (hunk, true))
+        .chain(diff::hunks(&base, &theirs).into_iter().map(|hunk| (hunk, false)))
+        .collect::<Vec<(Hunk, bool)>>();
+    changes.sort_by_key(|(hunk, ours)| (hunk.a_start, hunk.a_end, !ours));
+
+    let mut data = Vec::new();
+    let mut clean = true;
+    let mut pos = 0;
+    let mut i = 0;
+    while i < changes.len() {
+        // Collect every change that overlaps or touches the region so far.
+        let (lo, mut hi) = (changes[i].0.a_start, changes[i].0.a_end);
+        let mut j = i + 1;
+        while j < changes.len() && changes[j].0.a_start <= hi {
+            hi = hi.max(changes[j].0.a_end);
+            j += 1;
+        }
+        let group = &changes[i..j];
+        i = j;
+
+        data.extend(base[pos..lo].concat());
+        pos = hi;
+
+        let side = |is_ours: bool, lines: &[&[u8]]| -> Vec<u8> {
+            let hunks = group.iter().filter(|(_, o)| *o == is_ours).map(|(h, _)| h).collect::<Vec<&Hunk>>();
+            match (hunks.first(), hunks.last()) {
+                (Some(first), Some(last)) => {
+                    let start = first.b_start - (first.a_start - lo);
+                    let end = last.b_end + (hi - last.a_end);
+                    lines[start..end].concat()
+                },
+                _ => base[lo..hi].concat()
+            }
+        };
+        let (mine, yours) = (side(true, &ours), side(false, &theirs));
+        let original = base[lo..hi].concat();
+
+        if mine == yours || original == yours {
+            data.extend(mine);
+        } else if original == mine {
+            data.extend(yours);
+        } else {
+            clean = false;
+            self::write_conflict(&mut data, &original, &mine, &yours, labels, style);
+        }
+    }
+    data.extend(base[pos..].concat());
+
+    FileMerge { data, clean }
+}
+
+fn write_conflict(out: &mut Vec<u8>, base: &[u8], ours: &[u8], theirs: &[u8], labels: &Labels, style: ConflictStyle) {
+    let (ours_lines, theirs_lines) = (diff::lines(ours), diff::lines(theirs));
+    // The plain style moves lines both sides agree on out of the conflict.
+    let (prefix, suffix) = match style {
+        ConflictStyle::Merge => {
+            let prefix = ours_lines.iter().zip(theirs_lines.iter()).take_while(|(a, b)| a == b).count();
+            let suffix = ours_lines[prefix..].iter().rev()
+                .zip(theirs_lines[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            (prefix, suffix)
+        },
+        ConflictStyle::Diff3 => (0, 0)
+    };
+
+    let section = |out: &mut Vec<u8>, marker: &str, label: &str, lines: &[&[u8]]| {
+        out.extend(if label.is_empty() { format!("{}\n", marker) } else { format!("{} {}\n", marker, label) }.as_bytes());
+        let text = lines.concat();
+        let terminated = text.is_empty() || text.ends_with(b"\n");
+        out.extend(text);
+        if !terminated {
+            out.push(b'\n');
+        }
+    };
+
+    out.extend(ours_lines[..prefix].concat());
+    section(out, "<<<<<<<", labels.ours, &ours_lines[prefix..ours_lines.len() - suffix]);
+    if style == ConflictStyle::Diff3 {
+        section(out, "|||||||", labels.base, &diff::lines(base));
+    }
+    section(out, "=======", "", &theirs_lines[prefix..theirs_lines.len() - suffix]);
+    out.extend(format!(">>>>>>> {}\n", labels.theirs).as_bytes());
+    out.extend(ours_lines[ours_lines.len() - suffix..].concat());
+}
+
+/// Outcome of merging trees: the index to record, what the worktree should hold
+/// (conflicted files with markers) and the messages to show the user.
+pub struct TreeMerge {
+    pub index: Index,
+    pub worktree: Entries,
+    pub messages: Vec<String>,
+    pub conflicts: Vec<String>
+}
+
+impl TreeMerge {
+    pub fn clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+fn blob_data(repo: &Repository, sha: &str) -> Result<Vec<u8>, Box<WitError>> {
+    match object::read(repo, sha)? {
+        WitObject::BlobObject(blob) => Ok(blob.data().clone()),
+        _ => Err(malformed_object_err(format!("Object {} is not a blob", sha)))
+    }
+}
+
+fn is_file(mode: &str) -> bool {
+    mode == "100644" || mode == "100755"
+}
+
+/// Merge `ours` and `theirs` against `base`, path by path.
+pub fn merge_trees(
+    repo: &Repository,
+    base: &Entries,
+    ours: &Entries,
+    theirs: &Entries,
+    labels: &Labels,
+    style: ConflictStyle
+) -> Result<TreeMerge, Box<WitError>> {
+    let mut result = TreeMerge {
+        index: Index::new(),
+        worktree: Entries::new(),
+        messages: Vec::new(),
+        conflicts: Vec::new()
+    };
+    let paths = base.keys().chain(ours.keys()).chain(theirs.keys()).collect::<BTreeSet<&String>>();
+
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        let resolved = if o == t || b == t {
+            Some(o)
+        } else if b == o {
+            Some(t)
+        } else {
+            None
+        };
+        if let Some(entry) = resolved {
+            if let Some((mode, sha)) = entry {
+                result.index.add(IndexEntry::new(path, mode, sha, 0)?);
+                result.worktree.insert(path.clone(), (mode.clone(), sha.clone()));
+            }
+            continue;
+        }
+
+        let conflict = |result: &mut TreeMerge| -> Result<(), Box<WitError>> {
+            for (stage, entry) in [(1, b), (2, o), (3, t)] {
+                if let Some((mode, sha)) = entry {
+                    result.index.add(IndexEntry::new(path, mode, sha, stage)?);
+                }
+            }
+            result.conflicts.push(path.clone());
+            Ok(())
+        };
+
+        match (o, t) {
+            (Some((our_mode, our_sha)), Some((their_mode, their_sha))) => {
+                let base_mode = b.map(|(mode, _)| mode.as_str());
+                if !is_file(our_mode) || !is_file(their_mode) || base_mode.map(|mode| !is_file(mode)).unwrap_or(false) {
+                    conflict(&mut result)?;
+                    result.worktree.insert(path.clone(), (our_mode.clone(), our_sha.clone()));
+                    result.messages.push(format!("CONFLICT (content): Merge conflict in {}", path));
+                    continue;
+                }
+
+                result.messages.push(format!("Auto-merging {}", path));
+                let mode = if our_mode == their_mode || base_mode == Some(their_mode) {
+                    our_mode
+                } else {
+                    their_mode
+                };
+                let base_data = match b {
+                    Some((_, sha)) => self::blob_data(repo, sha)?,
+                    None => Vec::new()
+                };
+                let merged = self::merge_file(
+                    &base_data,
+                    &self::blob_data(repo, our_sha)?,
+                    &self::blob_data(repo, their_sha)?,
+                    labels,
+                    style
+                );
+                let sha = object::write(WitObject::BlobObject(Blob::new(Some(repo), merged.data)), true)?;
+                result.worktree.insert(path.clone(), (mode.clone(), sha.clone()));
+                if merged.clean {
+                    result.index.add(IndexEntry::new(path, mode, &sha, 0)?);
+                } else {
+                    conflict(&mut result)?;
+                    result.messages.push(format!(
+                        "CONFLICT ({}): Merge conflict in {}", if b.is_some() { "content" } else { "add/add" }, path
+                    ));
+                }
+            },
+            (Some(kept), None) | (None, Some(kept)) => {
+                let (deleted_in, modified_in) = if o.is_some() {
+                    (labels.theirs, labels.ours)
+                } else {
+                    (labels.ours, labels.theirs)
+                };
+                conflict(&mut result)?;
+                result.worktree.insert(path.clone(), kept.clone());
+                result.messages.push(format!(
+                    "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} of {} left in tree.",
+                    path, deleted_in, modified_in, modified_in, path
+                ));
+            },
+            (None, None) => ()
+        }
+    }
+    self::move_files_aside(&mut result, ours, labels)?;
+    Ok(result)
+}
+
+/// Move each merged file that has a directory of the same name next to it to
+/// `<path>~<label>` of the side it came from, and record a file/directory conflict.
+fn move_files_aside(result: &mut TreeMerge, ours: &Entries, labels: &Labels) -> Result<(), Box<WitError>> {
+    let paths = result.worktree.keys().cloned()
+        .chain(result.index.entries().iter().map(|entry| entry.file_path.clone()))
+        .collect::<BTreeSet<String>>();
+    let in_the_way = paths.iter().filter(|path| {
+        let dir = format!("{}/", path);
+        paths.range(dir.clone()..).next().map(|next| next.starts_with(&dir)).unwrap_or(false)
+    });
+
+    for path in in_the_way {
+        // The other side has the directory, so only one side can have the file.
+        let (side, stage) = if ours.contains_key(path) { (labels.ours, 2) } else { (labels.theirs, 3) };
+        let label = side.replace('/', "_");
+        let mut moved = format!("{}~{}", path, label);
+        for n in 0.. {
+            if !paths.contains(&moved) {
+                break;
+            }
+            moved = format!("{}~{}_{}", path, label, n);
+        }
+
+        let entries = result.index.entries().iter()
+            .filter(|entry| &entry.file_path == path)
+            .map(|entry| (entry.mode_str(), entry.hash.clone(), entry.stage()))
+            .collect::<Vec<(String, String, u16)>>();
+        result.index.remove(path);
+        for (mode, sha, old_stage) in entries {
+            result.index.add(IndexEntry::new(&moved, &mode, &sha, if old_stage == 0 { stage } else { old_stage })?);
+        }
+        if let Some(entry) = result.worktree.remove(path) {
+            result.worktree.insert(moved.clone(), entry);
+        }
+        result.conflicts.retain(|conflict| conflict != path);
+        result.conflicts.push(moved.clone());
+        result.messages.push(format!(
+            "CONFLICT (file/directory): directory in the way of {} from {}; moving it to {} instead.",
+            path, side, moved
+        ));
+    }
+    Ok(())
+}
+
+/// Merge commits `ours` and `theirs`. Several merge bases are first merged
+/// into a single virtual base, recursively, like git's recursive strategy.
+pub fn merge_commits(
+    repo: &Repository,
+    ours: &str,
+    theirs: &str,
+    labels: &Labels,
+    style: ConflictStyle
+) -> Result<TreeMerge, Box<WitError>> {
+    let bases = graph::merge_bases(repo, ours, &[theirs.to_owned()])?;
+    let base = match bases.split_last() {
+        None => Entries::new(),
+        Some((first, [])) => tree::flatten(repo, &commit::tree_of(repo, first)?)?,
+        Some((oldest, rest)) => {
+            let mut virtual_commit = oldest.clone();
+            for other in rest.iter().rev() {
+                let inner = self::merge_commits(repo, &virtual_commit, other, &Labels {
+                    base: "merged common ancestors",
+                    ours: "Temporary merge branch 1",
+                    theirs: "Temporary merge branch 2"
+                }, ConflictStyle::Merge)?;
+                let tree = tree::write(repo, &inner.worktree)?;
+                virtual_commit = commit::create(repo, &tree, &[virtual_commit, other.clone()], "merged common ancestors", None)?;
+            }
+            tree::flatten(repo, &commit::tree_of(repo, &virtual_commit)?)?
+        }
+    };
+    let base_label = match bases.as_slice() {
+        [one] => util::abbrev(one).to_owned(),
+        _ => String::from("merged common ancestors")
+    };
+
+    self::merge_trees(
+        repo,
+        &base,
+        &tree::flatten(repo, &commit::tree_of(repo, ours)?)?,
+        &tree::flatten(repo, &commit::tree_of(repo, theirs)?)?,
+        &Labels { base: &base_label, ..*labels },
+        style
+    )
+}
+
+/// Apply the change from `base` to `theirs` on top of the index and worktree,
+/// the way cherry-picks and rebases replay commits. The index is written even
+/// when there are conflicts.
+pub fn apply(
+    repo: &Repository,
+    base: &Entries,
+    theirs: &Entries,
+    labels: &Labels,
+    operation: &str
+) -> Result<TreeMerge, Box<WitError>> {
+    let current = Index::read(repo)?;
+    let mut result = self::merge_trees(
+        repo, base, &current.tree_entries(), theirs, labels, ConflictStyle::configured(repo)?
+    )?;
+    worktree::switch(repo, &current, &result.worktree, &mut result.index, false, operation)?;
+    result.index.write(repo)?;
+    for message in &result.messages {
+        println!("{}", message);
+    }
+    Ok(result)
+}
+
+/// Commits being merged and the prepared message of an unfinished merge,
+/// from `MERGE_HEAD` and `MERGE_MSG`.
+pub fn state(repo: &Repository) -> Result<Option<(Vec<String>, String)>, Box<WitError>> {
+    let head = Repository::path(repo, vec!["MERGE_HEAD"]);
+    if !head.is_file() {
+        return Ok(None);
+    }
+    let heads = fs::read_to_string(head)?.lines().map(str::to_owned).collect();
+    let msg = Repository::path(repo, vec!["MERGE_MSG"]);
+    let message = if msg.is_file() { fs::read_to_string(msg)? } else { String::new() };
+    Ok(Some((heads, message)))
+}
+
+pub fn write_state(repo: &Repository, heads: &[String], message: &str, no_ff: bool) -> Result<(), Box<WitError>> {
+    fs::write(Repository::path(repo, vec!["MERGE_HEAD"]), heads.iter().map(|head| format!("{}\n", head)).collect::<String>())?;
+    fs::write(Repository::path(repo, vec!["MERGE_MSG"]), message)?;
+    fs::write(Repository::path(repo, vec!["MERGE_MODE"]), if no_ff { "no-ff" } else { "" })?;
+    Ok(())
+}
+
+pub fn clear_state(repo: &Repository) -> Result<(), Box<WitError>> {
+    for name in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"] {
+        let path = Repository::path(repo, vec![name]);
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// A commit message without comment lines, trailing whitespace, and leading, trailing
+/// or repeated blank lines.
+pub fn clean_message(message: &str) -> String {
+    let mut ret = String::new();
+    let mut blank = false;
+    for line in message.lines().filter(|line| !line.starts_with('#')).map(str::trim_end) {
+        if line.is_empty() {
+            blank = !ret.is_empty();
+            continue;
+        }
+        if blank {
+            ret.push('\n');
+            blank = false;
+        }
+        ret.push_str(line);
+        ret.push('\n');
+    }
+    ret
+}
+
+/// The message git would give a merge of `name` into the current branch,
+/// e.g. `Merge branch 'topic' into dev`.
+pub fn default_message(repo: &Repository, name: &str) -> Result<String, Box<WitError>> {
+    let what = match reference::dwim(repo, name)? {
+        Some(full) if full.starts_with("refs/heads/") => format!("branch '{}'", reference::shorten(&full)),
+        Some(full) if full.starts_with("refs/remotes/") => format!("remote-tracking branch '{}'", reference::shorten(&full)),
+        Some(full) if full.starts_with("refs/tags/") => format!("tag '{}'", reference::shorten(&full)),
+        _ => format!("commit '{}'", name)
+    };
+    // Like git's default merge.suppressDest, merges into main or master leave the branch out.
+    let into = match reference::read_symbolic(repo, "HEAD")? {
+        Some(branch) if branch != "refs/heads/main" && branch != "refs/heads/master" => {
+            format!(" into {}", reference::shorten(&branch))
+        },
+        _ => String::new()
+    };
+    Ok(format!("Merge {}{}\n", what, into))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: Labels = Labels { base: "base", ours: "ours", theirs: "theirs" };
+    const BASE: &[u8] = b"a\nb\nc\nd\ne\n";
+
+    fn merge(ours: &[u8], theirs: &[u8], style: ConflictStyle) -> (String, bool) {
+        let merged = merge_file(BASE, ours, theirs, &LABELS, style);
+        (String::from_utf8(merged.data).unwrap(), merged.clean)
+    }
+
+    #[test]
+    fn clean_merge() {
+        let merged = merge(b"a\nB\nc\nd\ne\n", b"a\nb\nc\nD\ne\n", ConflictStyle::Merge);
+        assert_eq!(merged, (String::from("a\nB\nc\nD\ne\n"), true));
+        // Both sides making the same change is not a conflict.
+        let merged = merge(b"a\nb\nC\nd\n", b"a\nb\nC\nd\n", ConflictStyle::Merge);
+        assert_eq!(merged, (String::from("a\nb\nC\nd\n"), true));
+    }
+
+    #[test]
+    fn overlapping_conflict() {
+        let merged = merge(b"a\nb\nX\nd\ne\n", b"a\nb\nY\nd\ne\n", ConflictStyle::Merge);
+        assert_eq!(merged, (String::from("a\nb\n<<<<<<< ours\nX\n=======\nY\n>>>>>>> theirs\nd\ne\n"), false));
+    }
+
+    #[test]
+    fn adjacent_hunks_conflict() {
+        // Changes to neighbouring lines touch each other, so they conflict as in git.
+        let merged = merge(b"a\nB\nc\nd\ne\n", b"a\nb\nC\nd\ne\n", ConflictStyle::Merge);
+        assert_eq!(merged, (String::from("a\n<<<<<<< ours\nB\nc\n=======\nb\nC\n>>>>>>> theirs\nd\ne\n"), false));
+        // One unchanged line between them is enough to keep them apart.
+        let merged = merge(b"a\nB\nc\nd\ne\n", b"a\nb\nc\nD\ne\n", ConflictStyle::Merge);
+        assert!(merged.1);
+    }
+
+    #[test]
+    fn diff3_base_section() {
+        let merged = merge(b"a\nb\nX\nd\ne\n", b"a\nb\nY\nd\ne\n", ConflictStyle::Diff3);
+        assert_eq!(
+            merged,
+            (String::from("a\nb\n<<<<<<< ours\nX\n||||||| base\nc\n=======\nY\n>>>>>>> theirs\nd\ne\n"), false)
+        );
+        // Lines both sides agree on stay inside the conflict with diff3.
+        let merged = merge(b"a\nb\nX\nZ\ne\n", b"a\nb\nY\nZ\ne\n", ConflictStyle::Diff3);
+        assert_eq!(
+            merged,
+            (String::from("a\nb\n<<<<<<< ours\nX\nZ\n||||||| base\nc\nd\n=======\nY\nZ\n>>>>>>> theirs\ne\n"), false)
+        );
+    }
+
+    #[test]
+    fn delete_modify() {
+        let repo = Repository::temp("merge-delete-modify");
+        let entry = |sha: &str| (String::from("100644"), sha.repeat(40));
+        let base = Entries::from([(String::from("f"), entry("1")), (String::from("g"), entry("1"))]);
+        let ours = Entries::from([(String::from("f"), entry("2")), (String::from("g"), entry("1"))]);
+        let theirs = Entries::from([(String::from("g"), entry("1"))]);
+
+        let result = merge_trees(&repo, &base, &ours, &theirs, &LABELS, ConflictStyle::Merge).unwrap();
+        assert_eq!(result.conflicts, vec![String::from("f")]);
+        let stages = result.index.entries().iter()
+            .map(|entry| (entry.file_path.clone(), entry.stage()))
+            .collect::<Vec<(String, u16)>>();
+        assert_eq!(stages, vec![(String::from("f"), 1), (String::from("f"), 2), (String::from("g"), 0)]);
+        assert_eq!(result.worktree.get("f"), Some(&entry("2")));
+        assert_eq!(result.messages, vec![String::from(
+            "CONFLICT (modify/delete): f deleted in theirs and modified in ours.  Version ours of f left in tree."
+        )]);
+
+        // Deleting on our side keeps their version instead.
+        let result = merge_trees(&repo, &base, &theirs, &ours, &LABELS, ConflictStyle::Merge).unwrap();
+        assert_eq!(result.worktree.get("f"), Some(&entry("2")));
+        assert_eq!(result.conflicts, vec![String::from("f")]);
+    }
+}
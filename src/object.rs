@@ -1,6 +1,6 @@
 use std::io::prelude::*;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::from_utf8;
 
 use flate2::{
@@ -22,6 +22,10 @@ use crate::tag::Tag;
 use crate::tree::Tree;
 use crate::object::WitObject::*;
 use crate::reference;
+use crate::reflog;
+use crate::util;
+use crate::attr::Attributes;
+use crate::convert;
 
 pub trait Find<T> {
     fn find(&self, element: T) -> Result<usize, Box<WitError>> { self.find_from(element, 0) }
@@ -62,31 +66,31 @@ impl Replace for Vec<u8> {
 
 impl<T: PartialEq + std::fmt::Debug> Find<T> for Vec<T> {
     fn find_from(&self, element: T, start: usize) -> Result<usize, Box<WitError>> {
-        self.iter().skip(start).position(|el| *el == element).ok_or(
+        self.iter().skip(start).position(|el| *el == element).map(|idx| idx + start).ok_or(
             io_err(format!("{:?} not found.", element))
         )
     }
 
     fn find_some(&self, element: T, start: usize) -> Option<usize> {
-        self.iter().skip(start).position(|el| *el == element)
+        self.iter().skip(start).position(|el| *el == element).map(|idx| idx + start)
     }
 
     fn find_signed(&self, element: T, start: usize) -> isize {
-        match self.iter().skip(start).position(|el| *el == element) {
+        match self.find_some(element, start) {
             Some(idx) => idx as isize,
             None => -1
         }
     }
 
     fn find_exact(&self, element: T, start: usize) -> usize {
-        self.iter().skip(start).position(|el| *el == element).unwrap()
+        self.find_some(element, start).unwrap()
     }
 }
 
 pub enum WitObject<'a> {
     BlobObject(Blob<'a>),
     CommitObject(Commit<'a>),
-    TreeObject(Tree),
+    TreeObject(Tree<'a>),
     TagObject(Tag<'a>)
 }
 
@@ -127,24 +131,27 @@ pub trait Object {
     fn repo(&self) -> Option<&Repository>;
 }
 
-pub fn read<'a>(repo: &'a Repository, sha: &'a str) -> Result<WitObject<'a>, Box<WitError>> {
+pub fn read<'a>(repo: &'a Repository, sha: &str) -> Result<WitObject<'a>, Box<WitError>> {
+    if sha.len() != 40 || !sha.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Err(unknown_object_err(format!("Invalid object name {}.", sha)))?
+    }
     let path = Repository::file(&repo, vec!["objects", &sha[..2], &sha[2..]], false)?;
 
     let raw = fs::read(path)?;
     let mut decoded = Vec::<u8>::new();
-    ZlibDecoder::new(&raw[..]).read_exact(&mut decoded)?;
+    ZlibDecoder::new(&raw[..]).read_to_end(&mut decoded)?;
 
     let x = decoded.find(b' ') ?;
     let fmt = &decoded[..x];
 
-    let y = decoded[x..].to_vec().find(b'\x00')?;
+    let y = decoded.find_from(b'\x00', x)?;
 
-    let size = from_utf8(&decoded[x..y])?.parse::<usize>()?;
+    let size = from_utf8(&decoded[x+1..y])?.parse::<usize>()?;
     if size != decoded.len() - y - 1 {
         Err(malformed_object_err(format!("Malformed object {}: bad length", sha)))?
     }
 
-    build(from_utf8(&fmt)?, Some(repo), Some(raw[y+1..].to_vec()))
+    build(from_utf8(fmt)?, Some(repo), Some(decoded[y+1..].to_vec()))
 }
 
 pub fn find<'a>(repo: &'a Repository, name: &str, fmt: Option<&str>, follow: bool) -> Result<String, Box<WitError>> {
@@ -158,7 +165,9 @@ pub fn find<'a>(repo: &'a Repository, name: &str, fmt: Option<&str>, follow: boo
         });
         Err(ambiguous_reference_err(format!("Ambiguous reference {}: Candidates are:{}\n", name, candidates)))?
     }
-    let mut sha = sha.get(0).unwrap().to_string();
+    let mut sha = sha.first().ok_or(
+        unknown_reference_err(format!("Unknown reference {}.", name))
+    )?.to_string();
 
     if fmt.is_none() {
         return Ok(sha.to_string());
@@ -177,14 +186,12 @@ pub fn find<'a>(repo: &'a Repository, name: &str, fmt: Option<&str>, follow: boo
 
 
         if let TagObject(mut tag) = obj {
-            sha = tag.kvlm().get("object").unwrap().get(0).unwrap().to_string();
-        } else if let CommitObject(commit) = obj {
-            if fmt == b"tree" {
-                sha = commit.kvlm().get("tree").unwrap().get(0).unwrap().to_string();
-            }
+            sha = tag.kvlm().get("object").unwrap().first().unwrap().to_string();
+        } else if let (CommitObject(commit), b"tree") = (obj, fmt) {
+            sha = commit.kvlm().get("tree").unwrap().first().unwrap().to_string();
+        } else {
+            Err(unknown_object_err(format!("Unknown object {}.", sha)))?
         }
-
-        return Err(unknown_object_err(format!("Unknown object {}.", sha)))?;
     }
 }
 
@@ -195,9 +202,21 @@ pub fn resolve(repo: &Repository, name: &str) -> Result<Option<Vec<String>>, Box
     if name.trim().len() == 0 {
         return Ok(None);
     }
-    if name == "HEAD" {
+    // `<rev>~<n>` and `<rev>^<n>` walk to ancestors of <rev>, which may itself use `@{...}`.
+    let start = name.rfind('}').map(|idx| idx + 1).unwrap_or(0);
+    if let Some(idx) = name[start..].find(['~', '^']).map(|idx| idx + start).filter(|idx| *idx > 0) {
+        let base = self::find(repo, &name[..idx], Some("commit"), true)?;
+        return Ok(Some(vec![ self::navigate(repo, base, &name[idx..])? ]));
+    }
+    if let Some(caps) = Regex::new(r"^(.*)@\{([^}]+)\}$")?.captures(name) {
+        return Ok(Some(vec![ self::resolve_reflog(repo, &caps[1], &caps[2])? ]));
+    }
+    if name == "HEAD" || name == "@" {
         return Ok(Some(vec![ reference::resolve(repo, "HEAD")? ]));
     }
+    if let Some(full) = reference::dwim(repo, name)? {
+        return Ok(Some(vec![ reference::resolve(repo, &full)? ]));
+    }
 
     if hash_re.is_match(name) {
         let name = name.to_lowercase();
@@ -227,6 +246,57 @@ pub fn resolve(repo: &Repository, name: &str) -> Result<Option<Vec<String>>, Box
     Ok(Some(candidates))
 }
 
+/// Follow `~<n>` (n-th first-parent ancestor) and `^<n>` (n-th parent) steps from `sha`.
+/// A missing number means 1; `^0` is the commit itself.
+fn navigate(repo: &Repository, mut sha: String, steps: &str) -> Result<String, Box<WitError>> {
+    let invalid = || unknown_reference_err(format!("Invalid revision suffix {}", steps));
+    let mut rest = steps;
+    while let Some(op) = rest.chars().next() {
+        let digits = rest[1..].chars().take_while(char::is_ascii_digit).count();
+        let n = match &rest[1..1 + digits] {
+            "" => 1,
+            number => number.parse::<usize>()?
+        };
+        rest = &rest[1 + digits..];
+
+        match op {
+            '~' => for _ in 0..n {
+                sha = crate::graph::parents(repo, &sha)?.into_iter().next().ok_or(
+                    unknown_reference_err(format!("Commit {} has no parent", sha))
+                )?;
+            },
+            '^' if n == 0 => (),
+            '^' => {
+                sha = crate::graph::parents(repo, &sha)?.into_iter().nth(n - 1).ok_or(
+                    unknown_reference_err(format!("Commit {} has no parent {}", sha, n))
+                )?;
+            },
+            _ => Err(invalid())?
+        }
+    }
+    Ok(sha)
+}
+
+/// Resolve `<ref>@{<n>}` and `<ref>@{<date>}` through the reflog.
+/// An empty ref means the branch HEAD is attached to.
+fn resolve_reflog(repo: &Repository, base: &str, spec: &str) -> Result<String, Box<WitError>> {
+    let refname = if base.is_empty() || base == "@" {
+        reference::read_symbolic(repo, "HEAD")?.unwrap_or(String::from("HEAD"))
+    } else {
+        reference::dwim(repo, base)?.ok_or(
+            unknown_reference_err(format!("Unknown reference {}.", base))
+        )?
+    };
+
+    if let Ok(n) = spec.parse::<usize>() {
+        return reflog::nth(repo, &refname, n);
+    }
+    let timestamp = util::parse_date(spec, util::now().0).ok_or(
+        unknown_reference_err(format!("Invalid reflog selector @{{{}}}", spec))
+    )?;
+    reflog::at(repo, &refname, timestamp)
+}
+
 pub fn write(obj: WitObject, actually_write: bool) -> Result<String, Box<WitError>> {
     let data = obj.serialize()?;
     let mut result = Vec::new();
@@ -244,14 +314,10 @@ pub fn write(obj: WitObject, actually_write: bool) -> Result<String, Box<WitErro
         let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
         encoder.write_all(&result)?;
 
-        fs::write(
-            Repository::file(
-                obj.repo().ok_or(repo_not_found_err(format!("No repo found for object")))?, 
-                vec!["objects"],
-                actually_write
-            )?, // Path
-            encoder.finish()? // Data
-        )?;
+        let repo = obj.repo().ok_or(repo_not_found_err(format!("No repo found for object")))?;
+        let path = Repository::file(repo, vec!["objects", &sha[..2], &sha[2..]], actually_write)?;
+        fs::write(&path, encoder.finish()?)?;
+        repo.shared().apply(&path)?;
     }
 
     Ok(sha)
@@ -260,15 +326,110 @@ pub fn write(obj: WitObject, actually_write: bool) -> Result<String, Box<WitErro
 fn build<'a>(fmt: &str, repo: Option<&'a Repository>, data: Option<Vec<u8>>) -> Result<WitObject<'a>, Box<WitError>> {
     match fmt {
         "blob" => Ok(WitObject::BlobObject(Blob::new(repo, data.ok_or(missing_data_err("Data is required to construct a blob.".to_owned()))?))),
-        "commit" => Ok(WitObject::CommitObject(Commit::new(repo))),
-        "tree" => Ok(WitObject::TreeObject(Tree::from(&data.ok_or(missing_data_err("Data is required to construct a tree.".to_owned()))?)?)),
-        "tag" => Ok(WitObject::TagObject(Tag::new(repo))),
+        "commit" => {
+            let mut commit = Commit::new(repo);
+            if let Some(data) = data {
+                commit.deserialize(data)?;
+            }
+            Ok(WitObject::CommitObject(commit))
+        },
+        "tree" => Ok(WitObject::TreeObject(Tree::from(repo, &data.ok_or(missing_data_err("Data is required to construct a tree.".to_owned()))?)?)),
+        "tag" => {
+            let mut tag = Tag::new(repo);
+            if let Some(data) = data {
+                tag.deserialize(data)?;
+            }
+            Ok(WitObject::TagObject(tag))
+        },
         _ => Err(unknown_object_err(format!("Unknown object type {}", fmt)))
     }
 }
 
-pub fn hash<'a>(fd: &str, fmt: &str, repo: Option<&'a Repository>) -> Result<String, Box<WitError>>{
-    write(build(fmt, repo, Some(fs::read(fd)?))?, repo.is_none())
+/// Object name of `data` as an object of type `fmt`, written when a repository is given.
+pub fn hash_data(data: Vec<u8>, fmt: &str, repo: Option<&Repository>) -> Result<String, Box<WitError>> {
+    write(build(fmt, repo, Some(data))?, repo.is_some())
+}
+
+/// Tree entry mode for a worktree path, as git would record it.
+/// Directories that contain a `.git` are treated as gitlinks.
+pub fn mode_of(path: &Path, trust_filemode: bool) -> Result<&'static str, Box<WitError>> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.file_type().is_symlink() {
+        Ok("120000")
+    } else if meta.is_dir() {
+        Ok(if path.join(".git").exists() { "160000" } else { "40000" })
+    } else if trust_filemode && is_executable(&meta) {
+        Ok("100755")
+    } else {
+        Ok("100644")
+    }
+}
+
+#[cfg(unix)]
+pub fn is_executable(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// Read a worktree path the way it would be stored as a blob.
+/// Symlinks are not followed; their content is the link target.
+pub fn read_worktree(path: &Path, trust_filemode: bool) -> Result<(&'static str, Vec<u8>), Box<WitError>> {
+    let mode = mode_of(path, trust_filemode)?;
+    match mode {
+        "120000" => {
+            let target = fs::read_link(path)?;
+            Ok((mode, target.to_str().ok_or(path_conversion_err())?.as_bytes().to_vec()))
+        },
+        "100644" | "100755" => Ok((mode, fs::read(path)?)),
+        _ => Err(unknown_object_err(format!("{} is not a file.", path.display())))
+    }
+}
+
+/// Write blob data to the worktree honoring the tree entry mode.
+pub fn write_worktree(dest: &Path, mode: &str, data: &[u8]) -> Result<(), Box<WitError>> {
+    match fs::symlink_metadata(dest) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(dest)?,
+        Ok(_) => fs::remove_file(dest)?,
+        Err(_) => ()
+    }
+    match mode {
+        "120000" => write_symlink(dest, data),
+        "100755" => write_file(dest, data, 0o777),
+        "100644" | "100664" => write_file(dest, data, 0o666),
+        _ => Err(malformed_object_err(format!("Invalid mode {} for {}", mode, dest.display())))
+    }
+}
+
+#[cfg(unix)]
+fn write_file(dest: &Path, data: &[u8], mode: u32) -> Result<(), Box<WitError>> {
+    use std::os::unix::fs::OpenOptionsExt;
+    // Permissions are masked by the umask, just like git does.
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(mode).open(dest)?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_file(dest: &Path, data: &[u8], _mode: u32) -> Result<(), Box<WitError>> {
+    fs::write(dest, data)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_symlink(dest: &Path, data: &[u8]) -> Result<(), Box<WitError>> {
+    std::os::unix::fs::symlink(from_utf8(data)?, dest)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_symlink(dest: &Path, data: &[u8]) -> Result<(), Box<WitError>> {
+    // Without symlink support git writes the target as a plain file.
+    write_file(dest, data, 0o666)
 }
 
 pub fn graphviz(repo: &Repository, sha: String, seen: &mut Vec<String>) -> Result<(), Box<WitError>> {
@@ -302,21 +463,35 @@ pub fn graphviz(repo: &Repository, sha: String, seen: &mut Vec<String>) -> Resul
 }
 
 pub fn checkout<'a>(repo: &'a Repository, tree: &Tree, path: &PathBuf) -> Result<(), Box<WitError>> {
-    let mut obj: WitObject;
+    checkout_into(repo, tree, path, "", &mut Attributes::new(repo)?)
+}
+
+/// Write `tree` below `path`. `prefix` is the tree's place in the repository, which
+/// selects the attributes and so the filters each file goes through.
+fn checkout_into(repo: &Repository, tree: &Tree, path: &PathBuf, prefix: &str, attrs: &mut Attributes) -> Result<(), Box<WitError>> {
     let mut dest: PathBuf;
     for leaf in tree.leaves() {
-        obj = read(repo, &leaf.sha())?;
         dest = PathBuf::from(path).join(&leaf.path());
+        let name = format!("{}{}", prefix, leaf.path().to_str().ok_or(path_conversion_err())?);
 
-        match obj {
+        // Gitlinks point at commits in another repository, so there is nothing to read.
+        if leaf.mode() == "160000" {
+            fs::create_dir_all(&dest)?;
+            continue;
+        }
+
+        match read(repo, leaf.sha())? {
+            WitObject::BlobObject(blob) if leaf.mode() == "120000" => {
+                write_worktree(&dest, leaf.mode(), blob.data())?;
+            },
             WitObject::BlobObject(blob) => {
-                fs::write(&dest, blob.data())?;
+                write_worktree(&dest, leaf.mode(), &convert::to_worktree(repo, attrs, &name, blob.data().to_vec())?)?;
             },
             WitObject::TreeObject(tree) => {
                 fs::create_dir_all(&dest)?;
-                checkout(repo, &tree, &dest)?;
+                checkout_into(repo, &tree, &dest, &format!("{}/", name), attrs)?;
             },
-            _ => return Err(unknown_object_err(
+            obj => return Err(unknown_object_err(
                 format!(
                     "Object {} of type {} cannot be checked out.",
                     path.to_str().unwrap(),
@@ -326,4 +501,4 @@ pub fn checkout<'a>(repo: &'a Repository, tree: &Tree, path: &PathBuf) -> Result
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,587 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::{
+    repository::Repository,
+    object,
+    commit,
+    graph,
+    reference::{self, Transaction},
+    reflog,
+    index::Index,
+    tree::{self, Entries},
+    worktree,
+    attr::Attributes,
+    util,
+    merge::{self, Labels},
+    error::{WitError, builder::*}
+};
+
+/// A todo-list command of an interactive rebase.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verb {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Exec,
+    Drop
+}
+
+impl Verb {
+    fn parse(word: &str) -> Option<Verb> {
+        match word {
+            "p" | "pick" => Some(Verb::Pick),
+            "r" | "reword" => Some(Verb::Reword),
+            "e" | "edit" => Some(Verb::Edit),
+            "s" | "squash" => Some(Verb::Squash),
+            "f" | "fixup" => Some(Verb::Fixup),
+            "x" | "exec" => Some(Verb::Exec),
+            "d" | "drop" => Some(Verb::Drop),
+            _ => None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Verb::Pick => "pick",
+            Verb::Reword => "reword",
+            Verb::Edit => "edit",
+            Verb::Squash => "squash",
+            Verb::Fixup => "fixup",
+            Verb::Exec => "exec",
+            Verb::Drop => "drop"
+        }
+    }
+}
+
+/// One line of `git-rebase-todo`: a verb with a commit, or `exec` with a command.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub verb: Verb,
+    pub arg: String,
+    pub subject: String
+}
+
+impl Step {
+    fn parse(line: &str) -> Result<Option<Step>, Box<WitError>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let verb = Verb::parse(word).ok_or(
+            malformed_object_err(format!("Invalid command '{}' in the rebase todo list", word))
+        )?;
+        let rest = rest.trim();
+        if verb == Verb::Exec {
+            return Ok(Some(Step { verb, arg: rest.to_owned(), subject: String::new() }));
+        }
+        let (arg, subject) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if arg.is_empty() {
+            Err(malformed_object_err(format!("Missing commit after '{}' in the rebase todo list", word)))?
+        }
+        Ok(Some(Step { verb, arg: arg.to_owned(), subject: subject.trim().to_owned() }))
+    }
+
+    fn serialize(&self) -> String {
+        match self.verb {
+            Verb::Exec => format!("exec {}\n", self.arg),
+            verb => format!("{} {} {}\n", verb.name(), self.arg, self.subject)
+        }
+    }
+}
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\" but keep only the previous
+#                    commit's log message
+# x, exec <command> = run command (the rest of the line) using shell
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+pub struct Options<'a> {
+    pub upstream: &'a str,
+    pub onto: Option<&'a str>,
+    pub branch: Option<&'a str>,
+    pub interactive: bool,
+    pub autosquash: bool
+}
+
+fn path(repo: &Repository, name: &str) -> PathBuf {
+    Repository::path(repo, vec!["rebase-merge", name])
+}
+
+fn read_state(repo: &Repository, name: &str) -> Result<Option<String>, Box<WitError>> {
+    let path = self::path(repo, name);
+    Ok(if path.is_file() { Some(fs::read_to_string(path)?.trim_end().to_owned()) } else { None })
+}
+
+fn write_state(repo: &Repository, name: &str, value: &str) -> Result<(), Box<WitError>> {
+    fs::write(self::path(repo, name), format!("{}\n", value))?;
+    Ok(())
+}
+
+fn remove_state(repo: &Repository, name: &str) -> Result<(), Box<WitError>> {
+    let path = self::path(repo, name);
+    if path.is_file() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+pub fn in_progress(repo: &Repository) -> bool {
+    Repository::path(repo, vec!["rebase-merge"]).is_dir()
+}
+
+fn read_steps(repo: &Repository, name: &str) -> Result<Vec<Step>, Box<WitError>> {
+    let path = self::path(repo, name);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let mut ret = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        if let Some(step) = Step::parse(line)? {
+            ret.push(step);
+        }
+    }
+    Ok(ret)
+}
+
+fn write_steps(repo: &Repository, name: &str, steps: &[Step]) -> Result<(), Box<WitError>> {
+    fs::write(self::path(repo, name), steps.iter().map(Step::serialize).collect::<String>())?;
+    Ok(())
+}
+
+/// Open `path` in the user's editor and wait for it to close.
+fn edit(repo: &Repository, path: &PathBuf) -> Result<(), Box<WitError>> {
+    let editor = repo.editor();
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(path)
+        .current_dir(repo.worktree()?)
+        .status()?;
+    if !status.success() {
+        Err(io_err(format!("There was a problem with the editor '{}'.", editor)))?
+    }
+    Ok(())
+}
+
+/// Let the user edit a commit message; comment lines are dropped.
+fn edit_message(repo: &Repository, message: &str) -> Result<String, Box<WitError>> {
+    let path = Repository::path(repo, vec!["COMMIT_EDITMSG"]);
+    fs::write(&path, format!(
+        "{}\n\n# Please enter the commit message for your changes. Lines starting\n# with '#' will be ignored, and an empty message aborts the commit.\n",
+        message.trim_end()
+    ))?;
+    self::edit(repo, &path)?;
+    let message = merge::clean_message(&fs::read_to_string(&path)?);
+    if message.trim().is_empty() {
+        Err(merge_conflict_err(String::from("Aborting commit due to empty commit message.")))?
+    }
+    Ok(message)
+}
+
+/// Move `fixup! <subject>` and `squash! <subject>` commits right after the commit
+/// they refer to, by subject prefix or commit name, and turn them into fixups or squashes.
+fn autosquash(repo: &Repository, steps: Vec<Step>) -> Result<Vec<Step>, Box<WitError>> {
+    let mut ret: Vec<Step> = Vec::new();
+    let mut pending = Vec::new();
+    for step in steps {
+        let target = [("fixup! ", Verb::Fixup), ("squash! ", Verb::Squash)]
+            .iter()
+            .find_map(|(prefix, verb)| step.subject.strip_prefix(prefix).map(|rest| (rest.trim().to_owned(), *verb)));
+        match target {
+            Some((target, verb)) => pending.push((target, Step { verb, ..step })),
+            None => ret.push(step)
+        }
+    }
+
+    for (target, step) in pending {
+        let target_sha = object::find(repo, &target, Some("commit"), true).ok();
+        let position = ret.iter().position(|candidate| {
+            candidate.subject.starts_with(&target)
+                || target_sha.as_deref().map(|sha| sha.starts_with(&candidate.arg)).unwrap_or(false)
+        });
+        match position {
+            Some(idx) => {
+                // Keep earlier fixups of the same commit in front of this one.
+                let mut end = idx + 1;
+                while end < ret.len() && matches!(ret[end].verb, Verb::Fixup | Verb::Squash) {
+                    end += 1;
+                }
+                ret.insert(end, step);
+            },
+            None => ret.push(Step { verb: Verb::Pick, ..step })
+        }
+    }
+    Ok(ret)
+}
+
+/// Point HEAD directly at `sha`, detaching it.
+fn detach(repo: &Repository, sha: &str, message: &str) -> Result<(), Box<WitError>> {
+    let mut transaction = Transaction::new(repo);
+    transaction.update("HEAD", sha, None, false, message);
+    transaction.commit()
+}
+
+fn head_tree(repo: &Repository) -> Result<Entries, Box<WitError>> {
+    tree::flatten(repo, &commit::tree_of(repo, &reference::resolve(repo, "HEAD")?)?)
+}
+
+/// Start rebasing the current branch (or `branch`) onto `upstream`, or `onto`.
+/// Returns `false` when the rebase stopped and waits for `--continue`.
+pub fn start(repo: &Repository, opts: &Options) -> Result<bool, Box<WitError>> {
+    if self::in_progress(repo) {
+        Err(merge_conflict_err(String::from(
+            "It seems that there is already a rebase-merge directory.\nUse --continue, --skip or --abort."
+        )))?
+    }
+
+    if let Some(branch) = opts.branch {
+        let full = format!("refs/heads/{}", branch);
+        let sha = reference::resolve(repo, &full)?;
+        let current = Index::read(repo)?;
+        let target = commit::tree_of(repo, &sha)?;
+        let mut index = Index::from_tree(repo, &target)?;
+        worktree::switch(repo, &current, &tree::flatten(repo, &target)?, &mut index, false, "checkout")?;
+        index.write(repo)?;
+        reference::set_symbolic(repo, "HEAD", &full, &format!("rebase (start): checkout {}", branch))?;
+    }
+
+    let head = reference::resolve(repo, "HEAD")?;
+    let index = Index::read(repo)?;
+    if index.tree_entries() != self::head_tree(repo)? {
+        Err(dirty_worktree_err(String::from("cannot rebase: Your index contains uncommitted changes.")))?
+    }
+    let mut attrs = Attributes::new(repo)?;
+    for entry in index.entries() {
+        if worktree::is_modified(repo, &mut attrs, entry)? {
+            Err(dirty_worktree_err(String::from("cannot rebase: You have unstaged changes.")))?
+        }
+    }
+
+    let upstream = object::find(repo, opts.upstream, Some("commit"), true)?;
+    let onto = match opts.onto {
+        Some(onto) => object::find(repo, onto, Some("commit"), true)?,
+        None => upstream.clone()
+    };
+    let head_name = reference::read_symbolic(repo, "HEAD")?.unwrap_or(String::from("detached HEAD"));
+
+    // Merge commits are not replayed, like git without --rebase-merges.
+    let mut commits = graph::range(repo, std::slice::from_ref(&head), std::slice::from_ref(&upstream))?;
+    commits.reverse();
+    let mut steps = Vec::new();
+    for sha in commits {
+        let commit = commit::read(repo, &sha)?;
+        if commit.parents().len() <= 1 {
+            steps.push(Step { verb: Verb::Pick, arg: util::abbrev(&sha).to_owned(), subject: commit.subject().to_owned() });
+        }
+    }
+    if opts.autosquash {
+        steps = self::autosquash(repo, steps)?;
+    }
+
+    if !opts.interactive && !opts.autosquash && graph::merge_bases(repo, &head, std::slice::from_ref(&upstream))? == [onto.clone()] {
+        println!("Current branch {} is up to date.", reference::shorten(&head_name));
+        return Ok(true);
+    }
+
+    fs::create_dir_all(Repository::path(repo, vec!["rebase-merge"]))?;
+    self::write_state(repo, "head-name", &head_name)?;
+    self::write_state(repo, "onto", &onto)?;
+    self::write_state(repo, "orig-head", &head)?;
+    self::write_steps(repo, "git-rebase-todo", &steps)?;
+    self::write_steps(repo, "done", &[])?;
+
+    if opts.interactive {
+        self::write_state(repo, "interactive", "")?;
+        let path = self::path(repo, "git-rebase-todo");
+        fs::write(&path, format!(
+            "{}\n# Rebase {}..{} onto {} ({} command{})\n{}",
+            steps.iter().map(Step::serialize).collect::<String>(),
+            util::abbrev(&upstream), util::abbrev(&head), util::abbrev(&onto), steps.len(), if steps.len() == 1 { "" } else { "s" },
+            TODO_HELP
+        ))?;
+        let edited = self::edit(repo, &path).and_then(|_| self::read_steps(repo, "git-rebase-todo"));
+        match edited {
+            Ok(steps) if steps.is_empty() => {
+                fs::remove_dir_all(Repository::path(repo, vec!["rebase-merge"]))?;
+                println!("Nothing to do");
+                return Ok(true);
+            },
+            Ok(steps) => self::write_steps(repo, "git-rebase-todo", &steps)?,
+            Err(e) => {
+                fs::remove_dir_all(Repository::path(repo, vec!["rebase-merge"]))?;
+                Err(e)?
+            }
+        }
+    }
+
+    reference::update(repo, "ORIG_HEAD", &head, "")?;
+    let current = Index::read(repo)?;
+    let target = commit::tree_of(repo, &onto)?;
+    let mut index = Index::from_tree(repo, &target)?;
+    worktree::switch(repo, &current, &tree::flatten(repo, &target)?, &mut index, false, "rebase")?;
+    index.write(repo)?;
+    self::detach(repo, &onto, &format!("rebase (start): checkout {}", opts.onto.unwrap_or(opts.upstream)))?;
+
+    self::proceed(repo)
+}
+
+/// Run the todo list until it is done or a step stops.
+fn proceed(repo: &Repository) -> Result<bool, Box<WitError>> {
+    loop {
+        let mut todo = self::read_steps(repo, "git-rebase-todo")?;
+        if todo.is_empty() {
+            return self::finish(repo);
+        }
+        let step = todo.remove(0);
+        let mut done = self::read_steps(repo, "done")?;
+        done.push(step.clone());
+        self::write_steps(repo, "done", &done)?;
+        self::write_steps(repo, "git-rebase-todo", &todo)?;
+
+        if !self::run(repo, &step)? {
+            return Ok(false);
+        }
+    }
+}
+
+fn stop_hints() {
+    println!("hint: Resolve all conflicts manually, mark them as resolved in the index,");
+    println!("hint: then run \"wit rebase --continue\".");
+    println!("hint: You can instead skip this commit: run \"wit rebase --skip\".");
+    println!("hint: To abort and get back to the state before \"wit rebase\", run \"wit rebase --abort\".");
+}
+
+/// Execute a single step. Returns `false` when the rebase has to stop.
+fn run(repo: &Repository, step: &Step) -> Result<bool, Box<WitError>> {
+    // Any other step ends a chain of squashes and fixups.
+    if !matches!(step.verb, Verb::Squash | Verb::Fixup) {
+        self::remove_state(repo, "message-squash")?;
+    }
+    match step.verb {
+        Verb::Drop => return Ok(true),
+        Verb::Exec => {
+            println!("Executing: {}", step.arg);
+            let status = Command::new("sh").arg("-c").arg(&step.arg).current_dir(repo.worktree()?).status()?;
+            if !status.success() {
+                println!("warning: execution failed: {}", step.arg);
+                println!("You can fix the problem, and then run\n\n  wit rebase --continue\n");
+                return Ok(false);
+            }
+            return Ok(true);
+        },
+        _ => ()
+    }
+
+    let sha = object::find(repo, &step.arg, Some("commit"), true)?;
+    let commit = commit::read(repo, &sha)?;
+    let head = reference::resolve(repo, "HEAD")?;
+    let short = format!("{}... {}", util::abbrev(&sha), commit.subject());
+    let parent = commit.parents().first().cloned();
+
+    // A commit that already sits on HEAD is reused as it is.
+    if matches!(step.verb, Verb::Pick | Verb::Edit) && parent.as_deref() == Some(head.as_str()) {
+        let current = Index::read(repo)?;
+        let target = commit::tree_of(repo, &sha)?;
+        let mut index = Index::from_tree(repo, &target)?;
+        worktree::switch(repo, &current, &tree::flatten(repo, &target)?, &mut index, false, "rebase")?;
+        index.write(repo)?;
+        self::detach(repo, &sha, &format!("rebase (pick): {}", commit.subject()))?;
+        return self::after_commit(repo, step, &sha);
+    }
+
+    let base = match &parent {
+        Some(parent) => tree::flatten(repo, &commit::tree_of(repo, parent)?)?,
+        None => Entries::new()
+    };
+    let theirs = tree::flatten(repo, &commit::tree_of(repo, &sha)?)?;
+    let parent_label = format!("parent of {}", short);
+    let result = merge::apply(repo, &base, &theirs, &Labels { base: &parent_label, ours: "HEAD", theirs: &short }, "rebase")?;
+
+    if !result.clean() {
+        self::write_state(repo, "stopped-sha", &sha)?;
+        println!("error: could not apply {}", short);
+        self::stop_hints();
+        println!("Could not apply {}", short);
+        return Ok(false);
+    }
+    self::commit_step(repo, step, &sha)
+}
+
+/// Commit the index for `step`, which replays commit `sha`. Empty changes are dropped.
+fn commit_step(repo: &Repository, step: &Step, sha: &str) -> Result<bool, Box<WitError>> {
+    let original = commit::read(repo, sha)?;
+    let head = reference::resolve(repo, "HEAD")?;
+    let head_commit = commit::read(repo, &head)?;
+    let tree = Index::read(repo)?.write_tree(repo)?;
+    let subject = original.subject().to_owned();
+
+    let new = match step.verb {
+        Verb::Squash | Verb::Fixup => {
+            let message = self::squash_message(repo, step, head_commit.message(), original.message())?;
+            commit::create(repo, &tree, &head_commit.parents(), &message, head_commit.header("author"))?
+        },
+        _ if tree == commit::tree_of(repo, &head)? => {
+            println!("dropping {} {} -- patch contents already upstream", util::abbrev(sha), subject);
+            return Ok(true);
+        },
+        verb => {
+            let message = match verb {
+                Verb::Reword => self::edit_message(repo, original.message())?,
+                _ => original.message().to_owned()
+            };
+            commit::create(repo, &tree, &[head], &message, original.header("author"))?
+        }
+    };
+    self::detach(repo, &new, &format!("rebase ({}): {}", step.verb.name(), subject))?;
+    self::after_commit(repo, step, &new)
+}
+
+/// The message for folding `message` into the commit at HEAD with a squash or fixup.
+/// A chain of them collects its numbered messages in `message-squash`; the editor
+/// opens once the chain ends, if a squash was part of it.
+fn squash_message(repo: &Repository, step: &Step, head_message: &str, message: &str) -> Result<String, Box<WitError>> {
+    let previous = self::read_state(repo, "message-squash")?;
+    let count = previous.as_deref()
+        .and_then(|previous| previous.lines().next()?.strip_prefix("# This is a combination of ")?.split(' ').next()?.parse::<usize>().ok())
+        .unwrap_or(1) + 1;
+    let earlier = match &previous {
+        Some(previous) => previous.split_once('\n').map(|(_, rest)| rest.to_owned()).unwrap_or_default(),
+        None => format!("# This is the 1st commit message:\n\n{}", head_message.trim_end())
+    };
+    let added = match step.verb {
+        Verb::Squash => format!("# This is the commit message #{}:\n\n{}", count, message.trim_end()),
+        _ => format!(
+            "# The commit message #{} will be skipped:\n\n{}",
+            count, message.trim_end().lines().map(|line| format!("# {}", line).trim_end().to_owned()).collect::<Vec<String>>().join("\n")
+        )
+    };
+    let combined = format!("# This is a combination of {} commits.\n{}\n\n{}", count, earlier, added);
+
+    let chain_goes_on = self::read_steps(repo, "git-rebase-todo")?.first()
+        .map(|next| matches!(next.verb, Verb::Squash | Verb::Fixup))
+        .unwrap_or(false);
+    if chain_goes_on {
+        self::write_state(repo, "message-squash", &combined)?;
+        return Ok(merge::clean_message(&combined));
+    }
+    self::remove_state(repo, "message-squash")?;
+    if combined.contains("\n# This is the commit message #") {
+        self::edit_message(repo, &combined)
+    } else {
+        Ok(merge::clean_message(&combined))
+    }
+}
+
+/// `edit` stops once its commit is made so it can be amended.
+fn after_commit(repo: &Repository, step: &Step, new: &str) -> Result<bool, Box<WitError>> {
+    if step.verb != Verb::Edit {
+        return Ok(true);
+    }
+    self::write_state(repo, "amend", new)?;
+    println!("Stopped at {}...  {}", util::abbrev(new), commit::read(repo, new)?.subject());
+    println!("You can amend the commit now by staging changes, then run\n\n  wit rebase --continue\n");
+    Ok(false)
+}
+
+/// Move the rebased branch to the result and reattach HEAD.
+fn finish(repo: &Repository) -> Result<bool, Box<WitError>> {
+    let head_name = self::read_state(repo, "head-name")?.unwrap_or_default();
+    let onto = self::read_state(repo, "onto")?.unwrap_or_default();
+    let head = reference::resolve(repo, "HEAD")?;
+    if head_name.starts_with("refs/") {
+        let mut transaction = Transaction::new(repo);
+        transaction.update(&head_name, &head, None, false, &format!("rebase (finish): {} onto {}", head_name, onto));
+        transaction.commit()?;
+        reference::set_symbolic(repo, "HEAD", &head_name, "")?;
+        reflog::append(repo, "HEAD", &head, &head, &format!("rebase (finish): returning to {}", head_name))?;
+    }
+    fs::remove_dir_all(Repository::path(repo, vec!["rebase-merge"]))?;
+    println!("Successfully rebased and updated {}.", head_name);
+    Ok(true)
+}
+
+/// Commit the resolution of a stopped step, or amend an `edit`, then go on.
+pub fn resume(repo: &Repository) -> Result<bool, Box<WitError>> {
+    if !self::in_progress(repo) {
+        Err(merge_conflict_err(String::from("No rebase in progress?")))?
+    }
+    let index = Index::read(repo)?;
+    if !index.conflicts().is_empty() {
+        Err(merge_conflict_err(String::from(
+            "You must edit all merge conflicts and then mark them as resolved in the index."
+        )))?
+    }
+
+    if let Some(sha) = self::read_state(repo, "stopped-sha")? {
+        let step = self::read_steps(repo, "done")?.pop().ok_or(
+            malformed_object_err(String::from("The rebase done list is empty."))
+        )?;
+        if !self::commit_step(repo, &Step { verb: match step.verb { Verb::Edit => Verb::Pick, verb => verb }, ..step }, &sha)? {
+            return Ok(false);
+        }
+        self::remove_state(repo, "stopped-sha")?;
+    }
+    if let Some(amend) = self::read_state(repo, "amend")? {
+        // Staged changes are folded into the commit the rebase stopped at.
+        let head = reference::resolve(repo, "HEAD")?;
+        let tree = index.write_tree(repo)?;
+        if head == amend && tree != commit::tree_of(repo, &head)? {
+            let commit = commit::read(repo, &head)?;
+            let new = commit::create(repo, &tree, &commit.parents(), commit.message(), commit.header("author"))?;
+            self::detach(repo, &new, &format!("rebase (amend): {}", commit.subject()))?;
+        }
+        self::remove_state(repo, "amend")?;
+    }
+    self::proceed(repo)
+}
+
+/// Drop the step that stopped and go on with the rest.
+pub fn skip(repo: &Repository) -> Result<bool, Box<WitError>> {
+    if !self::in_progress(repo) {
+        Err(merge_conflict_err(String::from("No rebase in progress?")))?
+    }
+    worktree::reset(repo, &commit::tree_of(repo, &reference::resolve(repo, "HEAD")?)?)?;
+    self::remove_state(repo, "stopped-sha")?;
+    self::remove_state(repo, "amend")?;
+    self::proceed(repo)
+}
+
+/// Return to the branch and commit the rebase started from.
+pub fn abort(repo: &Repository) -> Result<(), Box<WitError>> {
+    if !self::in_progress(repo) {
+        Err(merge_conflict_err(String::from("No rebase in progress?")))?
+    }
+    let head_name = self::read_state(repo, "head-name")?.unwrap_or_default();
+    let orig_head = self::read_state(repo, "orig-head")?.ok_or(
+        malformed_object_err(String::from("Could not read orig-head of the rebase."))
+    )?;
+
+    worktree::reset(repo, &commit::tree_of(repo, &orig_head)?)?;
+    let message = format!("rebase (abort): returning to {}", head_name);
+    if head_name.starts_with("refs/") {
+        reference::set_symbolic(repo, "HEAD", &head_name, &message)?;
+    } else {
+        self::detach(repo, &orig_head, &message)?;
+    }
+    fs::remove_dir_all(Repository::path(repo, vec!["rebase-merge"]))?;
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::cmp::Ordering;
+use crate::{
+    repository::Repository,
+    object::{self, WitObject},
+    reference,
+    util,
+    error::{WitError, builder::*}
+};
+
+pub const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+/// A ref as seen by for-each-ref and friends.
+pub struct RefItem {
+    pub name: String,
+    pub sha: String
+}
+
+/// Whether `name` is selected by any of `patterns`. A pattern matches a ref
+/// either literally up to a `/` boundary (`refs/heads` matches `refs/heads/main`)
+/// or as a glob. No patterns select every ref.
+pub fn matches(name: &str, patterns: &[&str]) -> bool {
+    patterns.is_empty() || patterns.iter().any(|pattern| {
+        name == *pattern
+            || name.starts_with(&format!("{}/", pattern.trim_end_matches('/')))
+            || util::wildmatch(pattern, name, true)
+    })
+}
+
+/// Header lines and message of a commit or tag, `None` for blobs and trees.
+fn read_kvlm(repo: &Repository, sha: &str) -> Result<(String, Option<crate::kvlm::KVLM>), Box<WitError>> {
+    match object::read(repo, sha)? {
+        WitObject::CommitObject(commit) => Ok((String::from("commit"), Some(commit.kvlm().clone()))),
+        WitObject::TagObject(mut tag) => Ok((String::from("tag"), Some(tag.kvlm().clone()))),
+        obj => Ok((String::from_utf8(obj.fmt())?, None))
+    }
+}
+
+/// Expand a single `%(atom)`. A leading `*` evaluates the atom on the object
+/// an annotated tag points to and is empty for anything else.
+pub fn atom(repo: &Repository, item: &RefItem, atom: &str) -> Result<String, Box<WitError>> {
+    let (name, modifier) = match atom.split_once(':') {
+        Some((name, modifier)) => (name, Some(modifier)),
+        None => (atom, None)
+    };
+
+    match (name, modifier) {
+        ("refname", None) => return Ok(item.name.clone()),
+        ("refname", Some("short")) => return Ok(reference::shorten(&item.name).to_owned()),
+        ("refname", Some(other)) => Err(cli_argument_err(&format!("known refname modifier (got '{}')", other)))?,
+        _ => ()
+    }
+
+    let (name, sha) = match name.strip_prefix('*') {
+        Some(name) => match reference::peel(repo, &item.sha) {
+            Some(peeled) => (name, peeled),
+            None => return Ok(String::new())
+        },
+        None => (name, item.sha.clone())
+    };
+
+    if name == "objectname" {
+        return Ok(match modifier {
+            Some("short") => util::abbrev(&sha).to_owned(),
+            _ => sha
+        });
+    }
+
+    let (fmt, kvlm) = self::read_kvlm(repo, &sha)?;
+    let header = |key: &str| kvlm.as_ref()
+        .and_then(|kvlm| kvlm.get(key))
+        .and_then(|values| values.first())
+        .cloned();
+    let message = header("").unwrap_or_default();
+    let signature = |who: &str| -> Option<(String, String, i64, String)> {
+        let key = match who {
+            "creator" if fmt == "tag" => "tagger",
+            "creator" => "committer",
+            who => who
+        };
+        header(key).map(|line| util::parse_signature(&line))
+    };
+
+    Ok(match name {
+        "objecttype" => fmt.clone(),
+        "subject" => message.split("\n\n").next().unwrap_or("").trim_end().replace('\n', " "),
+        "body" => message.split_once("\n\n").map(|(_, body)| body.to_owned()).unwrap_or_default(),
+        "contents" => message,
+        _ => {
+            let (who, field) = ["author", "committer", "tagger", "creator"]
+                .iter()
+                .find_map(|who| name.strip_prefix(who).map(|field| (*who, field)))
+                .ok_or(cli_argument_err(&format!("known field name (got '{}')", name)))?;
+            match (signature(who), field) {
+                (None, _) => String::new(),
+                (Some((name, _, _, _)), "name") => name,
+                (Some((_, email, _, _)), "email") => format!("<{}>", email),
+                (Some((_, _, timestamp, tz)), "date") => util::format_date(timestamp, &tz, modifier.unwrap_or("default")),
+                (Some((name, email, timestamp, tz)), "") => format!("{} <{}> {} {}", name, email, timestamp, tz),
+                _ => Err(cli_argument_err(&format!("known field name (got '{}')", atom)))?
+            }
+        }
+    })
+}
+
+/// Expand a format string: `%(atom)`, `%%` for a literal percent sign
+/// and `%xx` for the byte with hex value xx. Bytes are collected first so that
+/// several `%xx` can spell out one multi-byte character.
+pub fn format(repo: &Repository, item: &RefItem, format: &str) -> Result<String, Box<WitError>> {
+    let mut ret = Vec::new();
+    let mut rest = format;
+    while let Some(idx) = rest.find('%') {
+        ret.extend_from_slice(&rest.as_bytes()[..idx]);
+        rest = &rest[idx + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            ret.push(b'%');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('(') {
+            let end = after.find(')').ok_or(
+                cli_argument_err(&format!("closing ')' in format '{}'", format))
+            )?;
+            ret.extend_from_slice(self::atom(repo, item, &after[..end])?.as_bytes());
+            rest = &after[end + 1..];
+        } else if let Some(byte) = rest.get(..2).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            ret.push(byte);
+            rest = &rest[2..];
+        } else {
+            ret.push(b'%');
+        }
+    }
+    ret.extend_from_slice(rest.as_bytes());
+    Ok(String::from_utf8_lossy(&ret).into_owned())
+}
+
+/// Sort by `keys`, where the last key is the primary one like git's repeated `--sort`.
+/// A leading `-` reverses a key; dates compare chronologically.
+pub fn sort(repo: &Repository, items: &mut Vec<RefItem>, keys: &[&str]) -> Result<(), Box<WitError>> {
+    enum Value {
+        Text(String),
+        Number(i64)
+    }
+
+    let mut keyed = Vec::new();
+    for item in items.drain(..) {
+        let mut values = Vec::new();
+        for key in keys.iter().rev() {
+            let key = key.trim_start_matches('-');
+            values.push(if key.ends_with("date") {
+                Value::Number(self::atom(repo, &item, &format!("{}:unix", key))?.parse::<i64>().unwrap_or(0))
+            } else {
+                Value::Text(self::atom(repo, &item, key)?)
+            });
+        }
+        keyed.push((values, item));
+    }
+
+    keyed.sort_by(|(a, _), (b, _)| {
+        for (i, key) in keys.iter().rev().enumerate() {
+            let ord = match (&a[i], &b[i]) {
+                (Value::Number(a), Value::Number(b)) => a.cmp(b),
+                (Value::Text(a), Value::Text(b)) => a.cmp(b),
+                _ => Ordering::Equal
+            };
+            let ord = if key.starts_with('-') { ord.reverse() } else { ord };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    });
+
+    items.extend(keyed.into_iter().map(|(_, item)| item));
+    Ok(())
+}
@@ -1,11 +1,13 @@
-use std::path::{PathBuf, Path};
+use std::path::Path;
 use std::fs;
 use linked_hash_map::LinkedHashMap;
 use crate::{
     repository::Repository,
+    object::{self, WitObject::TagObject},
+    reflog::{self, ZERO_SHA},
     error::{
         WitError,
-        builder::utf8_err
+        builder::{ utf8_err, unknown_reference_err, malformed_object_err, ref_verification_err }
     }
 };
 
@@ -17,49 +19,536 @@ pub enum Ref {
 pub type IndirectRef = LinkedHashMap<String, Ref>;
 pub type DirectRef = String;
 
+/// A single entry of `.git/packed-refs`.
+/// `peeled` holds the target of an annotated tag, from the following `^` line.
+pub struct PackedRef {
+    pub sha: String,
+    pub peeled: Option<String>
+}
+
+pub type PackedRefs = LinkedHashMap<String, PackedRef>;
+
+/// Maximum number of symbolic refs followed before giving up, like git's `SYMREF_MAXDEPTH`.
+pub const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The contents of a ref file: either a symbolic `ref: <target>` or an object name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefValue {
+    Symbolic(String),
+    Object(String)
+}
+
+impl RefValue {
+    /// Parse ref file contents. `ref:` may be followed by any amount of
+    /// whitespace and trailing whitespace (including the newline) is ignored.
+    pub fn parse(data: &str) -> RefValue {
+        let data = data.trim();
+        match data.strip_prefix("ref:") {
+            Some(target) => RefValue::Symbolic(target.trim().to_owned()),
+            None => RefValue::Object(data.to_owned())
+        }
+    }
+}
+
+/// The object name `ref_path` points to, following symbolic refs.
 pub fn resolve(repo: &Repository, ref_path: &str) -> Result<String, Box<WitError>> {
-    let paths = ref_path.split('/').collect::<Vec<&str>>();
-    let data = std::fs::read_to_string(Repository::file(repo, paths, false)?)?;
-    if data.starts_with("ref: ") {
-        self::resolve(repo, &data[5..])
-    } else {
-        Ok(data)
+    let target = self::target(repo, ref_path)?;
+    match self::read(repo, &target)?.map(|data| RefValue::parse(&data)) {
+        Some(RefValue::Object(sha)) => Ok(sha),
+        _ => Err(unknown_reference_err(format!("Unknown reference {}.", ref_path)))
+    }
+}
+
+/// The target of `name` if it is a symbolic ref, without dereferencing it further.
+pub fn read_symbolic(repo: &Repository, name: &str) -> Result<Option<String>, Box<WitError>> {
+    match self::read(repo, name)?.map(|data| RefValue::parse(&data)) {
+        Some(RefValue::Symbolic(target)) => Ok(Some(target)),
+        _ => Ok(None)
+    }
+}
+
+/// Make `name` a symbolic ref pointing at `target`.
+pub fn set_symbolic(repo: &Repository, name: &str, target: &str, message: &str) -> Result<(), Box<WitError>> {
+    self::check_name(name)?;
+    if !(target.starts_with("refs/") || (name != "HEAD" && target == "HEAD")) {
+        Err(unknown_reference_err(format!("Refusing to point {} outside of refs/: {}", name, target)))?
+    }
+    self::check_name(target)?;
+    let lock = repo.lock(&Repository::path(repo, name.split('/').collect()))?;
+    let old = self::resolve(repo, name).unwrap_or_default();
+    lock.commit(format!("ref: {}\n", target).as_bytes())?;
+
+    let new = self::resolve(repo, target).unwrap_or_default();
+    if !message.is_empty() && old != new {
+        reflog::append(repo, name, &old, &new, message)?;
+    }
+    Ok(())
+}
+
+/// Delete the symbolic ref `name` itself, leaving its target alone.
+pub fn delete_symbolic(repo: &Repository, name: &str) -> Result<(), Box<WitError>> {
+    self::check_name(name)?;
+    if self::read_symbolic(repo, name)?.is_none() {
+        Err(unknown_reference_err(format!("Cannot delete {}, not a symbolic ref", name)))?
     }
+    let path = Repository::path(repo, name.split('/').collect());
+    let _lock = repo.lock(&path)?;
+    fs::remove_file(&path)?;
+    self::prune_dirs(repo, &path)?;
+    reflog::remove(repo, name)
 }
 
-pub fn list(repo: &Repository, path: Option<PathBuf>) -> Result<IndirectRef, Box<WitError>> {
-    let path = if let None = path {
-        Repository::dir(repo, vec!["refs"], false)?
+/// Short, unambiguous-in-practice form of a full ref name, e.g. `main` for `refs/heads/main`.
+pub fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Whether `name` is a valid ref name, following `git check-ref-format`: no component
+/// starts with `.` or ends with `.lock`, and there is no `..`, `@{`, `//`, control
+/// character, space or any of `~^:?*[\`.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "@"
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && !name.chars().any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+        && name.split('/').all(|part| !part.starts_with('.') && !part.ends_with(".lock"))
+}
+
+/// Whether `name` is a pseudo-ref living directly in the git dir, like `HEAD` or `ORIG_HEAD`.
+fn is_pseudo(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+/// Whether `name` can be stored as a ref: a pseudo-ref or a valid name under `refs/`.
+/// Anything else, like `config` or `refs/../x`, would land outside the ref namespace.
+pub fn is_ref_name(name: &str) -> bool {
+    self::is_pseudo(name) || (name.starts_with("refs/") && self::is_valid_name(name))
+}
+
+/// Fail unless `name` passes `is_ref_name`.
+pub fn check_name(name: &str) -> Result<(), Box<WitError>> {
+    if !self::is_ref_name(name) {
+        Err(ref_verification_err(format!("Refusing to update ref with bad name '{}'.", name)))?
+    }
+    Ok(())
+}
+
+/// Read the value of a ref without following it.
+/// Loose refs take precedence over entries in packed-refs; names that are not refs read as missing.
+pub fn read(repo: &Repository, ref_path: &str) -> Result<Option<String>, Box<WitError>> {
+    if !self::is_ref_name(ref_path) {
+        return Ok(None);
+    }
+    let path = Repository::path(repo, ref_path.split('/').collect());
+    if path.is_file() {
+        return Ok(Some(fs::read_to_string(path)?.trim_end().to_owned()));
+    }
+    Ok(self::read_packed(repo)?.remove(ref_path).map(|packed| packed.sha))
+}
+
+/// Expand a short name the way git does, e.g. `main` to `refs/heads/main`.
+pub fn dwim(repo: &Repository, name: &str) -> Result<Option<String>, Box<WitError>> {
+    let rules = [
+        "{}",
+        "refs/{}",
+        "refs/tags/{}",
+        "refs/heads/{}",
+        "refs/remotes/{}",
+        "refs/remotes/{}/HEAD"
+    ];
+    // Only pseudo-refs like HEAD or ORIG_HEAD may live directly in the git dir.
+    let pseudo = name.starts_with("refs/") || self::is_pseudo(name);
+    for rule in rules {
+        if rule == "{}" && !pseudo {
+            continue;
+        }
+        let full = rule.replace("{}", name);
+        if self::read(repo, &full)?.is_some() {
+            return Ok(Some(full));
+        }
+    }
+    Ok(None)
+}
+
+pub fn read_packed(repo: &Repository) -> Result<PackedRefs, Box<WitError>> {
+    let mut ret = PackedRefs::new();
+    let path = Repository::path(repo, vec!["packed-refs"]);
+    if !path.is_file() {
+        return Ok(ret);
+    }
+
+    let mut last: Option<String> = None;
+    for line in fs::read_to_string(path)?.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            let name = last.as_ref().ok_or(
+                malformed_object_err(format!("Peeled line without a ref in packed-refs: {}", line))
+            )?;
+            if let Some(entry) = ret.get_mut(name) {
+                entry.peeled = Some(peeled.trim().to_owned());
+            }
+            continue;
+        }
+        let (sha, name) = line.split_once(' ').ok_or(
+            malformed_object_err(format!("Malformed line in packed-refs: {}", line))
+        )?;
+        ret.insert(name.trim().to_owned(), PackedRef { sha: sha.to_owned(), peeled: None });
+        last = Some(name.trim().to_owned());
+    }
+    Ok(ret)
+}
+
+/// Packed-refs contents sorted by name, recomputing the peeled value of every entry.
+fn serialize_packed(repo: &Repository, refs: &PackedRefs) -> String {
+    let mut names = refs.keys().collect::<Vec<&String>>();
+    names.sort();
+
+    let mut data = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    for name in names {
+        let sha = &refs[name].sha;
+        data.push_str(&format!("{} {}\n", sha, name));
+        if let Some(peeled) = self::peel(repo, sha) {
+            data.push_str(&format!("^{}\n", peeled));
+        }
+    }
+    data
+}
+
+/// The object an annotated tag ultimately points to, or `None` if `sha` is not a tag.
+pub fn peel(repo: &Repository, sha: &str) -> Option<String> {
+    let mut current = sha.to_owned();
+    let mut peeled = None;
+    while let Ok(TagObject(mut tag)) = object::read(repo, &current) {
+        current = tag.kvlm().get("object")?.first()?.to_owned();
+        peeled = Some(current.clone());
+    }
+    peeled
+}
+
+/// Full names of all loose refs below `refs/`, sorted.
+pub fn loose(repo: &Repository) -> Result<Vec<String>, Box<WitError>> {
+    fn walk(dir: &Path, prefix: &str, out: &mut Vec<String>) -> Result<(), Box<WitError>> {
+        for file in fs::read_dir(dir)? {
+            let file = file?;
+            let name = format!(
+                "{}/{}",
+                prefix,
+                file.file_name().to_str().ok_or(utf8_err(String::from("Could not read file name.")))?
+            );
+            if file.path().is_dir() {
+                walk(&file.path(), &name, out)?;
+            } else if !name.ends_with(".lock") {
+                out.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    let mut ret = Vec::new();
+    let path = Repository::path(repo, vec!["refs"]);
+    if path.is_dir() {
+        walk(&path, "refs", &mut ret)?;
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+/// Every loose and packed ref with the object it resolves to, sorted by name.
+/// Symbolic refs that do not resolve to anything are skipped.
+pub fn all(repo: &Repository) -> Result<Vec<(String, String)>, Box<WitError>> {
+    let mut names = self::loose(repo)?;
+    names.extend(self::read_packed(repo)?.keys().cloned());
+    names.sort();
+    names.dedup();
+
+    let mut ret = Vec::new();
+    for name in names {
+        match self::resolve(repo, &name) {
+            Ok(sha) => ret.push((name, sha)),
+            Err(_) if self::read_symbolic(repo, &name)?.is_some() => (),
+            Err(e) => Err(e)?
+        }
+    }
+    Ok(ret)
+}
+
+/// List loose and packed refs, optionally limited to those below `prefix`
+/// (e.g. `refs/tags`), nested by path component below `refs/`.
+pub fn list(repo: &Repository, prefix: Option<&str>) -> Result<IndirectRef, Box<WitError>> {
+    let mut ret: IndirectRef = IndirectRef::new();
+    for (name, sha) in self::all(repo)? {
+        if let Some(prefix) = prefix {
+            if !name.starts_with(&(prefix.trim_end_matches('/').to_owned() + "/")) {
+                continue;
+            }
+        }
+
+        let mut parts = name.split('/').skip(1).collect::<Vec<&str>>();
+        let leaf = parts.pop().ok_or(unknown_reference_err(format!("Invalid ref name {}", name)))?;
+
+        let mut node = &mut ret;
+        for part in parts {
+            if !node.contains_key(part) {
+                node.insert(part.to_owned(), Ref::Indirect(IndirectRef::new()));
+            }
+            node = match node.get_mut(part) {
+                Some(Ref::Indirect(refs)) => refs,
+                _ => Err(unknown_reference_err(format!("Ref {} conflicts with an existing ref", name)))?
+            };
+        }
+        node.insert(leaf.to_owned(), Ref::Direct(sha));
+    }
+
+    Ok(ret)
+}
+
+/// Delete a ref from both the loose refs and packed-refs. A symbolic ref is deleted
+/// itself rather than the ref it points at. Returns the object the ref was at.
+pub fn delete(repo: &Repository, name: &str) -> Result<String, Box<WitError>> {
+    let old = self::read(repo, name)?.ok_or(
+        unknown_reference_err(format!("Unknown reference {}.", name))
+    )?;
+    // Only a direct value can be checked; a symbolic one has nothing to compare with.
+    let (sha, expected) = if old.starts_with("ref: ") {
+        (self::resolve(repo, name).unwrap_or(String::from(ZERO_SHA)), None)
     } else {
-        path.unwrap()
+        (old.clone(), Some(old))
     };
+    let mut transaction = Transaction::new(repo);
+    transaction.delete(name, expected.as_deref(), false, "");
+    transaction.commit()?;
+    Ok(sha)
+}
 
-    let mut ret: IndirectRef = IndirectRef::new();
+/// Pack loose refs into packed-refs. Tags are always packed, other refs only with `all`.
+pub fn pack(repo: &Repository, all: bool, prune: bool) -> Result<(), Box<WitError>> {
+    let packed_lock = repo.lock(&Repository::path(repo, vec!["packed-refs"]))?;
+    let mut packed = self::read_packed(repo)?;
+    let mut packed_loose = Vec::new();
 
-    for file in std::fs::read_dir(path)? {
-        let can = file?;
-
-        let name = String::from(
-            can
-            .file_name()
-            .to_str().ok_or(utf8_err(format!("Could not read file name.")))?
-        );
-        if can.path().is_dir() {
-            ret.insert(
-                name,
-                Ref::Indirect(
-                    self::list(
-                        repo,
-                        Some(can.path())
-                    )?
-                )
-            );
+    for name in self::loose(repo)? {
+        let data = match self::read(repo, &name)?.map(|data| RefValue::parse(&data)) {
+            Some(RefValue::Object(sha)) => sha,
+            _ => continue
+        };
+        if !(all || name.starts_with("refs/tags/")) {
+            continue;
+        }
+        packed.insert(name.clone(), PackedRef { sha: data.clone(), peeled: None });
+        packed_loose.push((name, data));
+    }
+
+    packed_lock.commit(self::serialize_packed(repo, &packed).as_bytes())?;
+
+    if prune {
+        for (name, sha) in packed_loose {
+            let path = Repository::path(repo, name.split('/').collect());
+            // Leave refs alone that were updated while we were packing.
+            let _lock = repo.lock(&path)?;
+            if fs::read_to_string(&path)?.trim_end() == sha {
+                fs::remove_file(&path)?;
+                self::prune_dirs(repo, &path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+enum Op {
+    Update(String),
+    Delete,
+    Verify
+}
+
+struct RefUpdate {
+    name: String,
+    op: Op,
+    // None skips the check, ZERO_SHA requires the ref to not exist.
+    old: Option<String>,
+    deref: bool,
+    message: String
+}
+
+/// A set of ref updates applied all-or-nothing.
+/// Every ref is locked with `<ref>.lock` and checked against its expected old value
+/// before anything is written; new values are renamed into place.
+pub struct Transaction<'a> {
+    repo: &'a Repository,
+    updates: Vec<RefUpdate>
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new(repo: &'a Repository) -> Self {
+        Transaction { repo, updates: Vec::new() }
+    }
+
+    pub fn update(&mut self, name: &str, new: &str, old: Option<&str>, deref: bool, message: &str) -> &mut Self {
+        self.push(name, Op::Update(new.to_owned()), old, deref, message)
+    }
+
+    pub fn delete(&mut self, name: &str, old: Option<&str>, deref: bool, message: &str) -> &mut Self {
+        self.push(name, Op::Delete, old, deref, message)
+    }
+
+    pub fn verify(&mut self, name: &str, old: Option<&str>, deref: bool) -> &mut Self {
+        self.push(name, Op::Verify, Some(old.unwrap_or(ZERO_SHA)), deref, "")
+    }
+
+    fn push(&mut self, name: &str, op: Op, old: Option<&str>, deref: bool, message: &str) -> &mut Self {
+        self.updates.push(RefUpdate {
+            name: name.to_owned(),
+            op,
+            old: old.map(|old| if old.is_empty() { ZERO_SHA.to_owned() } else { old.to_owned() }),
+            deref,
+            message: message.to_owned()
+        });
+        self
+    }
+
+    pub fn commit(self) -> Result<(), Box<WitError>> {
+        let repo = self.repo;
+        for update in &self.updates {
+            self::check_name(&update.name)?;
+        }
+
+        // Work out which file every update writes to.
+        let mut targets: Vec<String> = Vec::new();
+        for update in &self.updates {
+            let target = if update.deref {
+                self::target(repo, &update.name)?
+            } else {
+                update.name.clone()
+            };
+            self::check_name(&target)?;
+            if targets.contains(&target) {
+                Err(ref_verification_err(format!("Multiple updates for ref '{}' not allowed.", target)))?
+            }
+            targets.push(target);
+        }
+
+        // Lock everything first, so we either hold all locks or none.
+        let mut locks = Vec::new();
+        for target in &targets {
+            locks.push(repo.lock(&Repository::path(repo, target.split('/').collect()))?);
+        }
+        let deleting = self.updates.iter().any(|update| matches!(update.op, Op::Delete));
+        let packed_lock = if deleting {
+            Some(repo.lock(&Repository::path(repo, vec!["packed-refs"]))?)
         } else {
-            ret.insert(name.clone(), Ref::Direct(resolve(repo, &name)?));
+            None
+        };
+
+        let mut current = Vec::new();
+        for (update, target) in self.updates.iter().zip(&targets) {
+            let value = match self::read(repo, target)?.map(|data| RefValue::parse(&data)) {
+                Some(RefValue::Symbolic(_)) => self::resolve(repo, target).ok(),
+                Some(RefValue::Object(sha)) => Some(sha),
+                None => None
+            };
+            if let Some(expected) = &update.old {
+                let matches = match &value {
+                    Some(value) => value == expected,
+                    None => expected == ZERO_SHA
+                };
+                if !matches {
+                    Err(ref_verification_err(format!(
+                        "Cannot lock ref '{}': is at {} but expected {}",
+                        update.name,
+                        value.as_deref().unwrap_or(ZERO_SHA),
+                        expected
+                    )))?
+                }
+            }
+            if let (Op::Delete, None) = (&update.op, &value) {
+                Err(unknown_reference_err(format!("Unknown reference {}.", update.name)))?
+            }
+            current.push(value.unwrap_or_default());
+        }
+
+        // Deleted refs have to go from packed-refs before their loose files disappear,
+        // otherwise a stale packed value would show through.
+        if let Some(packed_lock) = packed_lock {
+            let mut packed = self::read_packed(repo)?;
+            let mut changed = false;
+            for (update, target) in self.updates.iter().zip(&targets) {
+                if let Op::Delete = update.op {
+                    changed |= packed.remove(target).is_some();
+                }
+            }
+            if changed {
+                packed_lock.commit(self::serialize_packed(repo, &packed).as_bytes())?;
+            }
+        }
+
+        let head = self::read_symbolic(repo, "HEAD")?;
+        for (((update, target), lock), old) in self.updates.iter().zip(&targets).zip(locks).zip(current) {
+            match &update.op {
+                Op::Update(new) => {
+                    lock.commit(format!("{}\n", new).as_bytes())?;
+                    reflog::append(repo, target, &old, new, &update.message)?;
+                    if target != "HEAD" && head.as_deref() == Some(target.as_str()) {
+                        reflog::append(repo, "HEAD", &old, new, &update.message)?;
+                    }
+                },
+                Op::Delete => {
+                    let path = lock.path().to_path_buf();
+                    if path.is_file() {
+                        fs::remove_file(&path)?;
+                        self::prune_dirs(repo, &path)?;
+                    }
+                    drop(lock);
+                    reflog::remove(repo, target)?;
+                },
+                Op::Verify => ()
+            }
         }
+        Ok(())
     }
+}
 
-    Ok(ret)
+/// The ref that `name` ends up at after following symbolic refs.
+/// Fails on cycles and on chains longer than `MAX_SYMREF_DEPTH`.
+pub fn target(repo: &Repository, name: &str) -> Result<String, Box<WitError>> {
+    let mut chain = vec![name.to_owned()];
+    while let Some(next) = self::read_symbolic(repo, chain.last().unwrap())? {
+        if chain.contains(&next) {
+            chain.push(next.clone());
+            Err(unknown_reference_err(format!("Symbolic ref cycle: {}", chain.join(" -> "))))?
+        }
+        if chain.len() > MAX_SYMREF_DEPTH {
+            Err(unknown_reference_err(format!("Too many levels of symbolic refs at {}.", name)))?
+        }
+        chain.push(next);
+    }
+    Ok(chain.pop().unwrap())
+}
+
+/// Remove directories left empty by deleting the ref at `path`,
+/// keeping `refs/` and its direct children like `refs/heads`.
+fn prune_dirs(repo: &Repository, path: &Path) -> Result<(), Box<WitError>> {
+    let refs = Repository::path(repo, vec!["refs"]);
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current.parent() == Some(refs.as_path()) || !current.starts_with(&refs) || current == refs {
+            break;
+        }
+        if current.read_dir()?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(current)?;
+        dir = current.parent();
+    }
+    Ok(())
 }
 
 pub fn show(repo: &Repository, refs: &IndirectRef, with_hash: bool, prefix: &str) -> Result<(), Box<WitError>> {
@@ -80,19 +569,143 @@ pub fn show(repo: &Repository, refs: &IndirectRef, with_hash: bool, prefix: &str
     Ok(())
 }
 
-pub fn create(repo: &Repository, ref_name: String, sha: String) -> Result<(), Box<WitError>> {
-    fs::write(
-        Repository::file(
-            repo,
-            Path::new("refs/")
-                .join(&ref_name)
-                .to_str()
-                .ok_or(utf8_err(format!("Could not read file name.")))?
-                .split('/')
-                .collect::<Vec<&str>>(),
-            true
-        )?,
-        sha + "\n"
-    )?;
-    Ok(())
-}
\ No newline at end of file
+/// Create `refs/<ref_name>`, failing if it already exists.
+pub fn create(repo: &Repository, ref_name: String, sha: String, message: &str) -> Result<(), Box<WitError>> {
+    let name = Path::new("refs/")
+        .join(&ref_name)
+        .to_str()
+        .ok_or(utf8_err(String::from("Could not read file name.")))?
+        .to_owned();
+    if self::read(repo, &name)?.is_some() {
+        Err(ref_verification_err(format!("Reference '{}' already exists.", name)))?
+    }
+    let mut transaction = Transaction::new(repo);
+    transaction.update(&name, &sha, Some(ZERO_SHA), false, message);
+    transaction.commit()
+}
+
+/// Point `name`, or the ref it is a symbolic link to, at `sha`.
+pub fn update(repo: &Repository, name: &str, sha: &str, message: &str) -> Result<(), Box<WitError>> {
+    let mut transaction = Transaction::new(repo);
+    transaction.update(name, sha, None, true, message);
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: &str = "1111111111111111111111111111111111111111";
+    const TWO: &str = "2222222222222222222222222222222222222222";
+
+    fn packed(repo: &Repository, refs: &[(&str, &str)]) {
+        let data = refs.iter().map(|(name, sha)| format!("{} {}\n", sha, name)).collect::<String>();
+        fs::write(Repository::path(repo, vec!["packed-refs"]), data).unwrap();
+    }
+
+    #[test]
+    fn loose_shadows_packed() {
+        let repo = Repository::temp("reference-shadow");
+        packed(&repo, &[("refs/heads/a", ONE), ("refs/tags/t", ONE)]);
+        assert_eq!(resolve(&repo, "refs/heads/a").unwrap(), ONE);
+
+        fs::write(Repository::path(&repo, vec!["refs", "heads", "a"]), format!("{}\n", TWO)).unwrap();
+        assert_eq!(resolve(&repo, "refs/heads/a").unwrap(), TWO);
+        assert_eq!(resolve(&repo, "refs/tags/t").unwrap(), ONE);
+        assert_eq!(read_packed(&repo).unwrap()["refs/heads/a"].sha, ONE);
+    }
+
+    #[test]
+    fn delete_packed_and_loose() {
+        let repo = Repository::temp("reference-delete");
+        packed(&repo, &[("refs/heads/a", ONE), ("refs/heads/b", ONE)]);
+        fs::write(Repository::path(&repo, vec!["refs", "heads", "a"]), format!("{}\n", TWO)).unwrap();
+
+        delete(&repo, "refs/heads/a").unwrap();
+        assert_eq!(read(&repo, "refs/heads/a").unwrap(), None);
+        assert!(!Repository::path(&repo, vec!["refs", "heads", "a"]).exists());
+        assert!(!read_packed(&repo).unwrap().contains_key("refs/heads/a"));
+        assert_eq!(read(&repo, "refs/heads/b").unwrap().as_deref(), Some(ONE));
+        assert!(delete(&repo, "refs/heads/a").is_err());
+    }
+
+    #[test]
+    fn reflog_for_ref_and_head() {
+        let repo = Repository::temp("reference-reflog");
+        let branch = target(&repo, "HEAD").unwrap();
+        update(&repo, "HEAD", ONE, "first").unwrap();
+        update(&repo, &branch, TWO, "second").unwrap();
+        update(&repo, "refs/heads/other", ONE, "other").unwrap();
+
+        let entries = |name: &str| reflog::read(&repo, name).unwrap().iter()
+            .map(|entry| (entry.old.clone(), entry.new.clone(), entry.message.clone()))
+            .collect::<Vec<(String, String, String)>>();
+        let expected = vec![
+            (String::from(ZERO_SHA), String::from(ONE), String::from("first")),
+            (String::from(ONE), String::from(TWO), String::from("second"))
+        ];
+        assert_eq!(entries(&branch), expected);
+        assert_eq!(entries("HEAD"), expected);
+        assert_eq!(entries("refs/heads/other").len(), 1);
+    }
+
+    /// Paths of `.lock` files anywhere in the git dir.
+    fn locks(dir: &Path) -> Vec<std::path::PathBuf> {
+        let mut ret = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                ret.extend(locks(&path));
+            } else if path.extension().is_some_and(|ext| ext == "lock") {
+                ret.push(path);
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn old_value_mismatch() {
+        let repo = Repository::temp("reference-mismatch");
+        update(&repo, "refs/heads/a", ONE, "").unwrap();
+        packed(&repo, &[("refs/heads/c", ONE)]);
+        let packed_refs = fs::read_to_string(Repository::path(&repo, vec!["packed-refs"])).unwrap();
+
+        let mut transaction = Transaction::new(&repo);
+        transaction
+            .update("refs/heads/b", TWO, None, false, "")
+            .delete("refs/heads/c", None, false, "")
+            .update("refs/heads/a", TWO, Some(TWO), false, "");
+        assert!(transaction.commit().is_err());
+        assert_eq!(read(&repo, "refs/heads/a").unwrap().as_deref(), Some(ONE));
+        assert_eq!(read(&repo, "refs/heads/b").unwrap(), None);
+        assert_eq!(read(&repo, "refs/heads/c").unwrap().as_deref(), Some(ONE));
+        assert_eq!(fs::read_to_string(Repository::path(&repo, vec!["packed-refs"])).unwrap(), packed_refs);
+        assert!(locks(&repo.git_dir).is_empty());
+
+        // A ref expected not to exist must not exist.
+        let mut transaction = Transaction::new(&repo);
+        transaction.update("refs/heads/a", TWO, Some(ZERO_SHA), false, "");
+        assert!(transaction.commit().is_err());
+        assert!(locks(&repo.git_dir).is_empty());
+    }
+
+    #[test]
+    fn refuse_bad_names() {
+        let repo = Repository::temp("reference-bad-names");
+        let config = fs::read_to_string(Repository::path(&repo, vec!["config"])).unwrap();
+        for name in ["config", "refs/heads/../../x", "../x", "refs/heads/a.lock", "objects/x"] {
+            let mut transaction = Transaction::new(&repo);
+            transaction.update(name, ONE, None, false, "");
+            assert!(transaction.commit().is_err(), "{}", name);
+        }
+        assert_eq!(fs::read_to_string(Repository::path(&repo, vec!["config"])).unwrap(), config);
+        assert!(!Repository::path(&repo, vec!["x"]).exists());
+        assert!(set_symbolic(&repo, "config", "refs/heads/main", "").is_err());
+        assert!(set_symbolic(&repo, "HEAD", "refs/heads/../../config", "").is_err());
+
+        update(&repo, "ORIG_HEAD", ONE, "").unwrap();
+        update(&repo, "refs/heads/topic", ONE, "").unwrap();
+        assert_eq!(read(&repo, "refs/heads/topic").unwrap().as_deref(), Some(ONE));
+        assert_eq!(read(&repo, "config").unwrap(), None);
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use crate::{
+    repository::Repository,
+    config,
+    util,
+    error::{
+        WitError,
+        builder::{ unknown_reference_err, malformed_object_err }
+    }
+};
+
+pub const ZERO_SHA: &str = "0000000000000000000000000000000000000000";
+
+pub struct Entry {
+    pub old: String,
+    pub new: String,
+    pub identity: String,
+    pub timestamp: i64,
+    pub tz: String,
+    pub message: String
+}
+
+impl Entry {
+    pub fn parse(line: &str) -> Result<Entry, Box<WitError>> {
+        let malformed = || malformed_object_err(format!("Malformed reflog entry: {}", line));
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old, rest) = head.split_once(' ').ok_or_else(malformed)?;
+        let (new, rest) = rest.split_once(' ').ok_or_else(malformed)?;
+        let (identity, date) = rest.rsplit_once("> ").ok_or_else(malformed)?;
+        let (timestamp, tz) = date.split_once(' ').unwrap_or((date, "+0000"));
+
+        Ok(Entry {
+            old: old.to_owned(),
+            new: new.to_owned(),
+            identity: format!("{}>", identity),
+            timestamp: timestamp.parse::<i64>()?,
+            tz: tz.to_owned(),
+            message: message.to_owned()
+        })
+    }
+
+    pub fn serialize(&self) -> String {
+        format!(
+            "{} {} {} {} {}\t{}\n",
+            self.old, self.new, self.identity, self.timestamp, self.tz, self.message
+        )
+    }
+}
+
+fn path(repo: &Repository, refname: &str) -> PathBuf {
+    let mut paths = vec!["logs"];
+    paths.extend(refname.split('/'));
+    Repository::path(repo, paths)
+}
+
+pub fn exists(repo: &Repository, refname: &str) -> bool {
+    self::path(repo, refname).is_file()
+}
+
+/// Whether updates to `refname` are recorded, following `core.logAllRefUpdates`.
+pub fn should_log(repo: &Repository, refname: &str) -> bool {
+    if self::exists(repo, refname) {
+        return true;
+    }
+    let value = repo.conf.get("core.logallrefupdates").map(|value| value.to_lowercase());
+    match value.as_deref() {
+        Some("always") => true,
+        Some(value) if config::parse_bool(Some(value)) == Some(false) => false,
+        _ => refname == "HEAD"
+            || refname.starts_with("refs/heads/")
+            || refname.starts_with("refs/remotes/")
+            || refname.starts_with("refs/notes/")
+    }
+}
+
+/// Record a ref update. Only logs refs that `should_log` selects.
+pub fn append(repo: &Repository, refname: &str, old: &str, new: &str, message: &str) -> Result<(), Box<WitError>> {
+    if !self::should_log(repo, refname) {
+        return Ok(());
+    }
+    let (timestamp, tz) = util::now();
+    let entry = Entry {
+        old: if old.is_empty() { ZERO_SHA.to_owned() } else { old.to_owned() },
+        new: if new.is_empty() { ZERO_SHA.to_owned() } else { new.to_owned() },
+        identity: repo.identity(),
+        timestamp,
+        tz,
+        // The message has to stay on one line.
+        message: message.lines().next().unwrap_or("").to_owned()
+    };
+
+    let path = self::path(repo, refname);
+    let new_log = !path.is_file();
+    repo.create_dirs(path.parent().unwrap())?;
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(entry.serialize().as_bytes())?;
+    if new_log {
+        repo.shared().apply(&path)?;
+    }
+    Ok(())
+}
+
+/// Start an empty log for `refname`, so that its updates are recorded from now on.
+pub fn create(repo: &Repository, refname: &str) -> Result<(), Box<WitError>> {
+    let path = self::path(repo, refname);
+    if !path.is_file() {
+        repo.create_dirs(path.parent().unwrap())?;
+        fs::File::create(&path)?;
+        repo.shared().apply(&path)?;
+    }
+    Ok(())
+}
+
+/// Reflog entries of `refname`, oldest first.
+pub fn read(repo: &Repository, refname: &str) -> Result<Vec<Entry>, Box<WitError>> {
+    let path = self::path(repo, refname);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(Entry::parse)
+        .collect()
+}
+
+pub fn write(repo: &Repository, refname: &str, entries: &[Entry]) -> Result<(), Box<WitError>> {
+    repo.lock(&self::path(repo, refname))?
+        .commit(entries.iter().map(Entry::serialize).collect::<String>().as_bytes())
+}
+
+/// Remove the whole log of a ref, e.g. when the ref itself is deleted.
+pub fn remove(repo: &Repository, refname: &str) -> Result<(), Box<WitError>> {
+    let path = self::path(repo, refname);
+    if path.is_file() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Value of `refname@{n}`: the value the ref had `n` updates ago.
+pub fn nth(repo: &Repository, refname: &str, n: usize) -> Result<String, Box<WitError>> {
+    let entries = self::read(repo, refname)?;
+    if n < entries.len() {
+        Ok(entries[entries.len() - 1 - n].new.clone())
+    } else if n == entries.len() && n > 0 && entries[0].old != ZERO_SHA {
+        Ok(entries[0].old.clone())
+    } else {
+        Err(unknown_reference_err(format!(
+            "Log for '{}' only has {} entries.", refname, entries.len()
+        )))
+    }
+}
+
+/// Value of `refname@{date}`: the value the ref had at `timestamp`.
+pub fn at(repo: &Repository, refname: &str, timestamp: i64) -> Result<String, Box<WitError>> {
+    let entries = self::read(repo, refname)?;
+    if let Some(entry) = entries.iter().rev().find(|entry| entry.timestamp <= timestamp) {
+        return Ok(entry.new.clone());
+    }
+    match entries.first() {
+        Some(entry) if entry.old != ZERO_SHA => Ok(entry.old.clone()),
+        Some(entry) => Ok(entry.new.clone()),
+        None => Err(unknown_reference_err(format!("Log for '{}' is empty.", refname)))
+    }
+}
+
+/// Drop entries older than `cutoff`. Returns the number of entries removed.
+pub fn expire(repo: &Repository, refname: &str, cutoff: i64) -> Result<usize, Box<WitError>> {
+    let entries = self::read(repo, refname)?;
+    let total = entries.len();
+    let kept = entries.into_iter().filter(|entry| entry.timestamp >= cutoff).collect::<Vec<Entry>>();
+    let removed = total - kept.len();
+    if removed > 0 {
+        self::write(repo, refname, &kept)?;
+    }
+    Ok(removed)
+}
+
+/// Delete the single entry `refname@{n}`.
+pub fn delete(repo: &Repository, refname: &str, n: usize) -> Result<(), Box<WitError>> {
+    let mut entries = self::read(repo, refname)?;
+    if n >= entries.len() {
+        Err(unknown_reference_err(format!("No reflog entry {}@{{{}}}", refname, n)))?
+    }
+    entries.remove(entries.len() - 1 - n);
+    self::write(repo, refname, &entries)
+}
+
+/// All refs that have a reflog, sorted.
+pub fn list(repo: &Repository) -> Result<Vec<String>, Box<WitError>> {
+    fn walk(dir: &std::path::Path, prefix: &str, out: &mut Vec<String>) -> Result<(), Box<WitError>> {
+        for file in fs::read_dir(dir)? {
+            let file = file?;
+            let name = file.file_name().to_string_lossy().to_string();
+            let name = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+            if file.path().is_dir() {
+                walk(&file.path(), &name, out)?;
+            } else if !name.ends_with(".lock") {
+                out.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    let mut ret = Vec::new();
+    let logs = Repository::path(repo, vec!["logs"]);
+    if logs.is_dir() {
+        walk(&logs, "", &mut ret)?;
+    }
+    ret.sort();
+    Ok(ret)
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+
+use crate::{
+    reference,
+    error::{WitError, builder::*}
+};
+
+/// A `[+]<src>:<dst>` mapping between the refs of two repositories, as found in
+/// `remote.<name>.fetch` and `remote.<name>.push`. A pattern has one `*` on each
+/// side, standing for the same text in both names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refspec {
+    // Update the destination even when it is not a fast-forward.
+    pub force: bool,
+    pub src: String,
+    pub dst: Option<String>,
+    pub pattern: bool
+}
+
+impl Refspec {
+    /// Parse a fetch refspec, or a push refspec when `fetch` is false. Fetching may
+    /// leave out the destination or use an empty source for `HEAD`; pushing an empty
+    /// source deletes the destination.
+    pub fn parse(spec: &str, fetch: bool) -> Result<Refspec, Box<WitError>> {
+        let invalid = || config_err(format!("invalid refspec '{}'", spec));
+        let (force, rest) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec)
+        };
+        let (src, dst) = match rest.rsplit_once(':') {
+            Some((src, dst)) => (src, Some(dst)),
+            None => (rest, None)
+        };
+
+        let stars = |side: &str| side.matches('*').count();
+        let pattern = stars(src) == 1;
+        if stars(src) > 1 || dst.map(|dst| stars(dst) > 1 || (stars(dst) == 1) != pattern).unwrap_or(false) {
+            Err(invalid())?
+        }
+        // A pattern without a destination only makes sense for fetching.
+        if pattern && dst.map(str::is_empty).unwrap_or(!fetch) {
+            Err(invalid())?
+        }
+        let valid = |name: &str| reference::is_valid_name(&name.replacen('*', "x", 1));
+        // What gets pushed may be any revision, like `HEAD~1` or an object name.
+        let valid_src = match src {
+            "" => fetch || dst.map(|dst| !dst.is_empty()).unwrap_or(false),
+            src if fetch || pattern => valid(src),
+            _ => true
+        };
+        let valid_dst = match dst {
+            Some("") => fetch || src.is_empty(),
+            Some(dst) => valid(dst),
+            None => true
+        };
+        if !valid_src || !valid_dst || (src.is_empty() && dst.is_none()) {
+            Err(invalid())?
+        }
+
+        Ok(Refspec {
+            force,
+            src: src.to_owned(),
+            dst: dst.filter(|dst| !dst.is_empty()).map(str::to_owned),
+            pattern
+        })
+    }
+
+    /// Whether the ref `name` on the source side is covered by this refspec.
+    pub fn matches_src(&self, name: &str) -> bool {
+        self::capture(&self.src, self.pattern, name).is_some()
+    }
+
+    /// Whether the ref `name` on the destination side is covered by this refspec.
+    pub fn matches_dst(&self, name: &str) -> bool {
+        self.dst.as_ref().and_then(|dst| self::capture(dst, self.pattern, name)).is_some()
+    }
+
+    /// The destination ref that the source ref `name` goes to, e.g. `refs/remotes/origin/main`
+    /// for `refs/heads/main` with the default fetch refspec.
+    pub fn map_src(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_ref()?;
+        let captured = self::capture(&self.src, self.pattern, name)?;
+        Some(dst.replacen('*', captured, 1))
+    }
+
+    /// The source ref that ends up in the destination ref `name`.
+    pub fn map_dst(&self, name: &str) -> Option<String> {
+        let captured = self::capture(self.dst.as_ref()?, self.pattern, name)?;
+        Some(self.src.replacen('*', captured, 1))
+    }
+}
+
+impl fmt::Display for Refspec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", if self.force { "+" } else { "" }, self.src)?;
+        match &self.dst {
+            Some(dst) => write!(f, ":{}", dst),
+            None => Ok(())
+        }
+    }
+}
+
+/// What the `*` of `side` stands for in `name`, or all of `name` for an exact match.
+fn capture<'n>(side: &str, pattern: bool, name: &'n str) -> Option<&'n str> {
+    if !pattern {
+        return if side == name { Some(name) } else { None };
+    }
+    let (prefix, suffix) = side.split_once('*')?;
+    if name.len() < prefix.len() + suffix.len() {
+        return None;
+    }
+    name.strip_prefix(prefix)?.strip_suffix(suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Refspec, capture};
+
+    #[test]
+    fn parse_fetch() {
+        let spec = Refspec::parse("+refs/heads/*:refs/remotes/origin/*", true).unwrap();
+        assert!(spec.force && spec.pattern);
+        assert_eq!(spec.src, "refs/heads/*");
+        assert_eq!(spec.dst.as_deref(), Some("refs/remotes/origin/*"));
+        assert_eq!(spec.to_string(), "+refs/heads/*:refs/remotes/origin/*");
+
+        let spec = Refspec::parse("refs/heads/main", true).unwrap();
+        assert!(!spec.force && !spec.pattern && spec.dst.is_none());
+        assert!(Refspec::parse(":refs/heads/x", true).is_ok());
+        assert!(Refspec::parse("refs/heads/*", true).is_ok());
+
+        assert!(Refspec::parse("refs/*/*:refs/*", true).is_err());
+        assert!(Refspec::parse("refs/heads/*:refs/remotes/x", true).is_err());
+        assert!(Refspec::parse("refs/heads/a..b:refs/x", true).is_err());
+        assert!(Refspec::parse("", true).is_err());
+    }
+
+    #[test]
+    fn parse_push() {
+        let spec = Refspec::parse(":refs/heads/gone", false).unwrap();
+        assert_eq!((spec.src.as_str(), spec.dst.as_deref()), ("", Some("refs/heads/gone")));
+        assert!(Refspec::parse("HEAD~1:refs/heads/x", false).is_ok());
+        assert!(Refspec::parse("0123456789abcdef0123456789abcdef01234567:refs/heads/x", false).is_ok());
+        assert!(Refspec::parse("refs/heads/*:refs/heads/*", false).is_ok());
+
+        assert!(Refspec::parse("refs/heads/*", false).is_err());
+        assert!(Refspec::parse("refs/heads/a..*:refs/heads/*", false).is_err());
+        assert!(Refspec::parse("main:refs/heads/a..b", false).is_err());
+        assert!(Refspec::parse(":", false).is_err());
+    }
+
+    #[test]
+    fn captures() {
+        assert_eq!(capture("refs/heads/*", true, "refs/heads/main"), Some("main"));
+        assert_eq!(capture("refs/heads/*", true, "refs/heads/topic/x"), Some("topic/x"));
+        assert_eq!(capture("refs/*/main", true, "refs/heads/main"), Some("heads"));
+        assert_eq!(capture("refs/heads/*", true, "refs/tags/v1"), None);
+        assert_eq!(capture("refs/heads/main", false, "refs/heads/main"), Some("refs/heads/main"));
+        assert_eq!(capture("refs/heads/main", false, "refs/heads/mainline"), None);
+        // The prefix and suffix may not overlap.
+        assert_eq!(capture("refs/a*a", true, "refs/a"), None);
+    }
+
+    #[test]
+    fn mapping() {
+        let spec = Refspec::parse("+refs/heads/*:refs/remotes/origin/*", true).unwrap();
+        assert_eq!(spec.map_src("refs/heads/main").as_deref(), Some("refs/remotes/origin/main"));
+        assert_eq!(spec.map_src("refs/tags/v1"), None);
+        assert_eq!(spec.map_dst("refs/remotes/origin/topic/x").as_deref(), Some("refs/heads/topic/x"));
+        assert_eq!(spec.map_dst("refs/remotes/other/main"), None);
+
+        let spec = Refspec::parse("refs/heads/main:refs/heads/copy", true).unwrap();
+        assert_eq!(spec.map_src("refs/heads/main").as_deref(), Some("refs/heads/copy"));
+        assert_eq!(spec.map_dst("refs/heads/copy").as_deref(), Some("refs/heads/main"));
+        assert_eq!(Refspec::parse("refs/heads/main", true).unwrap().map_src("refs/heads/main"), None);
+    }
+}
@@ -0,0 +1,490 @@
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use crate::{
+    repository::Repository,
+    reference::{self, Transaction},
+    reflog::{self, ZERO_SHA},
+    refspec::Refspec,
+    config::{self, Config},
+    clone,
+    graph,
+    error::{WitError, builder::*}
+};
+
+/// A `[remote "<name>"]` section of the config.
+#[derive(Debug, Clone, Default)]
+pub struct Remote {
+    pub name: String,
+    pub urls: Vec<String>,
+    pub push_urls: Vec<String>,
+    pub fetch: Vec<Refspec>,
+    pub push: Vec<Refspec>,
+    pub mirror: bool
+}
+
+impl Remote {
+    /// The remote `name`, if the config has any setting for it.
+    pub fn get(conf: &Config, name: &str) -> Result<Option<Remote>, Box<WitError>> {
+        let prefix = format!("remote.{}.", name);
+        let mut ret = Remote { name: name.to_owned(), ..Default::default() };
+        let mut found = false;
+        for entry in conf.entries() {
+            let key = match entry.key.strip_prefix(&prefix) {
+                Some(key) => key,
+                None => continue
+            };
+            found = true;
+            let value = entry.value.clone().unwrap_or_default();
+            match key {
+                "url" => ret.urls.push(value),
+                "pushurl" => ret.push_urls.push(value),
+                "fetch" => ret.fetch.push(Refspec::parse(&value, true)?),
+                "push" => ret.push.push(Refspec::parse(&value, false)?),
+                "mirror" => ret.mirror = config::parse_bool(entry.value.as_deref()).unwrap_or(false),
+                _ => ()
+            }
+        }
+        Ok(if found { Some(ret) } else { None })
+    }
+
+    /// Where pushes go: the push URLs, or else the URLs fetched from.
+    pub fn push_targets(&self) -> &[String] {
+        if self.push_urls.is_empty() { &self.urls } else { &self.push_urls }
+    }
+
+    /// The remote-tracking ref that the remote's ref `name` is fetched into.
+    pub fn tracking_ref(&self, name: &str) -> Option<String> {
+        self.fetch.iter().find_map(|spec| spec.map_src(name))
+    }
+
+    /// Whether fetching from this remote writes to the local ref `name`.
+    pub fn tracks(&self, name: &str) -> bool {
+        self.fetch.iter().any(|spec| spec.matches_dst(name))
+    }
+}
+
+/// How `remote add --mirror` sets up a remote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirror {
+    Fetch,
+    Push
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    // Only fetch these branches instead of all of them.
+    pub branches: Vec<String>,
+    // The branch `refs/remotes/<name>/HEAD` points at.
+    pub master: Option<String>,
+    pub mirror: Option<Mirror>
+}
+
+/// How `set_url` changes the URLs of a remote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UrlChange {
+    Replace,
+    Add,
+    Delete
+}
+
+/// Names of all configured remotes, sorted.
+pub fn names(conf: &Config) -> Vec<String> {
+    let mut ret = conf.entries()
+        .iter()
+        .filter_map(|entry| entry.key.strip_prefix("remote.")?.rsplit_once('.').map(|(name, _)| name.to_owned()))
+        .collect::<Vec<String>>();
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+/// Whether `name` can be used for a remote: its remote-tracking refs must be valid ref names.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && reference::is_valid_name(&format!("refs/remotes/{}/test", name))
+}
+
+fn config_path(repo: &Repository) -> PathBuf {
+    repo.git_dir.join("config")
+}
+
+fn configured(repo: &Repository, name: &str) -> Result<Remote, Box<WitError>> {
+    Remote::get(&repo.conf, name)?.ok_or(config_err(format!("No such remote: '{}'", name)))
+}
+
+fn check_new_name(repo: &Repository, name: &str) -> Result<(), Box<WitError>> {
+    if !self::is_valid_name(name) {
+        Err(config_err(format!("'{}' is not a valid remote name", name)))?
+    }
+    if Remote::get(&repo.conf, name)?.is_some() {
+        Err(config_err(format!("remote {} already exists.", name)))?
+    }
+    Ok(())
+}
+
+/// Every loose and packed ref name, including symbolic refs that point nowhere.
+fn ref_names(repo: &Repository) -> Result<Vec<String>, Box<WitError>> {
+    let mut ret = reference::loose(repo)?;
+    ret.extend(reference::read_packed(repo)?.keys().cloned());
+    ret.sort();
+    ret.dedup();
+    Ok(ret)
+}
+
+/// Configure a new remote `name` fetching from `url`.
+pub fn add(repo: &Repository, name: &str, url: &str, opts: &AddOptions) -> Result<(), Box<WitError>> {
+    self::check_new_name(repo, name)?;
+    if opts.mirror.is_some() && opts.master.is_some() {
+        Err(cli_usage_err(String::from("specifying a master branch makes no sense with --mirror")))?
+    }
+    if opts.mirror == Some(Mirror::Push) && !opts.branches.is_empty() {
+        Err(cli_usage_err(String::from("specifying branches to track makes sense only with fetch mirrors")))?
+    }
+
+    let path = self::config_path(repo);
+    config::set(&path, &format!("remote.{}.url", name), url)?;
+    // A fetch mirror copies refs to the same names: all of them, or just the tracked branches.
+    let (src, dst) = match opts.mirror {
+        Some(_) if opts.branches.is_empty() => (String::from("refs/"), String::from("refs/")),
+        Some(_) => (String::from("refs/heads/"), String::from("refs/heads/")),
+        None => (String::from("refs/heads/"), format!("refs/remotes/{}/", name))
+    };
+    let fetch = match opts.mirror {
+        Some(Mirror::Push) => Vec::new(),
+        _ if opts.branches.is_empty() => vec![format!("+{}*:{}*", src, dst)],
+        _ => opts.branches.iter().map(|branch| format!("+{}{}:{}{}", src, branch, dst, branch)).collect()
+    };
+    for spec in fetch {
+        config::add(&path, &format!("remote.{}.fetch", name), &spec)?;
+    }
+    // Only pushing needs to know; fetch mirrors are all in the refspec.
+    if opts.mirror == Some(Mirror::Push) {
+        config::set(&path, &format!("remote.{}.mirror", name), "true")?;
+    }
+    if let Some(master) = &opts.master {
+        let head = format!("refs/remotes/{}/HEAD", name);
+        reference::set_symbolic(repo, &head, &format!("refs/remotes/{}/{}", name, master), "remote add")?;
+    }
+    Ok(())
+}
+
+/// Forget the remote `name`: its remote-tracking refs, the branches following it
+/// and its config section. Returns the local branches that its fetch refspecs
+/// cover but that are left alone.
+pub fn remove(repo: &Repository, name: &str) -> Result<Vec<String>, Box<WitError>> {
+    let remote = self::configured(repo, name)?;
+    let path = self::config_path(repo);
+    self::retarget_branches(repo, name, None)?;
+
+    // Refs that another remote fetches into as well stay.
+    let others = self::names(&repo.conf)
+        .into_iter()
+        .filter(|other| other != name)
+        .map(|other| Remote::get(&repo.conf, &other))
+        .collect::<Result<Vec<Option<Remote>>, Box<WitError>>>()?;
+    let mut kept = Vec::new();
+    let mut symbolic = Vec::new();
+    let mut transaction = Transaction::new(repo);
+    for refname in self::ref_names(repo)? {
+        if !remote.tracks(&refname) || others.iter().flatten().any(|other| other.tracks(&refname)) {
+            continue;
+        }
+        if !refname.starts_with("refs/remotes/") {
+            if let Some(branch) = refname.strip_prefix("refs/heads/") {
+                kept.push(branch.to_owned());
+            }
+        } else if reference::read_symbolic(repo, &refname)?.is_some() {
+            symbolic.push(refname);
+        } else {
+            transaction.delete(&refname, None, false, "");
+        }
+    }
+    for refname in symbolic {
+        reference::delete_symbolic(repo, &refname)?;
+    }
+    transaction.commit()?;
+
+    config::rename_section(&path, &format!("remote.{}", name), None)?;
+    Ok(kept)
+}
+
+/// Rename the remote `old` to `new`, along with its default fetch refspecs, its
+/// remote-tracking refs and the branches following it. Returns the fetch refspecs
+/// that were left as they are.
+pub fn rename(repo: &Repository, old: &str, new: &str) -> Result<Vec<String>, Box<WitError>> {
+    let remote = self::configured(repo, old)?;
+    self::check_new_name(repo, new)?;
+    let path = self::config_path(repo);
+    config::rename_section(&path, &format!("remote.{}", old), Some(&format!("remote.{}", new)))?;
+
+    let (old_prefix, new_prefix) = (format!("refs/remotes/{}/", old), format!("refs/remotes/{}/", new));
+    let mut skipped = Vec::new();
+    if !remote.fetch.is_empty() {
+        let key = format!("remote.{}.fetch", new);
+        config::unset(&path, &key, true)?;
+        for spec in &remote.fetch {
+            let mut spec = spec.clone();
+            match spec.dst.as_ref().and_then(|dst| dst.strip_prefix(&old_prefix)) {
+                Some(rest) => spec.dst = Some(format!("{}{}", new_prefix, rest)),
+                None => skipped.push(spec.to_string())
+            }
+            config::add(&path, &key, &spec.to_string())?;
+        }
+    }
+    self::retarget_branches(repo, old, Some(new))?;
+
+    // Symbolic refs are moved once the refs they point at have been.
+    let mut symbolic = Vec::new();
+    for refname in self::ref_names(repo)? {
+        let rest = match refname.strip_prefix(&old_prefix) {
+            Some(rest) => rest,
+            None => continue
+        };
+        let renamed = format!("{}{}", new_prefix, rest);
+        if let Some(target) = reference::read_symbolic(repo, &refname)? {
+            symbolic.push((refname, renamed, target));
+            continue;
+        }
+        let sha = reference::resolve(repo, &refname)?;
+        let log = reflog::read(repo, &refname)?;
+        let mut transaction = Transaction::new(repo);
+        transaction.delete(&refname, Some(&sha), false, "");
+        transaction.commit()?;
+        if !log.is_empty() {
+            reflog::write(repo, &renamed, &log)?;
+        }
+        let mut transaction = Transaction::new(repo);
+        transaction.update(&renamed, &sha, Some(ZERO_SHA), false, &format!("remote: renamed {} to {}", refname, renamed));
+        transaction.commit()?;
+    }
+    for (refname, renamed, target) in symbolic {
+        let target = match target.strip_prefix(&old_prefix) {
+            Some(rest) => format!("{}{}", new_prefix, rest),
+            None => target
+        };
+        reference::delete_symbolic(repo, &refname)?;
+        reference::set_symbolic(repo, &renamed, &target, &format!("remote: renamed {} to {}", refname, renamed))?;
+    }
+    Ok(skipped)
+}
+
+/// Point the branches that follow the remote `old` at `new`, or make them follow
+/// nothing when `new` is `None`.
+fn retarget_branches(repo: &Repository, old: &str, new: Option<&str>) -> Result<(), Box<WitError>> {
+    let path = self::config_path(repo);
+    for entry in repo.conf.entries().iter().filter(|entry| entry.origin == path) {
+        let (branch, key) = match entry.key.strip_prefix("branch.").and_then(|rest| rest.rsplit_once('.')) {
+            Some((branch, key)) if key == "remote" || key == "pushremote" => (branch, key),
+            _ => continue
+        };
+        if entry.value.as_deref() != Some(old) {
+            continue;
+        }
+        match new {
+            Some(new) => config::set(&path, &entry.key, new)?,
+            None => {
+                config::unset(&path, &entry.key, true)?;
+                let merge = format!("branch.{}.merge", branch);
+                if key == "remote" && repo.conf.entries().iter().any(|entry| entry.origin == path && entry.key == merge) {
+                    config::unset(&path, &merge, true)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Change the URLs, or with `push` the push URLs, of the remote `name`. `Replace` changes
+/// the one URL matching the regular expression `old`, or the only URL when there is no
+/// `old`; `Delete` removes every URL matching `url`.
+pub fn set_url(repo: &Repository, name: &str, url: &str, old: Option<&str>, push: bool, change: UrlChange) -> Result<(), Box<WitError>> {
+    let remote = Remote::get(&repo.conf, name)?.ok_or(config_err(format!("No such remote '{}'", name)))?;
+    let path = self::config_path(repo);
+    let key = format!("remote.{}.{}", name, if push { "pushurl" } else { "url" });
+    let urls = if push { remote.push_urls } else { remote.urls };
+
+    let pattern = match (change, old) {
+        (UrlChange::Add, _) => return config::add(&path, &key, url),
+        (UrlChange::Replace, None) => return config::set(&path, &key, url),
+        (UrlChange::Replace, Some(old)) => old,
+        (UrlChange::Delete, _) => url
+    };
+    let regex = Regex::new(pattern).map_err(|_| config_err(format!("Invalid old URL pattern: {}", pattern)))?;
+    let matches = urls.iter().filter(|url| regex.is_match(url)).count();
+    if matches == 0 {
+        Err(config_err(format!("No such URL found: {}", pattern)))?
+    }
+    match change {
+        UrlChange::Delete if matches == urls.len() && !push => Err(config_err(String::from("Will not delete all non-push URLs")))?,
+        UrlChange::Delete => config::unset_matching(&path, &key, |url| regex.is_match(url)),
+        _ if matches > 1 => Err(config_err(format!("{} has multiple values", key)))?,
+        _ => {
+            config::unset_matching(&path, &key, |url| regex.is_match(url))?;
+            config::add(&path, &key, url)
+        }
+    }
+}
+
+/// Print what is known about the remote `name`. With `query`, the remote repository
+/// is opened to compare its branches with ours.
+pub fn show(repo: &Repository, name: &str, query: bool) -> Result<(), Box<WitError>> {
+    let remote = self::configured(repo, name)?;
+    let abbrev = |name: &str| name.strip_prefix("refs/heads/").unwrap_or(name).to_owned();
+    let plural = |count: usize, one: &str, many: &str| if count == 1 { one.to_owned() } else { many.to_owned() };
+    // Only local repositories can be asked; anything else is shown as if not queried.
+    let query = query && remote.urls.first().is_none_or(|url| clone::is_local(url));
+    let not_queried = if query { "" } else { " (status not queried)" };
+    let upstream = match (query, remote.urls.first()) {
+        (true, Some(url)) => Some(Repository::open(None, clone::source_git_dir(url)?, false)?),
+        (true, None) => Err(config_err(format!("No URL configured for remote '{}'", name)))?,
+        (false, _) => None
+    };
+    let theirs = match &upstream {
+        Some(upstream) => reference::all(upstream)?,
+        None => Vec::new()
+    };
+    let ours = self::ref_names(repo)?;
+
+    println!("* remote {}", remote.name);
+    println!("  Fetch URL: {}", remote.urls.first().map(String::as_str).unwrap_or("(no URL)"));
+    for url in remote.push_targets() {
+        println!("  Push  URL: {}", url);
+    }
+    if remote.push_targets().is_empty() {
+        println!("  Push  URL: (no URL)");
+    }
+    let head = match &upstream {
+        Some(upstream) => reference::read_symbolic(upstream, "HEAD")?.map(|head| abbrev(&head)).unwrap_or(String::from("(unknown)")),
+        None => String::from("(not queried)")
+    };
+    println!("  HEAD branch: {}", head);
+
+    // The remote's branches next to what fetching would do with them.
+    let mut branches: Vec<(String, String)> = Vec::new();
+    if upstream.is_some() {
+        for (refname, _) in &theirs {
+            if let Some(tracking) = remote.tracking_ref(refname) {
+                let state = if ours.contains(&tracking) {
+                    String::from("tracked")
+                } else {
+                    format!("new (next fetch will store in remotes/{})", name)
+                };
+                branches.push((abbrev(refname), state));
+            }
+        }
+    }
+    for refname in &ours {
+        if !remote.tracks(refname) || reference::read_symbolic(repo, refname)?.is_some() {
+            continue;
+        }
+        let source = remote.fetch.iter().find_map(|spec| spec.map_dst(refname)).unwrap_or_default();
+        if upstream.is_none() {
+            branches.push((abbrev(&source), String::new()));
+        } else if !theirs.iter().any(|(theirs, _)| *theirs == source) {
+            branches.push((refname.clone(), String::from("stale (no longer on the remote)")));
+        }
+    }
+    branches.sort();
+    if !branches.is_empty() {
+        println!("  {}:{}", plural(branches.len(), "Remote branch", "Remote branches"), not_queried);
+        self::print_table(&branches);
+    }
+
+    // Local branches that follow one of the remote's branches.
+    let pulls = repo.conf.entries()
+        .iter()
+        .filter(|entry| entry.value.as_deref() == Some(name))
+        .filter_map(|entry| entry.key.strip_prefix("branch.")?.strip_suffix(".remote"))
+        .filter_map(|branch| {
+            let merge = repo.conf.get(&format!("branch.{}.merge", branch))?;
+            let verb = match repo.conf.get_bool(&format!("branch.{}.rebase", branch)) {
+                Ok(Some(true)) => "rebases onto remote",
+                _ => "merges with remote"
+            };
+            Some((branch.to_owned(), format!("{} {}", verb, abbrev(&merge))))
+        })
+        .collect::<Vec<(String, String)>>();
+    if !pulls.is_empty() {
+        println!("  {}:", plural(pulls.len(), "Local branch configured for pull", "Local branches configured for pull"));
+        self::print_table(&pulls);
+    }
+
+    // Without push refspecs, branches are pushed to the remote branches of the same name.
+    let mut pushes: Vec<(String, &str, String, Option<String>)> = Vec::new();
+    if remote.push.is_empty() {
+        if upstream.is_none() {
+            pushes.push((String::from("(matching)"), "pushes to", String::from("(matching)"), None));
+        }
+        for (refname, sha) in &theirs {
+            if let (Some(branch), Some(local)) = (refname.strip_prefix("refs/heads/"), reference::resolve(repo, refname).ok()) {
+                let state = self::push_state(repo, &local, Some(sha));
+                pushes.push((branch.to_owned(), "pushes to", branch.to_owned(), Some(state)));
+            }
+        }
+    }
+    for spec in &remote.push {
+        let sources = match spec.pattern {
+            true => ours.iter().filter(|refname| spec.matches_src(refname)).cloned().collect(),
+            false => vec![spec.src.clone()]
+        };
+        for src in sources {
+            let dst = match spec.pattern {
+                true => spec.map_src(&src).unwrap_or_default(),
+                false => spec.dst.clone().unwrap_or(src.clone())
+            };
+            let dst = match dst.starts_with("refs/") {
+                true => dst,
+                false => format!("refs/heads/{}", dst)
+            };
+            let state = match &upstream {
+                Some(_) => {
+                    let theirs = theirs.iter().find(|(name, _)| *name == dst).map(|(_, sha)| sha.as_str());
+                    let local = reference::dwim(repo, &src)?.and_then(|name| reference::resolve(repo, &name).ok());
+                    match local {
+                        Some(local) => Some(self::push_state(repo, &local, theirs)),
+                        None => Some(String::from("delete"))
+                    }
+                },
+                None => None
+            };
+            pushes.push((abbrev(&src), if spec.force { "forces to" } else { "pushes to" }, abbrev(&dst), state));
+        }
+    }
+    if !pushes.is_empty() {
+        let heading = plural(pushes.len(), "Local ref configured for push", "Local refs configured for push");
+        println!("  {}{}:", heading, not_queried);
+        let src_width = pushes.iter().map(|(src, ..)| src.len()).max().unwrap_or(0);
+        let dst_width = pushes.iter().map(|(_, _, dst, _)| dst.len()).max().unwrap_or(0);
+        for (src, verb, dst, state) in pushes {
+            match state {
+                Some(state) => println!("    {:<sw$} {} {:<dw$} ({})", src, verb, dst, state, sw = src_width, dw = dst_width),
+                None => println!("    {:<sw$} {} {}", src, verb, dst, sw = src_width)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What pushing `local` over the remote's `remote` would do.
+fn push_state(repo: &Repository, local: &str, remote: Option<&str>) -> String {
+    let state = match remote {
+        None => "create",
+        Some(remote) if remote == local => "up to date",
+        Some(remote) if graph::is_ancestor(repo, remote, local).unwrap_or(false) => "fast-forwardable",
+        Some(_) => "local out of date"
+    };
+    state.to_owned()
+}
+
+/// Rows of `name state`, with the names padded to line the states up.
+fn print_table(rows: &[(String, String)]) {
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, state) in rows {
+        if state.is_empty() {
+            println!("    {}", name);
+        } else {
+            println!("    {:<width$} {}", name, state, width = width);
+        }
+    }
+}
@@ -1,82 +1,229 @@
 use std::path::{PathBuf, Path};
 use std::fs;
 
-use ini::configparser::ini::Ini;
-use crate::error::{builder::*, WitError};
+use crate::{
+    config::{ self, Config },
+    lockfile::LockFile,
+    reference,
+    util,
+    error::{builder::*, WitError}
+};
+
+/// Who besides the owner may use the repository, from `init --shared`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shared {
+    Umask,
+    Group,
+    All,
+    // Exact permissions for files, as an octal number.
+    Perm(u32)
+}
+
+impl Shared {
+    pub fn parse(value: &str) -> Option<Shared> {
+        match value {
+            "umask" | "false" | "0" => Some(Shared::Umask),
+            "" | "group" | "true" | "1" => Some(Shared::Group),
+            "all" | "world" | "everybody" | "2" => Some(Shared::All),
+            octal => u32::from_str_radix(octal, 8).ok()
+                .filter(|perm| *perm <= 0o777 && perm & 0o600 == 0o600)
+                .map(Shared::Perm)
+        }
+    }
+
+    /// How `core.sharedRepository` records the mode; the umask default is not recorded.
+    fn config_value(&self) -> Option<String> {
+        match self {
+            Shared::Umask => None,
+            Shared::Group => Some(String::from("1")),
+            Shared::All => Some(String::from("2")),
+            Shared::Perm(perm) => Some(format!("0{:o}", perm))
+        }
+    }
+
+    /// Widen the permissions of the single file or directory at `path`, as git does:
+    /// directories also get execute bits wherever they are readable, and the setgid bit.
+    #[cfg(unix)]
+    pub fn apply(&self, path: &Path) -> Result<(), Box<WitError>> {
+        use std::os::unix::fs::PermissionsExt;
+        if *self == Shared::Umask {
+            return Ok(());
+        }
+        let meta = fs::symlink_metadata(path)?;
+        if meta.file_type().is_symlink() {
+            return Ok(());
+        }
+        let mode = meta.permissions().mode();
+        let mut new = match self {
+            Shared::Perm(perm) => (mode & !0o777) | perm,
+            Shared::All => mode | 0o664,
+            _ => mode | 0o660
+        };
+        if meta.is_dir() {
+            new |= (new & 0o444) >> 2;
+            new |= 0o2000;
+        } else if mode & 0o100 != 0 {
+            new |= (new & 0o444) >> 2;
+        }
+        fs::set_permissions(path, fs::Permissions::from_mode(new))?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, _path: &Path) -> Result<(), Box<WitError>> {
+        Ok(())
+    }
+}
+
+/// How `init` sets up a new repository.
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    pub bare: bool,
+    pub initial_branch: Option<String>,
+    pub template: Option<PathBuf>,
+    pub shared: Option<Shared>
+}
 
 #[derive(Debug, Clone)]
 pub struct Repository {
-    pub worktree: PathBuf,
+    // `None` in a bare repository.
+    pub worktree: Option<PathBuf>,
     pub git_dir: PathBuf,
-    pub conf: Ini
+    pub conf: Config
 }
 
 impl Repository {
     pub fn new(path: &str, force: bool) -> Result<Repository, Box<WitError>> {
-        let git_dir = Path::new(path).join(".git");
-        let mut config = Ini::new();
+        Self::open(Some(PathBuf::from(path)), Path::new(path).join(".git"), force)
+    }
+
+    /// The repository in `git_dir` with its worktree at `worktree`, if it has one.
+    pub fn open(worktree: Option<PathBuf>, git_dir: PathBuf, force: bool) -> Result<Repository, Box<WitError>> {
         let config_path = git_dir.join("config");
 
         if !(force || git_dir.is_dir()) {
-            Err(repo_creation_err(format!("{} is not a git repository", path)))?
+            Err(repo_creation_err(format!("{} is not a git repository", git_dir.display())))?
         }
 
-        if config_path.exists() && config_path.is_file() {
-            config.load(match config_path.to_str() {
-                Some(string) => string,
-                None => Err(repo_creation_err(format!("Could not load config from {:?}", config_path)))?
-            }).unwrap();
-        } else if !force {
-            Err(repo_creation_err(format!("Could not create repository in {}", path)))?
+        if !(force || config_path.is_file()) {
+            Err(repo_creation_err(format!("Could not create repository in {}", git_dir.display())))?
         }
+        let config = Config::load(Some(&git_dir))?;
 
         if !force {
-            let version = config
-                .get("core", "repositoryformatversion")
+            let version = Config::from_files(&[config_path], Some(&git_dir), false)?
+                .get_int("core.repositoryformatversion")?
                 .ok_or(
                     version_mismatch_err(format!("Could not read repository format version from config."))
-                )?.parse::<i32>()?;
+                )?;
             if version != 0 {
                 Err(version_mismatch_err(format!("Unsupported repositoryformatversion {}", version)))?
             }
         }
         Ok(Repository {
-            worktree: PathBuf::from(path),
-            git_dir: git_dir,
+            worktree,
+            git_dir,
             conf: config
         })
     }
 
+    /// The repository `GIT_DIR` names, or else the first one found in `path` or one of its
+    /// parents. The search stops below `GIT_CEILING_DIRECTORIES` and, unless
+    /// `GIT_DISCOVERY_ACROSS_FILESYSTEM` is set, at filesystem boundaries.
     pub fn find(path: &str, required: bool) -> Result<Option<Repository>, Box<WitError>> {
-        let path = fs::canonicalize(path)?;
-
-        if path.join(".git").is_dir() {
-            return Ok(
-                Some(
-                    Repository::new(
-                        path
-                            .to_str()
-                            .ok_or(path_conversion_err())?,
-                        false
-                    )?
-                )
-            )
-        }
-
-        let parent = fs::canonicalize(path.join(".."))?;
-
-        if parent == path {
-            return if required {
-                Err(repo_not_found_err(format!("No git directory in {:?}", path)))?
-            } else {
-                Ok(None)
+        let cwd = std::env::current_dir()?;
+        if let Some(git_dir) = std::env::var_os("GIT_DIR").filter(|dir| !dir.is_empty()) {
+            let git_dir = fs::canonicalize(cwd.join(&git_dir))
+                .ok()
+                .filter(|dir| Self::is_git_dir(dir))
+                .ok_or(repo_not_found_err(format!("not a git repository: '{}'", Path::new(&git_dir).display())))?;
+            // Without a worktree setting, the current directory is the top of the worktree.
+            return Ok(Some(Self::with_worktree(Some(cwd), git_dir)?));
+        }
+
+        let start = fs::canonicalize(path)?;
+        // Only the nearest ceiling above the start counts; the search stops below it.
+        let ceiling = self::ceiling_dirs().into_iter()
+            .filter(|ceiling| *ceiling != start && start.starts_with(ceiling))
+            .max_by_key(|ceiling| ceiling.components().count());
+        let across = std::env::var("GIT_DISCOVERY_ACROSS_FILESYSTEM").ok()
+            .and_then(|value| config::parse_bool(Some(&value)))
+            .unwrap_or(false);
+        let device = self::device(&start);
+        let mut dir = start.as_path();
+        loop {
+            if let Some(git_dir) = Self::dot_git(dir)? {
+                return Ok(Some(Self::with_worktree(Some(dir.to_path_buf()), git_dir)?));
+            }
+            // A bare repository, or the inside of a git directory: no worktree either way.
+            if Self::is_git_dir(dir) {
+                return Ok(Some(Self::with_worktree(None, dir.to_path_buf())?));
+            }
+            let parent = match dir.parent() {
+                Some(parent) if ceiling.as_deref() != Some(parent) => parent,
+                _ => break
+            };
+            if !across && self::device(parent) != device {
+                if required {
+                    Err(repo_not_found_err(format!(
+                        "No git directory in {:?} up to mount point {:?}; stopping at filesystem boundary (GIT_DISCOVERY_ACROSS_FILESYSTEM not set)",
+                        start, dir
+                    )))?
+                }
+                return Ok(None);
             }
+            dir = parent;
         }
+        if required {
+            Err(repo_not_found_err(format!("No git directory in {:?}", start)))?
+        }
+        Ok(None)
+    }
 
-        Self::find(
-            parent.to_str().ok_or(path_conversion_err())?,
-            required
-        )
+    /// Open a repository found at `git_dir`, with its worktree from `GIT_WORK_TREE`,
+    /// `core.worktree` (relative to `git_dir`), none if `core.bare` is set, or else `default`.
+    fn with_worktree(default: Option<PathBuf>, git_dir: PathBuf) -> Result<Repository, Box<WitError>> {
+        let mut repo = Self::open(default, git_dir, false)?;
+        let worktree = match (std::env::var_os("GIT_WORK_TREE").filter(|dir| !dir.is_empty()), repo.conf.get("core.worktree")) {
+            (Some(worktree), _) => Some(std::env::current_dir()?.join(worktree)),
+            (None, Some(worktree)) => Some(repo.git_dir.join(worktree)),
+            (None, None) => None
+        };
+        if let Some(worktree) = worktree {
+            repo.worktree = Some(fs::canonicalize(&worktree)
+                .map_err(|err| io_err(format!("cannot use {} as the worktree: {}", worktree.display(), err)))?);
+        } else if repo.conf.get_bool("core.bare")?.unwrap_or(false) {
+            repo.worktree = None;
+        }
+        Ok(repo)
+    }
+
+    /// The git directory that `dir/.git` is or, as a file with a `gitdir:` line, points to.
+    fn dot_git(dir: &Path) -> Result<Option<PathBuf>, Box<WitError>> {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Ok(Some(dot_git).filter(|dot_git| Self::is_git_dir(dot_git)));
+        }
+        if !dot_git.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&dot_git)?;
+        let target = content.trim_end().strip_prefix("gitdir: ")
+            .ok_or(repo_not_found_err(format!("invalid gitfile format: {}", dot_git.display())))?;
+        match fs::canonicalize(dir.join(target)) {
+            Ok(git_dir) if Self::is_git_dir(&git_dir) => Ok(Some(git_dir)),
+            _ => Err(repo_not_found_err(format!("not a git repository: {}", target)))
+        }
+    }
+
+    /// The worktree, or an error in a bare repository.
+    pub fn worktree(&self) -> Result<&Path, Box<WitError>> {
+        self.worktree.as_deref().ok_or_else(bare_repo_err)
+    }
+
+    /// Whether `dir` has what a git directory needs: `HEAD`, `objects` and `refs`.
+    pub fn is_git_dir(dir: &Path) -> bool {
+        dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
     }
 
     pub fn path(base: &Repository, paths: Vec<&str>) -> PathBuf {
@@ -109,63 +256,196 @@ impl Repository {
                 Err(io_err(format!("{:?} is not a directory.", path)))
             }
         } else if mkdir {
-            return match fs::create_dir_all(&path) {
-                Ok(_) => Ok(path),
-                Err(err) => Err(Box::<WitError>::from(err))
-            }
+            repo.create_dirs(&path)?;
+            Ok(path)
         } else {
-            return Err(io_err(format!("Failed to create {:?}", path)))
+            Err(io_err(format!("Failed to create {:?}", path)))
         }
     }
 
-    pub fn create(path: &str) -> Result<Self, Box<WitError>> {
-        let repo = Self::new(path, true)?;
+    /// `fs::create_dir_all`, with each new directory shared like the repository.
+    pub fn create_dirs(&self, path: &Path) -> Result<(), Box<WitError>> {
+        let missing = path.ancestors().take_while(|dir| !dir.exists()).map(Path::to_path_buf).collect::<Vec<PathBuf>>();
+        fs::create_dir_all(path)?;
+        let shared = self.shared();
+        for dir in missing.iter().rev() {
+            shared.apply(dir)?;
+        }
+        Ok(())
+    }
 
-        if repo.worktree.exists() {
-            if !repo.worktree.is_dir() {
-                Err(repo_creation_err(format!("{} is not a directory.", path)))?
-            }
-            if !repo.worktree.read_dir().map(|mut i| i.next().is_none()).unwrap_or(false) {
-                Err(repo_creation_err(format!("Directory {} is not empty.", path)))?
+    /// Lock a file in the git directory, which gets the permissions of the repository's
+    /// sharing mode once committed.
+    pub fn lock(&self, path: &Path) -> Result<LockFile, Box<WitError>> {
+        if let Some(parent) = path.parent() {
+            self.create_dirs(parent)?;
+        }
+        Ok(LockFile::acquire(path)?.shared(self.shared()))
+    }
+
+    /// How widely the repository is shared, from `core.sharedRepository`.
+    pub fn shared(&self) -> Shared {
+        self.conf.get("core.sharedrepository").and_then(|value| Shared::parse(&value)).unwrap_or(Shared::Umask)
+    }
+
+    /// Create a repository at `path`: a worktree with a `.git` directory, or with `bare`
+    /// just the git directory itself. An existing repository is reinitialized instead,
+    /// which only adds missing template files and updates the sharing mode.
+    /// Returns the repository and whether it already existed.
+    pub fn create(path: &str, opts: &InitOptions) -> Result<(Self, bool), Box<WitError>> {
+        let top = PathBuf::from(path);
+        let git_dir = if opts.bare { top.clone() } else { top.join(".git") };
+        let repo = Self::open(if opts.bare { None } else { Some(top.clone()) }, git_dir.clone(), true)?;
+        let global = Config::load(None)?;
+
+        if top.exists() && !top.is_dir() {
+            Err(repo_creation_err(format!("{} is not a directory.", path)))?
+        }
+        let reinit = Self::is_git_dir(&git_dir);
+        let branch = opts.initial_branch.clone()
+            .or(global.get("init.defaultbranch"))
+            .unwrap_or(String::from("main"));
+        if !reinit && !reference::is_valid_name(&format!("refs/heads/{}", branch)) {
+            Err(repo_creation_err(format!("invalid initial branch name: '{}'", branch)))?
+        }
+        fs::create_dir_all(&git_dir)?;
+
+        let template = match &opts.template {
+            Some(template) => Some(template.clone()),
+            None => std::env::var_os("GIT_TEMPLATE_DIR").map(PathBuf::from)
+                .or(global.get("init.templatedir").map(|dir| util::expand_user(&dir)))
+        };
+        if let Some(template) = template.filter(|dir| !dir.as_os_str().is_empty()) {
+            self::copy_template(&template, &git_dir)?;
+        }
+
+        if reinit {
+            if let Some(branch) = &opts.initial_branch {
+                eprintln!("warning: re-init: ignored --initial-branch={}", branch);
             }
         } else {
-            if let Err(e) = fs::create_dir_all(&repo.worktree) {
-                Err(Box::<WitError>::from(e))?
+            Self::dir(&repo, vec!["branches"], true)?;
+            Self::dir(&repo, vec!["objects"], true)?;
+            Self::dir(&repo, vec!["refs", "tags"], true)?;
+            Self::dir(&repo, vec!["refs", "heads"], true)?;
+
+            // .git/description
+            let description = Self::path(&repo, vec!["description"]);
+            if !description.exists() {
+                fs::write(description, "Unnamed repository; edit this file 'description' to name the repository.\n")?;
             }
-        }
 
-        Self::dir(&repo, vec!["branches"], true)?;
-        Self::dir(&repo, vec!["objects"], true)?;
-        Self::dir(&repo, vec!["refs", "tags"], true)?;
-        Self::dir(&repo, vec!["refs", "heads"], true)?;
+            // .git/HEAD
+            fs::write(Self::file(&repo, vec!["HEAD"], true)?, format!("ref: refs/heads/{}\n", branch))?;
 
-        // .git/description
-        if let Err(err) = fs::write(Self::file(&repo, vec!["description"], true)?, "Unnamed repository; edit this file 'description' to name the repository.\n") {
-            Err(Box::<WitError>::from(err))?
+            // .git/config
+            fs::write(Self::file(&repo, vec!["config"], true)?, Self::default_config(opts.bare))?;
         }
 
-        // .git/HEAD
-        if let Err(err) = fs::write(Self::file(&repo, vec!["HEAD"], true)?, "ref: refs/heads/main\n") {
-            Err(Box::<WitError>::from(err))?
+        if let Some(shared) = opts.shared {
+            if let Some(value) = shared.config_value() {
+                config::set(&git_dir.join("config"), "core.sharedrepository", &value)?;
+            }
+            self::share(&git_dir, shared)?;
         }
 
-        // .git/config
-        if let Err(err) = Self::default_config().write(
-            Self::file(&repo, vec!["config"], true)?
-                .to_str()
-                .ok_or(repo_creation_err(format!("Error opening config file.")))?
-        ) {
-            Err(Box::<WitError>::from(err))?
-        }
+        let repo = if opts.bare { Self::open(None, top, false)? } else { Self::new(path, false)? };
+        Ok((repo, reinit))
+    }
 
-        Ok(repo)
+    /// A new repository `wit-<name>-<pid>` in the temporary directory, for tests.
+    #[cfg(test)]
+    pub fn temp(name: &str) -> Repository {
+        let path = std::env::temp_dir().join(format!("wit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        Self::create(path.to_str().unwrap(), &InitOptions::default()).unwrap().0
+    }
+
+    /// `Name <email>` used for new commits, tags and reflog entries.
+    /// The `GIT_COMMITTER_*` environment variables win over `user.*` config.
+    pub fn identity(&self) -> String {
+        let user = std::env::var("USER").unwrap_or(String::from("unknown"));
+        let name = std::env::var("GIT_COMMITTER_NAME").ok()
+            .or(self.conf.get("user.name"))
+            .unwrap_or(user.clone());
+        let email = std::env::var("GIT_COMMITTER_EMAIL").ok()
+            .or(self.conf.get("user.email"))
+            .unwrap_or(format!("{}@{}", user, std::env::var("HOSTNAME").unwrap_or(String::from("localhost"))));
+        format!("{} <{}>", name, email)
     }
 
-    fn default_config() -> Ini {
-        let mut config = Ini::new();
-        config.set("core", "repositoryformatversion", Some(String::from("0")));
-        config.set("core", "filemode", Some(String::from("false")));
-        config.set("core", "bare", Some(String::from("false")));
-        config
+    /// Editor command for messages and todo lists, looked up the way git does.
+    pub fn editor(&self) -> String {
+        std::env::var("GIT_EDITOR").ok()
+            .or(self.conf.get("core.editor"))
+            .or(std::env::var("VISUAL").ok())
+            .or(std::env::var("EDITOR").ok())
+            .unwrap_or(String::from("vi"))
     }
-}
\ No newline at end of file
+
+    fn default_config(bare: bool) -> String {
+        format!(
+            "[core]\n\trepositoryformatversion = 0\n\tfilemode = {}\n\tbare = {}\n",
+            if cfg!(unix) { "true" } else { "false" },
+            bare
+        )
+    }
+}
+
+/// `GIT_CEILING_DIRECTORIES`: absolute directories that discovery does not go up into.
+fn ceiling_dirs() -> Vec<PathBuf> {
+    std::env::var_os("GIT_CEILING_DIRECTORIES")
+        .map(|dirs| std::env::split_paths(&dirs).collect::<Vec<PathBuf>>())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| fs::canonicalize(&dir).unwrap_or(dir))
+        .collect()
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).map(|meta| meta.dev()).ok()
+}
+
+#[cfg(not(unix))]
+fn device(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Copy the files of a template directory into a git directory, leaving alone files
+/// that are already there. The template's own `config` is not used.
+fn copy_template(template: &Path, git_dir: &Path) -> Result<(), Box<WitError>> {
+    if !template.is_dir() {
+        eprintln!("warning: templates not found in {}", template.display());
+        return Ok(());
+    }
+    let mut dirs = vec![(template.to_path_buf(), git_dir.to_path_buf())];
+    while let Some((from, to)) = dirs.pop() {
+        fs::create_dir_all(&to)?;
+        for entry in fs::read_dir(&from)? {
+            let entry = entry?;
+            let dest = to.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push((entry.path(), dest));
+            } else if fs::symlink_metadata(&dest).is_err() && !(from == template && entry.file_name() == "config") {
+                fs::copy(entry.path(), &dest)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Widen the permissions of `path` and everything in it the way `shared` asks.
+fn share(path: &Path, shared: Shared) -> Result<(), Box<WitError>> {
+    if shared == Shared::Umask {
+        return Ok(());
+    }
+    if fs::symlink_metadata(path)?.is_dir() {
+        for entry in fs::read_dir(path)? {
+            self::share(&entry?.path(), shared)?;
+        }
+    }
+    shared.apply(path)
+}
@@ -0,0 +1,415 @@
+use std::fs;
+
+use crate::{
+    repository::Repository,
+    object,
+    commit,
+    graph,
+    reference,
+    index::Index,
+    tree::{self, Entries},
+    worktree,
+    util,
+    merge::{self, Labels},
+    error::{WitError, builder::*}
+};
+
+/// What to do with each commit of a cherry-pick or revert.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Pick,
+    Revert
+}
+
+impl Action {
+    /// The command, as shown to the user.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert"
+        }
+    }
+
+    /// The pseudo-ref naming the commit being applied when a step stops.
+    pub fn head_file(&self) -> &'static str {
+        match self {
+            Action::Pick => "CHERRY_PICK_HEAD",
+            Action::Revert => "REVERT_HEAD"
+        }
+    }
+
+    fn todo_verb(&self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert"
+        }
+    }
+
+    fn parse(verb: &str) -> Option<Action> {
+        match verb {
+            "pick" | "p" => Some(Action::Pick),
+            "revert" => Some(Action::Revert),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Only update the index and worktree (`-n`).
+    pub no_commit: bool,
+    /// Append "(cherry picked from commit ...)" to picked messages (`-x`).
+    pub record_origin: bool,
+    /// Parent number to diff merge commits against (`-m`).
+    pub mainline: Option<usize>
+}
+
+impl Options {
+    /// Same layout as git's `sequencer/opts`, a small config file.
+    fn serialize(&self) -> String {
+        let mut ret = String::from("[options]\n");
+        if self.no_commit {
+            ret.push_str("\tno-commit = true\n");
+        }
+        if self.record_origin {
+            ret.push_str("\trecord-origin = true\n");
+        }
+        if let Some(mainline) = self.mainline {
+            ret.push_str(&format!("\tmainline = {}\n", mainline));
+        }
+        ret
+    }
+
+    fn parse(data: &str) -> Options {
+        let mut ret = Options::default();
+        for line in data.lines() {
+            match line.split_once('=').map(|(key, value)| (key.trim(), value.trim())) {
+                Some(("no-commit", value)) => ret.no_commit = value == "true",
+                Some(("record-origin", value)) => ret.record_origin = value == "true",
+                Some(("mainline", value)) => ret.mainline = value.parse().ok(),
+                _ => ()
+            }
+        }
+        ret
+    }
+}
+
+fn path(repo: &Repository, name: &str) -> std::path::PathBuf {
+    Repository::path(repo, vec!["sequencer", name])
+}
+
+/// Whether a cherry-pick or revert has stopped and waits for `--continue`.
+pub fn in_progress(repo: &Repository) -> bool {
+    Repository::path(repo, vec!["sequencer"]).is_dir()
+        || [Action::Pick, Action::Revert].iter().any(|action| Repository::path(repo, vec![action.head_file()]).is_file())
+}
+
+/// Commits named on the command line, in the order they are applied. Single commits
+/// are used as given; ranges (`A..B`, `^A B`) are walked, oldest first for
+/// cherry-picks and newest first for reverts, like git.
+pub fn revisions(repo: &Repository, specs: &[&str], action: Action) -> Result<Vec<String>, Box<WitError>> {
+    let find = |name: &str| object::find(repo, name, Some("commit"), true);
+    if !specs.iter().any(|spec| spec.contains("..") || spec.starts_with('^')) {
+        return specs.iter().map(|spec| find(spec)).collect();
+    }
+
+    let (mut include, mut exclude) = (Vec::new(), Vec::new());
+    for spec in specs {
+        if let Some((from, to)) = spec.split_once("..") {
+            exclude.push(find(if from.is_empty() { "HEAD" } else { from })?);
+            include.push(find(if to.is_empty() { "HEAD" } else { to })?);
+        } else if let Some(hidden) = spec.strip_prefix('^') {
+            exclude.push(find(hidden)?);
+        } else {
+            include.push(find(spec)?);
+        }
+    }
+    let mut ret = graph::range(repo, &include, &exclude)?;
+    if action == Action::Pick {
+        ret.reverse();
+    }
+    if ret.is_empty() {
+        Err(unknown_reference_err(String::from("empty commit set passed")))?
+    }
+    Ok(ret)
+}
+
+/// Apply `commits` one after another. Returns `false` when a step stopped on
+/// conflicts; the remaining steps are kept in `.git/sequencer` for `--continue`.
+pub fn start(repo: &Repository, action: Action, commits: &[String], opts: &Options) -> Result<bool, Box<WitError>> {
+    if self::in_progress(repo) {
+        Err(merge_conflict_err(String::from(
+            "A cherry-pick or revert is already in progress.\nUse --continue, --skip or --abort."
+        )))?
+    }
+    if merge::state(repo)?.is_some() {
+        Err(merge_conflict_err(String::from("You have not concluded your merge (MERGE_HEAD exists).")))?
+    }
+    let head = reference::resolve(repo, "HEAD")?;
+    let index = Index::read(repo)?;
+    if !index.conflicts().is_empty() {
+        Err(merge_conflict_err(format!("{} is not possible because you have unmerged files.", action.command())))?
+    }
+    if !opts.no_commit && index.tree_entries() != tree::flatten(repo, &commit::tree_of(repo, &head)?)? {
+        Err(dirty_worktree_err(format!(
+            "Your local changes would be overwritten by {}.\nPlease commit your changes or stash them to proceed.",
+            action.command()
+        )))?
+    }
+
+    for sha in commits {
+        self::parent(repo, sha, opts)?;
+    }
+
+    fs::create_dir_all(Repository::path(repo, vec!["sequencer"]))?;
+    fs::write(self::path(repo, "head"), format!("{}\n", head))?;
+    fs::write(self::path(repo, "opts"), opts.serialize())?;
+    self::write_todo(repo, &commits.iter().map(|sha| (action, sha.clone())).collect::<Vec<(Action, String)>>())?;
+    self::proceed(repo)
+}
+
+fn read_todo(repo: &Repository) -> Result<Vec<(Action, String)>, Box<WitError>> {
+    let path = self::path(repo, "todo");
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let mut ret = Vec::new();
+    for line in fs::read_to_string(path)?.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('#')) {
+        let mut words = line.split_whitespace();
+        let action = words.next().and_then(Action::parse).ok_or(
+            malformed_object_err(format!("Invalid line in sequencer todo: {}", line))
+        )?;
+        let sha = object::find(repo, words.next().unwrap_or(""), Some("commit"), true)?;
+        ret.push((action, sha));
+    }
+    Ok(ret)
+}
+
+fn write_todo(repo: &Repository, todo: &[(Action, String)]) -> Result<(), Box<WitError>> {
+    let mut data = String::new();
+    for (action, sha) in todo {
+        let commit = commit::read(repo, sha)?;
+        data.push_str(&format!("{} {} {}\n", action.todo_verb(), util::abbrev(sha), commit.subject()));
+    }
+    fs::write(self::path(repo, "todo"), data)?;
+    Ok(())
+}
+
+fn read_opts(repo: &Repository) -> Result<Options, Box<WitError>> {
+    let path = self::path(repo, "opts");
+    Ok(if path.is_file() { Options::parse(&fs::read_to_string(path)?) } else { Options::default() })
+}
+
+/// Work through the todo list until it is empty or a step stops.
+fn proceed(repo: &Repository) -> Result<bool, Box<WitError>> {
+    let opts = self::read_opts(repo)?;
+    let mut todo = self::read_todo(repo)?;
+    while !todo.is_empty() {
+        let (action, sha) = todo[0].clone();
+        if !self::apply(repo, action, &sha, &opts)? {
+            self::write_todo(repo, &todo)?;
+            return Ok(false);
+        }
+        todo.remove(0);
+        self::write_todo(repo, &todo)?;
+    }
+    fs::remove_dir_all(Repository::path(repo, vec!["sequencer"]))?;
+    Ok(true)
+}
+
+/// The message a replayed commit gets.
+fn message(repo: &Repository, action: Action, sha: &str, opts: &Options) -> Result<String, Box<WitError>> {
+    let commit = commit::read(repo, sha)?;
+    Ok(match action {
+        Action::Pick if opts.record_origin => {
+            format!("{}\n\n(cherry picked from commit {})\n", commit.message().trim_end(), sha)
+        },
+        Action::Pick => commit.message().to_owned(),
+        Action::Revert => {
+            let parents = commit.parents();
+            let reverts = match opts.mainline.and_then(|m| parents.get(m - 1)) {
+                Some(parent) if parents.len() > 1 => format!(
+                    "This reverts commit {}, reversing\nchanges made to {}.", sha, parent
+                ),
+                _ => format!("This reverts commit {}.", sha)
+            };
+            format!("Revert \"{}\"\n\n{}\n", commit.subject(), reverts)
+        }
+    })
+}
+
+/// The parent whose changes a commit is compared against: its only parent,
+/// or for merges the one picked with `-m`.
+fn parent(repo: &Repository, sha: &str, opts: &Options) -> Result<Option<String>, Box<WitError>> {
+    let parents = commit::read(repo, sha)?.parents();
+    match (parents.len(), opts.mainline) {
+        (0, None) => Ok(None),
+        (1, None) => Ok(Some(parents[0].clone())),
+        (_, None) => Err(merge_conflict_err(format!(
+            "commit {} is a merge but no -m option was given.", sha
+        ))),
+        (0 | 1, Some(_)) => Err(merge_conflict_err(format!(
+            "mainline was specified but commit {} is not a merge.", sha
+        ))),
+        (n, Some(m)) => Ok(Some(parents.get(m - 1).cloned().ok_or(merge_conflict_err(format!(
+            "commit {} does not have parent {} (it has {}).", sha, m, n
+        )))?))
+    }
+}
+
+/// Replay a single commit on top of the index. Returns `false` when it stopped.
+fn apply(repo: &Repository, action: Action, sha: &str, opts: &Options) -> Result<bool, Box<WitError>> {
+    let commit = commit::read(repo, sha)?;
+    let short = format!("{}... {}", util::abbrev(sha), commit.subject());
+
+    let parent = self::parent(repo, sha, opts)?;
+    let parent_entries = match &parent {
+        Some(parent) => tree::flatten(repo, &commit::tree_of(repo, parent)?)?,
+        None => Entries::new()
+    };
+    let commit_entries = tree::flatten(repo, &commit::tree_of(repo, sha)?)?;
+    let parent_label = format!("parent of {}", short);
+    let (base, theirs, labels) = match action {
+        Action::Pick => (parent_entries, commit_entries, Labels { base: &parent_label, ours: "HEAD", theirs: &short }),
+        Action::Revert => (commit_entries, parent_entries, Labels { base: &short, ours: "HEAD", theirs: &parent_label })
+    };
+
+    let result = merge::apply(repo, &base, &theirs, &labels, action.command())?;
+
+    let message = self::message(repo, action, sha, opts)?;
+    let stop = |message: &str| -> Result<bool, Box<WitError>> {
+        fs::write(Repository::path(repo, vec![action.head_file()]), format!("{}\n", sha))?;
+        fs::write(Repository::path(repo, vec!["MERGE_MSG"]), message)?;
+        Ok(false)
+    };
+
+    if !result.clean() {
+        let conflicts = result.conflicts.iter().map(|path| format!("#\t{}\n", path)).collect::<String>();
+        println!("error: could not {} {}", if action == Action::Pick { "apply" } else { "revert" }, short);
+        println!("hint: After resolving the conflicts, mark them as resolved in the index, then run");
+        println!("hint: \"wit {} --continue\".", action.command());
+        println!("hint: You can instead skip this commit with \"wit {} --skip\".", action.command());
+        println!("hint: To abort and get back to the state before \"wit {}\",", action.command());
+        println!("hint: run \"wit {} --abort\".", action.command());
+        return stop(&format!("{}\n# Conflicts:\n{}", message, conflicts));
+    }
+    if opts.no_commit {
+        return Ok(true);
+    }
+
+    let head = reference::resolve(repo, "HEAD")?;
+    let tree = result.index.write_tree(repo)?;
+    if tree == commit::tree_of(repo, &head)? {
+        println!("The previous {} is now empty, possibly due to conflict resolution.", action.command());
+        println!("Use \"wit {} --skip\" to move on to the next commit.", action.command());
+        return stop(&message);
+    }
+    self::commit(repo, action, sha, &tree, &message)?;
+    Ok(true)
+}
+
+/// Record the result of a step on top of HEAD, keeping the author of picked commits.
+fn commit(repo: &Repository, action: Action, sha: &str, tree: &str, message: &str) -> Result<(), Box<WitError>> {
+    let head = reference::resolve(repo, "HEAD")?;
+    let original = commit::read(repo, sha)?;
+    let author = match action {
+        Action::Pick => original.header("author"),
+        Action::Revert => None
+    };
+    let new = commit::create(repo, tree, &[head], message, author)?;
+    let subject = message.lines().next().unwrap_or("");
+    reference::update(repo, "HEAD", &new, &format!("{}: {}", action.command(), subject))?;
+    let branch = match reference::target(repo, "HEAD")?.as_str() {
+        "HEAD" => String::from("detached HEAD"),
+        target => reference::shorten(target).to_owned()
+    };
+    println!("[{} {}] {}", branch, util::abbrev(&new), subject);
+    Ok(())
+}
+
+/// The step that stopped, from `CHERRY_PICK_HEAD` or `REVERT_HEAD`.
+fn stopped(repo: &Repository) -> Result<Option<(Action, String)>, Box<WitError>> {
+    for action in [Action::Pick, Action::Revert] {
+        let path = Repository::path(repo, vec![action.head_file()]);
+        if path.is_file() {
+            return Ok(Some((action, fs::read_to_string(path)?.trim().to_owned())));
+        }
+    }
+    Ok(None)
+}
+
+/// Remove the files that mark a stopped cherry-pick or revert.
+pub fn clear_stopped(repo: &Repository) -> Result<(), Box<WitError>> {
+    for name in [Action::Pick.head_file(), Action::Revert.head_file(), "MERGE_MSG"] {
+        let path = Repository::path(repo, vec![name]);
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// `--continue` commits the resolved step and goes on; `--skip` drops it instead.
+pub fn resume(repo: &Repository, skip: bool) -> Result<bool, Box<WitError>> {
+    if !self::in_progress(repo) {
+        Err(merge_conflict_err(String::from("no cherry-pick or revert in progress")))?
+    }
+    let opts = self::read_opts(repo)?;
+    let mut todo = self::read_todo(repo)?;
+
+    match self::stopped(repo)? {
+        Some(_) if skip => {
+            worktree::reset(repo, &commit::tree_of(repo, &reference::resolve(repo, "HEAD")?)?)?;
+        },
+        Some((action, sha)) => {
+            let index = Index::read(repo)?;
+            if !index.conflicts().is_empty() {
+                Err(merge_conflict_err(format!(
+                    "{} is not possible because you have unmerged files.", action.command()
+                )))?
+            }
+            if !opts.no_commit {
+                let message = fs::read_to_string(Repository::path(repo, vec!["MERGE_MSG"])).unwrap_or_default();
+                self::commit(repo, action, &sha, &index.write_tree(repo)?, &merge::clean_message(&message))?;
+            }
+        },
+        None if skip => Err(merge_conflict_err(String::from("There is nothing to skip.")))?,
+        // The stopped step was committed by hand.
+        None => ()
+    }
+    self::clear_stopped(repo)?;
+    if !todo.is_empty() {
+        todo.remove(0);
+    }
+
+    if !Repository::path(repo, vec!["sequencer"]).is_dir() {
+        return Ok(true);
+    }
+    self::write_todo(repo, &todo)?;
+    self::proceed(repo)
+}
+
+/// Go back to the HEAD and index from before the cherry-pick or revert started.
+pub fn abort(repo: &Repository) -> Result<(), Box<WitError>> {
+    if !self::in_progress(repo) {
+        Err(merge_conflict_err(String::from("no cherry-pick or revert in progress")))?
+    }
+    let head_path = self::path(repo, "head");
+    let original = if head_path.is_file() {
+        fs::read_to_string(head_path)?.trim().to_owned()
+    } else {
+        reference::resolve(repo, "HEAD")?
+    };
+    let action = self::stopped(repo)?.map(|(action, _)| action).unwrap_or(Action::Pick);
+
+    worktree::reset(repo, &commit::tree_of(repo, &original)?)?;
+    if reference::resolve(repo, "HEAD")? != original {
+        reference::update(repo, "HEAD", &original, &format!("{}: aborting", action.command()))?;
+    }
+    self::clear_stopped(repo)?;
+    let sequencer = Repository::path(repo, vec!["sequencer"]);
+    if sequencer.is_dir() {
+        fs::remove_dir_all(sequencer)?;
+    }
+    Ok(())
+}
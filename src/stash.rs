@@ -0,0 +1,390 @@
+use crate::{
+    repository::Repository,
+    object::{self, WitObject},
+    commit,
+    reference::{self, Transaction},
+    reflog,
+    index::{Index, IndexEntry},
+    tree::{self, Entries},
+    worktree,
+    attr::Attributes,
+    util,
+    diff,
+    merge::{self, Labels},
+    error::{WitError, builder::*}
+};
+
+const STASH_REF: &str = "refs/stash";
+
+pub struct Options<'a> {
+    pub message: Option<&'a str>,
+    pub keep_index: bool,
+    pub include_untracked: bool
+}
+
+/// The stash entry number of `stash@{n}`, or of a plain `n`.
+pub fn parse_selector(selector: Option<&str>) -> Result<usize, Box<WitError>> {
+    let selector = selector.unwrap_or("0");
+    let n = selector.strip_prefix("stash@{").and_then(|rest| rest.strip_suffix('}')).unwrap_or(selector);
+    n.parse::<usize>().map_err(|_| unknown_reference_err(format!("{} is not a valid reference", selector)))
+}
+
+/// `(w, b, i, u)`: the stash commit, the commit it was made on, its index commit and its
+/// untracked-files commit, if any.
+fn read(repo: &Repository, n: usize) -> Result<(String, String, String, Option<String>), Box<WitError>> {
+    let entries = reflog::read(repo, STASH_REF)?;
+    if entries.is_empty() {
+        Err(unknown_reference_err(String::from("No stash entries found.")))?
+    }
+    let sha = reflog::nth(repo, STASH_REF, n).map_err(
+        |_| unknown_reference_err(format!("stash@{{{}}} is not a valid reference", n))
+    )?;
+    let parents = commit::read(repo, &sha)?.parents();
+    if parents.len() < 2 {
+        Err(malformed_object_err(format!("'{}' is not a stash-like commit", sha)))?
+    }
+    Ok((sha, parents[0].clone(), parents[1].clone(), parents.get(2).cloned()))
+}
+
+/// Save local changes as a stash entry and go back to a clean worktree.
+pub fn push(repo: &Repository, opts: &Options) -> Result<(), Box<WitError>> {
+    let head = reference::resolve(repo, "HEAD").map_err(
+        |_| unknown_reference_err(String::from("You do not have the initial commit yet"))
+    )?;
+    let head_tree = commit::tree_of(repo, &head)?;
+    let index = Index::read(repo)?;
+    if !index.conflicts().is_empty() {
+        Err(merge_conflict_err(String::from("could not save index tree: you have unmerged paths")))?
+    }
+
+    // The worktree state of tracked files, with fresh blobs for every modified file.
+    let mut work = index.tree_entries();
+    let mut modified = false;
+    let mut attrs = Attributes::new(repo)?;
+    for entry in index.entries() {
+        if !worktree::is_modified(repo, &mut attrs, entry)? {
+            continue;
+        }
+        modified = true;
+        match worktree::store_path(repo, &mut attrs, &entry.file_path)? {
+            Some(found) => { work.insert(entry.file_path.clone(), found); },
+            None => { work.remove(&entry.file_path); }
+        }
+    }
+    let untracked = if opts.include_untracked { worktree::untracked(repo, &index)? } else { Vec::new() };
+    let index_tree = index.write_tree(repo)?;
+    if !modified && untracked.is_empty() && index_tree == head_tree {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let branch = match reference::read_symbolic(repo, "HEAD")? {
+        Some(name) => reference::shorten(&name).to_owned(),
+        None => String::from("(no branch)")
+    };
+    let base = format!("{}: {} {}", branch, util::abbrev(&head), commit::read(repo, &head)?.subject());
+    let index_commit = commit::create(repo, &index_tree, std::slice::from_ref(&head), &format!("index on {}", base), None)?;
+    let mut parents = vec![head.clone(), index_commit];
+    if !untracked.is_empty() {
+        let mut entries = Entries::new();
+        for path in &untracked {
+            if let Some(found) = worktree::store_path(repo, &mut attrs, path)? {
+                entries.insert(path.clone(), found);
+            }
+        }
+        let tree = tree::write(repo, &entries)?;
+        parents.push(commit::create(repo, &tree, &[], &format!("untracked files on {}", base), None)?);
+    }
+    let message = match opts.message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", base)
+    };
+    let stash = commit::create(repo, &tree::write(repo, &work)?, &parents, &message, None)?;
+
+    // refs/stash always keeps a log, since the log is the stack.
+    reflog::create(repo, STASH_REF)?;
+    reference::update(repo, STASH_REF, &stash, &message)?;
+    println!("Saved working directory and index state {}", message);
+
+    for path in &untracked {
+        worktree::remove_file(repo, path)?;
+    }
+    worktree::hard_reset(repo, &head_tree)?;
+    if opts.keep_index {
+        let current = Index::read(repo)?;
+        let mut index = Index::from_tree(repo, &index_tree)?;
+        worktree::switch(repo, &current, &tree::flatten(repo, &index_tree)?, &mut index, true, "stash")?;
+        index.write(repo)?;
+    }
+    Ok(())
+}
+
+/// Reapply stash entry `n` on top of the current worktree. Returns `false` on conflicts.
+pub fn apply(repo: &Repository, n: usize) -> Result<bool, Box<WitError>> {
+    let top = repo.worktree()?;
+    let (stash, base, _, untracked) = self::read(repo, n)?;
+    let original = Index::read(repo)?;
+    if !original.conflicts().is_empty() {
+        Err(merge_conflict_err(String::from("Cannot apply a stash in the middle of a merge")))?
+    }
+
+    // Untracked files must not clobber anything, and only come back once the merge went through.
+    let untracked = match untracked {
+        Some(untracked) => tree::flatten(repo, &commit::tree_of(repo, &untracked)?)?,
+        None => Entries::new()
+    };
+    let check_untracked = || -> Result<(), Box<WitError>> {
+        let existing = untracked.keys()
+            .filter(|path| top.join(path).symlink_metadata().is_ok())
+            .map(|path| format!("{} already exists, no checkout", path))
+            .collect::<Vec<String>>();
+        if !existing.is_empty() {
+            Err(dirty_worktree_err(format!("{}\ncould not restore untracked files from stash", existing.join("\n"))))?
+        }
+        Ok(())
+    };
+    check_untracked()?;
+
+    let base_tree = tree::flatten(repo, &commit::tree_of(repo, &base)?)?;
+    let stash_tree = tree::flatten(repo, &commit::tree_of(repo, &stash)?)?;
+    let labels = Labels { base: "Stash base", ours: "Updated upstream", theirs: "Stashed changes" };
+    let result = merge::apply(repo, &base_tree, &stash_tree, &labels, "merge")?;
+    check_untracked()?;
+    let mut attrs = Attributes::new(repo)?;
+    for (path, (mode, sha)) in &untracked {
+        worktree::checkout_file(repo, &mut attrs, path, mode, sha)?;
+    }
+    if !result.clean() {
+        return Ok(false);
+    }
+
+    // Changes come back unstaged, except for files the stash adds.
+    let mut index = original;
+    for entry in result.index.entries() {
+        if index.entry(&entry.file_path, 0).is_none() {
+            index.add(IndexEntry::new(&entry.file_path, &entry.mode_str(), &entry.hash, 0)?);
+        }
+    }
+    index.write(repo)?;
+    Ok(true)
+}
+
+/// Remove stash entry `n`, moving `refs/stash` when the top entry goes away.
+pub fn drop(repo: &Repository, n: usize) -> Result<(), Box<WitError>> {
+    let (stash, ..) = self::read(repo, n)?;
+    let mut entries = reflog::read(repo, STASH_REF)?;
+    let idx = entries.len() - 1 - n;
+    let removed = entries.remove(idx);
+    // Keep the chain of old values intact across the gap.
+    if let Some(next) = entries.get_mut(idx) {
+        next.old = removed.old;
+    }
+
+    match entries.last() {
+        None => { reference::delete(repo, STASH_REF)?; },
+        Some(top) => {
+            if n == 0 {
+                let mut transaction = Transaction::new(repo);
+                transaction.update(STASH_REF, &top.new, Some(&stash), false, "");
+                transaction.commit()?;
+            }
+            reflog::write(repo, STASH_REF, &entries)?;
+        }
+    }
+    println!("Dropped refs/stash@{{{}}} ({})", n, stash);
+    Ok(())
+}
+
+/// `apply` followed by `drop` when the stash applied cleanly.
+pub fn pop(repo: &Repository, n: usize) -> Result<bool, Box<WitError>> {
+    if !self::apply(repo, n)? {
+        println!("The stash entry is kept in case you need it again.");
+        return Ok(false);
+    }
+    self::drop(repo, n)?;
+    Ok(true)
+}
+
+pub fn list(repo: &Repository) -> Result<(), Box<WitError>> {
+    for (n, entry) in reflog::read(repo, STASH_REF)?.iter().rev().enumerate() {
+        println!("stash@{{{}}}: {}", n, entry.message);
+    }
+    Ok(())
+}
+
+pub fn clear(repo: &Repository) -> Result<(), Box<WitError>> {
+    if reference::read(repo, STASH_REF)?.is_some() {
+        reference::delete(repo, STASH_REF)?;
+    }
+    reflog::remove(repo, STASH_REF)
+}
+
+/// A diffstat of the changes recorded in stash entry `n`.
+pub fn show(repo: &Repository, n: usize) -> Result<(), Box<WitError>> {
+    let (stash, base, ..) = self::read(repo, n)?;
+    let before = tree::flatten(repo, &commit::tree_of(repo, &base)?)?;
+    let after = tree::flatten(repo, &commit::tree_of(repo, &stash)?)?;
+    let blob = |entry: Option<&(String, String)>| -> Result<Vec<u8>, Box<WitError>> {
+        match entry {
+            Some((_, sha)) => match object::read(repo, sha)? {
+                WitObject::BlobObject(blob) => Ok(blob.data().to_vec()),
+                _ => Ok(Vec::new())
+            },
+            None => Ok(Vec::new())
+        }
+    };
+
+    let mut rows = Vec::new();
+    for path in before.keys().chain(after.keys().filter(|path| !before.contains_key(*path))) {
+        let (old, new) = (before.get(path), after.get(path));
+        if old == new {
+            continue;
+        }
+        let (a, b) = (blob(old)?, blob(new)?);
+        let (added, removed) = diff::hunks(&diff::lines(&a), &diff::lines(&b))
+            .iter()
+            .fold((0, 0), |(added, removed), hunk| (added + hunk.b_end - hunk.b_start, removed + hunk.a_end - hunk.a_start));
+        rows.push((path.clone(), added, removed));
+    }
+    rows.sort();
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let name_width = rows.iter().map(|(path, ..)| path.len()).max().unwrap_or(0);
+    let most = rows.iter().map(|(_, added, removed)| added + removed).max().unwrap_or(0);
+    let count_width = most.to_string().len();
+    // Scale the graph down when it would not fit in 80 columns.
+    let room = 80usize.saturating_sub(name_width + count_width + 6).max(10);
+    let scale = |count: usize| if most > room { (count * room).div_ceil(most) } else { count };
+    for (path, added, removed) in &rows {
+        println!(
+            " {:<name_width$} | {:>count_width$} {}{}",
+            path, added + removed, "+".repeat(scale(*added)), "-".repeat(scale(*removed)),
+            name_width = name_width, count_width = count_width
+        );
+    }
+
+    let (added, removed) = rows.iter().fold((0, 0), |(a, r), (_, added, removed)| (a + added, r + removed));
+    let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+    let mut summary = format!(" {} changed", plural(rows.len(), "file"));
+    if added > 0 || removed == 0 {
+        summary.push_str(&format!(", {}(+)", plural(added, "insertion")));
+    }
+    if removed > 0 || added == 0 {
+        summary.push_str(&format!(", {}(-)", plural(removed, "deletion")));
+    }
+    println!("{}", summary);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A repository whose first commit tracks `f` and `g`.
+    fn repo(name: &str) -> Repository {
+        let repo = Repository::temp(name);
+        let path = repo.worktree().unwrap().to_path_buf();
+        let mut index = Index::new();
+        let mut attrs = Attributes::new(&repo).unwrap();
+        for (file, data) in [("f", "one\n"), ("g", "two\n")] {
+            fs::write(path.join(file), data).unwrap();
+            let (mode, sha) = worktree::store_path(&repo, &mut attrs, file).unwrap().unwrap();
+            index.add(IndexEntry::new(file, &mode, &sha, 0).unwrap());
+        }
+        index.write(&repo).unwrap();
+        let head = commit::create(&repo, &index.write_tree(&repo).unwrap(), &[], "initial", None).unwrap();
+        reference::update(&repo, "HEAD", &head, "").unwrap();
+        repo
+    }
+
+    /// Contents of `path` in the tree of commit `sha`.
+    fn file_at(repo: &Repository, sha: &str, path: &str) -> Option<String> {
+        let tree = tree::flatten(repo, &commit::tree_of(repo, sha).unwrap()).unwrap();
+        tree.get(path).map(|(_, blob)| match object::read(repo, blob).unwrap() {
+            WitObject::BlobObject(blob) => String::from_utf8(blob.data().clone()).unwrap(),
+            _ => panic!("{} is not a blob", blob)
+        })
+    }
+
+    /// Stage `data` as the contents of `path`.
+    fn stage(repo: &Repository, path: &str, data: &str) {
+        fs::write(repo.worktree().unwrap().join(path), data).unwrap();
+        let mut index = Index::read(repo).unwrap();
+        let mut attrs = Attributes::new(repo).unwrap();
+        let (mode, sha) = worktree::store_path(repo, &mut attrs, path).unwrap().unwrap();
+        index.add(IndexEntry::new(path, &mode, &sha, 0).unwrap());
+        index.write(repo).unwrap();
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let repo = repo("stash-round-trip");
+        let top = repo.worktree().unwrap();
+        let head = reference::resolve(&repo, "HEAD").unwrap();
+        stage(&repo, "f", "staged\n");
+        fs::write(top.join("f"), "worktree\n").unwrap();
+        stage(&repo, "new", "added\n");
+        fs::write(top.join("g"), "changed\n").unwrap();
+        fs::write(top.join("loose"), "untracked\n").unwrap();
+
+        push(&repo, &Options { message: Some("test"), keep_index: false, include_untracked: true }).unwrap();
+        let stash = reference::resolve(&repo, STASH_REF).unwrap();
+        let parents = commit::read(&repo, &stash).unwrap().parents();
+        assert_eq!(parents.len(), 3);
+        assert_eq!(parents[0], head);
+        // The index commit holds the staged state and the stash commit the worktree.
+        assert_eq!(commit::read(&repo, &parents[1]).unwrap().parents(), vec![head.clone()]);
+        assert_eq!(file_at(&repo, &parents[1], "f").as_deref(), Some("staged\n"));
+        assert_eq!(file_at(&repo, &parents[1], "g").as_deref(), Some("two\n"));
+        assert_eq!(file_at(&repo, &stash, "f").as_deref(), Some("worktree\n"));
+        assert_eq!(file_at(&repo, &stash, "g").as_deref(), Some("changed\n"));
+        assert_eq!(file_at(&repo, &stash, "new").as_deref(), Some("added\n"));
+        assert_eq!(file_at(&repo, &stash, "loose"), None);
+        // Untracked files live in a root commit of their own.
+        assert!(commit::read(&repo, &parents[2]).unwrap().parents().is_empty());
+        let untracked = tree::flatten(&repo, &commit::tree_of(&repo, &parents[2]).unwrap()).unwrap();
+        assert_eq!(untracked.keys().collect::<Vec<&String>>(), vec!["loose"]);
+
+        // The worktree is clean again.
+        assert_eq!(fs::read_to_string(top.join("f")).unwrap(), "one\n");
+        assert_eq!(fs::read_to_string(top.join("g")).unwrap(), "two\n");
+        assert!(!top.join("new").exists() && !top.join("loose").exists());
+        assert_eq!(Index::read(&repo).unwrap().write_tree(&repo).unwrap(), commit::tree_of(&repo, &head).unwrap());
+
+        assert!(pop(&repo, 0).unwrap());
+        assert_eq!(fs::read_to_string(top.join("f")).unwrap(), "worktree\n");
+        assert_eq!(fs::read_to_string(top.join("g")).unwrap(), "changed\n");
+        assert_eq!(fs::read_to_string(top.join("new")).unwrap(), "added\n");
+        assert_eq!(fs::read_to_string(top.join("loose")).unwrap(), "untracked\n");
+        // Modifications come back unstaged; added files stay in the index.
+        let index = Index::read(&repo).unwrap();
+        let head_tree = tree::flatten(&repo, &commit::tree_of(&repo, &head).unwrap()).unwrap();
+        assert_eq!(index.entry("f", 0).unwrap().hash, head_tree["f"].1);
+        assert_eq!(index.entry("g", 0).unwrap().hash, head_tree["g"].1);
+        assert!(index.entry("new", 0).is_some());
+        assert!(index.entry("loose", 0).is_none());
+        assert!(reference::read(&repo, STASH_REF).unwrap().is_none());
+    }
+
+    #[test]
+    fn keep_index() {
+        let repo = repo("stash-keep-index");
+        let top = repo.worktree().unwrap();
+        stage(&repo, "f", "staged\n");
+        fs::write(top.join("f"), "worktree\n").unwrap();
+
+        push(&repo, &Options { message: None, keep_index: true, include_untracked: false }).unwrap();
+        let stash = reference::resolve(&repo, STASH_REF).unwrap();
+        assert_eq!(commit::read(&repo, &stash).unwrap().parents().len(), 2);
+        // The staged state stays in both the index and the worktree.
+        assert_eq!(fs::read_to_string(top.join("f")).unwrap(), "staged\n");
+        let index = Index::read(&repo).unwrap();
+        assert_eq!(file_at(&repo, &stash, "f").as_deref(), Some("worktree\n"));
+        let parents = commit::read(&repo, &stash).unwrap().parents();
+        let staged = tree::flatten(&repo, &commit::tree_of(&repo, &parents[1]).unwrap()).unwrap();
+        assert_eq!(index.entry("f", 0).unwrap().hash, staged["f"].1);
+    }
+}
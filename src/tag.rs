@@ -51,16 +51,17 @@ pub fn create(repo: &Repository, name: &str, reference: &str, create_object: boo
         kvlm.insert("object".to_owned(), vec![sha]);
         kvlm.insert("type".to_owned(), vec!["commit".to_owned()]);
         kvlm.insert("tag".to_owned(), vec![name.to_owned()]);
-        kvlm.insert("tagger".to_owned(), vec![format!("{} <{}>", "Will Hopkins", "willothyh@gmail.com")]);
+        let (timestamp, tz) = crate::util::now();
+        kvlm.insert("tagger".to_owned(), vec![format!("{} {} {}", repo.identity(), timestamp, tz)]);
         // Commit message
-        kvlm.insert("".to_owned(), vec![format!("{}", "Created by wit")]);
+        kvlm.insert("".to_owned(), vec![format!("{}", "Created by wit\n")]);
 
         // Create the tag object
         let tag_sha = object::write(TagObject(tag), true)?;
         // Create the ref
-        reference::create(&repo, "tags/".to_owned() + name, tag_sha)
+        reference::create(repo, "tags/".to_owned() + name, tag_sha, &format!("tag: tagging {}", name))
     } else {
         // Create lightweight tag
-        reference::create(&repo, "tags/".to_owned() + name, sha)
+        reference::create(repo, "tags/".to_owned() + name, sha, &format!("tag: tagging {}", name))
     }
 }
\ No newline at end of file
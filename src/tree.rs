@@ -1,20 +1,30 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
-use crate::{object::{Find, Object}, error::{WitError, builder::*}};
+use crate::{
+    object::{self, Find, Object, WitObject},
+    repository::Repository,
+    error::{WitError, builder::*}
+};
 
-pub struct Tree {
+/// Flattened tree: path of every blob or gitlink mapped to its `(mode, sha)`.
+pub type Entries = BTreeMap<String, (String, String)>;
+
+pub struct Tree<'a> {
+    repo: Option<&'a Repository>,
     leaves: Vec<Leaf>,
 }
 
-impl Tree {
-    pub fn new() -> Self {
+impl<'a> Tree<'a> {
+    pub fn new(repo: Option<&'a Repository>) -> Self {
         Tree {
+            repo,
             leaves: Vec::new(),
         }
     }
 
-    pub fn from(raw: &Vec<u8>) -> Result<Self, Box<WitError>> {
-        let mut tree = Self::new();
+    pub fn from(repo: Option<&'a Repository>, raw: &Vec<u8>) -> Result<Self, Box<WitError>> {
+        let mut tree = Self::new(repo);
         let mut pos = 0;
 
         while pos < raw.len() {
@@ -35,7 +45,7 @@ impl Tree {
     }
 
     pub fn parse_one(raw: &Vec<u8>, start: usize) -> Result<(usize, Leaf), Box<WitError>> {
-        let mode_end = raw.find_from(b'\n', start)?;
+        let mode_end = raw.find_from(b' ', start)?;
         if mode_end - start != 5 && mode_end - start != 6 {
             return Err(mode_err(mode_end - start));
         }
@@ -50,21 +60,21 @@ impl Tree {
     }
 }
 
-impl Object for Tree {
+impl<'a> Object for Tree<'a> {
     fn serialize(&self) -> Result<Vec<u8>, Box<WitError>> {
         let mut bytes = Vec::<u8>::new();
-        bytes.extend(b"");
 
-        for leaf in self.leaves() {
+        // Git sorts entries by name, comparing subtrees as if they ended with '/'.
+        let mut leaves = self.leaves().iter().collect::<Vec<&Leaf>>();
+        leaves.sort_by_key(|leaf| leaf.sort_key());
+        for leaf in leaves {
             bytes.extend(leaf.mode().as_bytes());
             bytes.push(b' ');
             bytes.extend(leaf.path().to_str().ok_or(
                 utf8_err(format!("Could not convert {} to str.", leaf.path().to_str().unwrap_or("")))
             )?.as_bytes());
             bytes.push(b'\x00');
-            bytes.extend(
-                u32::from_str_radix(leaf.sha(), 16)?.to_be_bytes().to_vec()
-            );
+            bytes.extend(crate::util::unhex(leaf.sha())?);
         }
 
         Ok(bytes)
@@ -82,11 +92,11 @@ impl Object for Tree {
     }
 
     fn fmt(&self) -> Vec<u8> {
-        todo!()
+        b"tree".to_vec()
     }
 
-    fn repo(&self) -> Option<&crate::repository::Repository> {
-        todo!()
+    fn repo(&self) -> Option<&Repository> {
+        self.repo
     }
 }
 
@@ -116,5 +126,55 @@ impl Leaf {
     pub fn sha(&self) -> &str {
         &self.sha
     }
+
+    fn sort_key(&self) -> Vec<u8> {
+        let mut key = self.path.to_string_lossy().as_bytes().to_vec();
+        if self.mode == "40000" {
+            key.push(b'/');
+        }
+        key
+    }
+}
+
+/// Every blob and gitlink reachable from tree `sha`, keyed by full path.
+pub fn flatten(repo: &Repository, sha: &str) -> Result<Entries, Box<WitError>> {
+    fn walk(repo: &Repository, sha: &str, prefix: &str, out: &mut Entries) -> Result<(), Box<WitError>> {
+        let tree = match object::read(repo, sha)? {
+            WitObject::TreeObject(tree) => tree,
+            _ => Err(malformed_object_err(format!("Object {} is not a tree", sha)))?
+        };
+        for leaf in tree.leaves() {
+            let path = format!("{}{}", prefix, leaf.path().to_string_lossy());
+            if leaf.mode() == "40000" {
+                walk(repo, leaf.sha(), &format!("{}/", path), out)?;
+            } else {
+                out.insert(path, (leaf.mode().to_owned(), leaf.sha().to_owned()));
+            }
+        }
+        Ok(())
+    }
+
+    let mut ret = Entries::new();
+    walk(repo, sha, "", &mut ret)?;
+    Ok(ret)
+}
+
+/// Write the trees for flattened `entries` and return the root tree's sha.
+pub fn write(repo: &Repository, entries: &Entries) -> Result<String, Box<WitError>> {
+    let mut tree = Tree::new(Some(repo));
+    let mut subtrees: BTreeMap<&str, Entries> = BTreeMap::new();
+    for (path, (mode, sha)) in entries {
+        match path.split_once('/') {
+            Some((dir, rest)) => {
+                subtrees.entry(dir).or_default().insert(rest.to_owned(), (mode.clone(), sha.clone()));
+            },
+            None => tree.add_leaf(Leaf::new(mode.clone(), PathBuf::from(path), sha.clone()))
+        }
+    }
+    for (dir, entries) in subtrees {
+        let sha = self::write(repo, &entries)?;
+        tree.add_leaf(Leaf::new(String::from("40000"), PathBuf::from(dir), sha));
+    }
+    object::write(WitObject::TreeObject(tree), true)
 }
 
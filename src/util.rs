@@ -1,16 +1,666 @@
 pub fn hex(vec: &Vec<u8>) -> String {
-    let mut sha = 0;
-    let mut buf: [u8; 4] = [0, 0, 0, 0];
-    vec.iter().enumerate().for_each(|(i, x)| {
-        if i % 4 == 0 {
-            // https://stackoverflow.com/questions/36669427/does-rust-have-a-way-to-convert-several-bytes-to-a-number
-            sha +=
-            ((buf[0] as u32) << 24) +
-            ((buf[1] as u32) << 16) +
-            ((buf[2] as u32) <<  8) +
-            ((buf[3] as u32) <<  0);
-        }
-        buf[i % 4] = *x;
-    });
-    format!("{:x}", &sha)
-}
\ No newline at end of file
+    let mut sha = String::with_capacity(vec.len() * 2);
+    for byte in vec {
+        sha.push_str(&format!("{:02x}", byte));
+    }
+    sha
+}
+
+/// The short form of an object name, as shown in messages.
+pub fn abbrev(sha: &str) -> &str {
+    sha.get(..7).unwrap_or(sha)
+}
+
+pub fn unhex(sha: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
+    (0..sha.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&sha[i..(i + 2).min(sha.len())], 16))
+        .collect()
+}
+
+/// Seconds since the epoch and the local timezone offset used for new signatures.
+pub fn now() -> (i64, String) {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let offset = self::local_offset(secs).unwrap_or(0);
+    let sign = if offset < 0 { '-' } else { '+' };
+    (secs, format!("{}{:02}{:02}", sign, offset.abs() / 3600, offset.abs() % 3600 / 60))
+}
+
+/// Offset from UTC in seconds at `at`, from the zoneinfo file `TZ` names or else
+/// `/etc/localtime`.
+fn local_offset(at: i64) -> Option<i64> {
+    self::zone_offset(std::env::var("TZ").ok().as_deref(), std::path::Path::new("/etc/localtime"), at)
+}
+
+/// Offset at `at` for the zone `tz` names, or the zoneinfo file `localtime` without one.
+/// A `tz` that is not a zone name is read as a POSIX rule.
+fn zone_offset(tz: Option<&str>, localtime: &std::path::Path, at: i64) -> Option<i64> {
+    let path = match tz {
+        Some(tz) => {
+            let name = tz.strip_prefix(':').unwrap_or(tz);
+            let path = if name.starts_with('/') {
+                std::path::PathBuf::from(name)
+            } else {
+                std::path::Path::new("/usr/share/zoneinfo").join(name)
+            };
+            if !path.is_file() {
+                return self::posix_offset(name, at);
+            }
+            path
+        },
+        None => localtime.to_path_buf()
+    };
+    self::tzif_offset(&std::fs::read(path).ok()?, at)
+}
+
+/// A POSIX `TZ` time `[+|-]hh[:mm[:ss]]` in seconds, and the text after it.
+fn posix_time(text: &str) -> Option<(i64, &str)> {
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, text.strip_prefix('+').unwrap_or(text))
+    };
+    let end = text.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(text.len());
+    let parts = text[..end].split(':').collect::<Vec<&str>>();
+    if parts.len() > 3 {
+        return None;
+    }
+    let mut secs = 0;
+    for (part, unit) in parts.iter().zip([3600, 60, 1]) {
+        secs += part.parse::<i64>().ok()? * unit;
+    }
+    Some((sign * secs, &text[end..]))
+}
+
+/// The text after a POSIX `TZ` zone name, which is either alphabetic or quoted in `<>`.
+fn posix_name(text: &str) -> Option<&str> {
+    match text.strip_prefix('<') {
+        Some(quoted) => Some(&quoted[quoted.find('>')? + 1..]),
+        None => Some(text.trim_start_matches(|c: char| c.is_ascii_alphabetic()))
+    }
+}
+
+/// Local midnight plus the time of day of a POSIX `TZ` switch date in `year`, as seconds
+/// since the epoch: `Jn` (1-365, never counting February 29), `n` (0-365) or `Mm.w.d`
+/// (day `d` of week `w` of month `m`, week 5 being the last).
+fn posix_date(spec: &str, year: i64) -> Option<i64> {
+    let (date, time) = match spec.split_once('/') {
+        Some((date, time)) => (date, self::posix_time(time).filter(|(_, rest)| rest.is_empty())?.0),
+        None => (spec, 7200)
+    };
+    let jan1 = days_from_civil(year, 1, 1);
+    let day = if let Some(n) = date.strip_prefix('J') {
+        let n = n.parse::<i64>().ok().filter(|n| (1..=365).contains(n))?;
+        let leap = days_from_civil(year, 3, 1) - days_from_civil(year, 2, 1) == 29;
+        jan1 + n - 1 + if leap && n >= 60 { 1 } else { 0 }
+    } else if let Some(rule) = date.strip_prefix('M') {
+        let parts = rule.split('.').map(|n| n.parse::<i64>().ok()).collect::<Option<Vec<i64>>>()?;
+        let (month, week, weekday) = match parts[..] {
+            [month @ 1..=12, week @ 1..=5, weekday @ 0..=6] => (month, week, weekday),
+            _ => return None
+        };
+        let first = days_from_civil(year, month, 1);
+        let next = if month == 12 { days_from_civil(year + 1, 1, 1) } else { days_from_civil(year, month + 1, 1) };
+        // The epoch was a Thursday, weekday 4.
+        let mut day = first + (weekday - first - 4).rem_euclid(7) + (week - 1) * 7;
+        while day >= next {
+            day -= 7;
+        }
+        day
+    } else {
+        jan1 + date.parse::<i64>().ok().filter(|n| (0..=365).contains(n))?
+    };
+    Some(day * 86400 + time)
+}
+
+/// Offset at `at` under a POSIX `TZ` rule such as `<+03>-3` or `CET-1CEST,M3.5.0,M10.5.0/3`,
+/// whose offsets count west of UTC. Daylight saving time starts and ends at the two dates
+/// after the names; without them it follows the US rules, as in glibc.
+fn posix_offset(rule: &str, at: i64) -> Option<i64> {
+    let rest = self::posix_name(rule)?;
+    if rest.is_empty() {
+        // A bare name like `UTC`.
+        return Some(0);
+    }
+    let (offset, rest) = self::posix_time(rest)?;
+    let standard = -offset;
+    if rest.is_empty() {
+        return Some(standard);
+    }
+    let rest = self::posix_name(rest)?;
+    let (daylight, rest) = match rest.is_empty() || rest.starts_with(',') {
+        true => (standard + 3600, rest),
+        false => self::posix_time(rest).map(|(offset, rest)| (-offset, rest))?
+    };
+    let dates = if rest.is_empty() { ",M3.2.0,M11.1.0" } else { rest };
+    let (start, end) = dates.strip_prefix(',')?.split_once(',')?;
+
+    let (year, _, _) = civil_from_days((at + standard).div_euclid(86400));
+    // The switch to daylight saving time is given in standard time and the switch back in
+    // daylight saving time.
+    let start = self::posix_date(start, year)? - standard;
+    let end = self::posix_date(end, year)? - daylight;
+    let summer = match start < end {
+        true => start <= at && at < end,
+        false => at < end || start <= at
+    };
+    Some(if summer { daylight } else { standard })
+}
+
+/// Offset in effect at `at` according to compiled zoneinfo (TZif) data. Times after
+/// the last transition of version 2+ data follow the POSIX rule in the footer.
+fn tzif_offset(data: &[u8], at: i64) -> Option<i64> {
+    let be = |bytes: &[u8]| bytes.iter().fold(0i64, |n, byte| (n << 8) | *byte as i64);
+    let counts = |header: &[u8]| -> Option<Vec<usize>> {
+        if header.get(..4)? != b"TZif" {
+            return None;
+        }
+        Some((0..6).map(|i| be(&header[20 + i * 4..24 + i * 4]) as usize).collect())
+    };
+    let header = data.get(..44)?;
+    // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+    let mut n = counts(header)?;
+    let (mut body, mut time_size) = (&data[44..], 4);
+    if header[4] >= b'2' {
+        let skip = n[3] * 5 + n[4] * 6 + n[5] + n[2] * 8 + n[1] + n[0];
+        let second = data.get(44 + skip..)?;
+        n = counts(second.get(..44)?)?;
+        body = &second[44..];
+        time_size = 8;
+    }
+    let (timecnt, typecnt) = (n[3], n[4]);
+    let times = body.get(..timecnt * time_size)?;
+    let indices = body.get(timecnt * time_size..timecnt * (time_size + 1))?;
+    let types = body.get(timecnt * (time_size + 1)..timecnt * (time_size + 1) + typecnt * 6)?;
+    let utoff = |index: usize| -> Option<i64> {
+        let offset = be(types.get(index * 6..index * 6 + 4)?);
+        Some(if offset >= 1 << 31 { offset - (1 << 32) } else { offset })
+    };
+
+    let transitions = times.chunks(time_size).map(|time| {
+        let time = be(time);
+        match time_size {
+            4 if time >= 1 << 31 => time - (1 << 32),
+            _ => time
+        }
+    }).collect::<Vec<i64>>();
+    let last = transitions.iter().rposition(|time| *time <= at);
+    if time_size == 8 && last.map(|i| i + 1 == timecnt).unwrap_or(timecnt == 0) {
+        // The footer between newlines describes the times after the last transition.
+        let end = timecnt * (time_size + 1) + typecnt * 6 + n[5] + n[2] * 12 + n[1] + n[0];
+        let footer = body.get(end..).and_then(|rest| std::str::from_utf8(rest).ok()).unwrap_or("");
+        let rule = footer.trim_matches('\n');
+        if !rule.is_empty() {
+            return self::posix_offset(rule, at);
+        }
+    }
+    match last {
+        Some(i) => utoff(*indices.get(i)? as usize),
+        None => utoff(0)
+    }
+}
+
+/// Days since the epoch for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The proleptic Gregorian year, month and day of a day count since the epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+/// Parse the date formats accepted in `@{<date>}` and `--expire=<date>`:
+/// `now`, `yesterday`, `<n> <unit>s ago` (spaces or dots), `@<timestamp>`
+/// and `YYYY-MM-DD[ HH:MM[:SS]]` in local time.
+pub fn parse_date(date: &str, now: i64) -> Option<i64> {
+    let date = date.trim().to_lowercase();
+    match date.as_str() {
+        "now" => return Some(now),
+        "never" => return Some(0),
+        "yesterday" => return Some(now - 86400),
+        _ => ()
+    }
+    if let Some(timestamp) = date.strip_prefix('@') {
+        return timestamp.parse::<i64>().ok();
+    }
+
+    let words = date.split([' ', '.']).filter(|w| !w.is_empty()).collect::<Vec<&str>>();
+    if words.len() == 3 && words[2] == "ago" {
+        let count = words[0].parse::<i64>().ok()?;
+        let unit = match words[1].trim_end_matches('s') {
+            "second" | "sec" => 1,
+            "minute" | "min" => 60,
+            "hour" => 3600,
+            "day" => 86400,
+            "week" => 7 * 86400,
+            "month" => 30 * 86400,
+            "year" => 365 * 86400,
+            _ => return None
+        };
+        return Some(now - count * unit);
+    }
+
+    let (day, time) = match date.split_once([' ', 't']) {
+        Some((day, time)) => (day, Some(time)),
+        None => (date.as_str(), None)
+    };
+    let day = day.split('-').map(|n| n.parse::<i64>().ok()).collect::<Option<Vec<i64>>>()?;
+    if day.len() != 3 {
+        return None;
+    }
+    let mut secs = days_from_civil(day[0], day[1], day[2]) * 86400;
+    if let Some(time) = time {
+        let time = time.split(':').map(|n| n.parse::<i64>().ok()).collect::<Option<Vec<i64>>>()?;
+        if time.len() < 2 || time.len() > 3 {
+            return None;
+        }
+        secs += time[0] * 3600 + time[1] * 60 + time.get(2).unwrap_or(&0);
+    }
+    // The offset has to be the one in effect at the resulting time, which may differ
+    // from the offset at `secs` read as UTC around a switch to daylight saving time.
+    let offset = self::local_offset(secs).unwrap_or(0);
+    Some(secs - self::local_offset(secs - offset).unwrap_or(offset))
+}
+
+/// Split a `Name <email> timestamp tz` signature line into its parts.
+pub fn parse_signature(signature: &str) -> (String, String, i64, String) {
+    let (name, rest) = signature.split_once('<').unwrap_or((signature, ""));
+    let (email, date) = rest.split_once('>').unwrap_or((rest, ""));
+    let mut date = date.split_whitespace();
+    let timestamp = date.next().and_then(|t| t.parse::<i64>().ok()).unwrap_or(0);
+    let tz = date.next().unwrap_or("+0000");
+    (name.trim().to_owned(), email.to_owned(), timestamp, tz.to_owned())
+}
+
+/// Offset of a `+hhmm` timezone in seconds.
+fn tz_offset(tz: &str) -> i64 {
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let digits = tz.trim_start_matches(['+', '-']);
+    match digits.parse::<i64>() {
+        Ok(n) => sign * ((n / 100) * 3600 + (n % 100) * 60),
+        Err(_) => 0
+    }
+}
+
+/// Format a timestamp in the given timezone. `style` is one of git's date
+/// formats: `default`, `iso`, `short` or `unix`.
+pub fn format_date(timestamp: i64, tz: &str, style: &str) -> String {
+    if style == "unix" {
+        return timestamp.to_string();
+    }
+    let local = timestamp + tz_offset(tz);
+    let days = local.div_euclid(86400);
+    let secs = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (secs / 3600, secs % 3600 / 60, secs % 60);
+
+    match style {
+        "short" => format!("{:04}-{:02}-{:02}", year, month, day),
+        "iso" => format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+            year, month, day, hour, minute, second, tz
+        ),
+        _ => {
+            const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+            const MONTHS: [&str; 12] = [
+                "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"
+            ];
+            format!(
+                "{} {} {} {:02}:{:02}:{:02} {} {}",
+                WEEKDAYS[days.rem_euclid(7) as usize], MONTHS[(month - 1) as usize], day,
+                hour, minute, second, year, tz
+            )
+        }
+    }
+}
+
+/// Match `text` against a shell glob supporting `*`, `?` and `[...]` classes.
+/// With `pathname`, wildcards do not match `/` except for `**`, as in git's wildmatch.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    fn class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+        // Returns whether `c` matches and the length of the class.
+        let mut i = 1;
+        let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+        if negate {
+            i += 1;
+        }
+        let mut matched = false;
+        let mut first = true;
+        while i < pattern.len() && (first || pattern[i] != ']') {
+            first = false;
+            let lo = if pattern[i] == '\\' && i + 1 < pattern.len() { i += 1; pattern[i] } else { pattern[i] };
+            if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+                if lo <= c && c <= pattern[i + 2] {
+                    matched = true;
+                }
+                i += 3;
+            } else {
+                if lo == c {
+                    matched = true;
+                }
+                i += 1;
+            }
+        }
+        if i >= pattern.len() {
+            return None;
+        }
+        Some((matched != negate, i + 1))
+    }
+
+    type Memo = std::collections::HashMap<(usize, usize), bool>;
+
+    // Results are remembered by how much of the pattern and text is left; without that
+    // `*` and `**` backtrack exponentially on patterns like `**/**/**/x`.
+    fn matches(p: &[char], t: &[char], pathname: bool, memo: &mut Memo) -> bool {
+        if let Some(known) = memo.get(&(p.len(), t.len())) {
+            return *known;
+        }
+        let result = step(p, t, pathname, memo);
+        memo.insert((p.len(), t.len()), result);
+        result
+    }
+
+    fn step(p: &[char], t: &[char], pathname: bool, memo: &mut Memo) -> bool {
+        if p.is_empty() {
+            return t.is_empty();
+        }
+        match p[0] {
+            '*' if pathname && p.get(1) == Some(&'*') => {
+                // `**/` matches zero or more whole directories.
+                if p.get(2) == Some(&'/') {
+                    return (0..=t.len()).any(|i| (i == 0 || t[i - 1] == '/') && matches(&p[3..], &t[i..], pathname, memo));
+                }
+                (0..=t.len()).any(|i| matches(&p[2..], &t[i..], pathname, memo))
+            },
+            '*' => {
+                for i in 0..=t.len() {
+                    if matches(&p[1..], &t[i..], pathname, memo) {
+                        return true;
+                    }
+                    if i < t.len() && pathname && t[i] == '/' {
+                        break;
+                    }
+                }
+                false
+            },
+            '?' => !(t.is_empty() || (pathname && t[0] == '/')) && matches(&p[1..], &t[1..], pathname, memo),
+            '[' => match (t.first(), class(p, *t.first().unwrap_or(&'\0'))) {
+                (Some(c), Some((true, len))) if !(pathname && *c == '/') => matches(&p[len..], &t[1..], pathname, memo),
+                (Some(c), None) => *c == '[' && matches(&p[1..], &t[1..], pathname, memo),
+                _ => false
+            },
+            '\\' if p.len() > 1 => !t.is_empty() && t[0] == p[1] && matches(&p[2..], &t[1..], pathname, memo),
+            c => !t.is_empty() && t[0] == c && matches(&p[1..], &t[1..], pathname, memo)
+        }
+    }
+
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+    matches(&pattern, &text, pathname, &mut Memo::new())
+}
+
+/// `path` with a leading `~/` replaced by the home directory.
+pub fn expand_user(path: &str) -> std::path::PathBuf {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => std::path::Path::new(&home).join(rest),
+        _ => std::path::PathBuf::from(path)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/git/<name>`, where the config home defaults to `~/.config`.
+pub fn xdg_config(name: &str) -> Option<std::path::PathBuf> {
+    std::env::var("XDG_CONFIG_HOME").ok()
+        .map(std::path::PathBuf::from)
+        .or(std::env::var("HOME").ok().map(|home| std::path::Path::new(&home).join(".config")))
+        .map(|config| config.join("git").join(name))
+}
+
+/// Split a command line into words the way git splits aliases: whitespace separates
+/// words, single quotes are literal, and backslashes escape outside single quotes.
+/// `None` if a quote is left open.
+pub fn split_cmdline(line: &str) -> Option<Vec<String>> {
+    let mut ret = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => ret.extend(word.take()),
+            (None, '\'') | (None, '"') => {
+                word.get_or_insert_with(String::new);
+                quote = Some(c);
+            },
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.get_or_insert_with(String::new).push(c),
+            (_, '\\') => word.get_or_insert_with(String::new).push(chars.next()?),
+            (_, c) => word.get_or_insert_with(String::new).push(c)
+        }
+    }
+    if quote.is_some() {
+        return None;
+    }
+    ret.extend(word);
+    Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seconds since the epoch of a UTC date and time of day.
+    fn utc(year: i64, month: i64, day: i64, secs: i64) -> i64 {
+        days_from_civil(year, month, day) * 86400 + secs
+    }
+
+    /// One block of TZif data with the given transitions and offsets, each time
+    /// taking `size` bytes.
+    fn tzif_block(version: u8, transitions: &[(i64, u8)], offsets: &[i64], size: usize) -> Vec<u8> {
+        let mut data = b"TZif".to_vec();
+        data.push(version);
+        data.extend([0; 15]);
+        for count in [0, 0, 0, transitions.len(), offsets.len(), 4] {
+            data.extend((count as u32).to_be_bytes());
+        }
+        for (time, _) in transitions {
+            data.extend(&time.to_be_bytes()[8 - size..]);
+        }
+        data.extend(transitions.iter().map(|(_, index)| *index));
+        for offset in offsets {
+            data.extend((*offset as i32).to_be_bytes());
+            data.extend([0, 0]);
+        }
+        data.extend(b"UTC\0");
+        data
+    }
+
+    #[test]
+    fn tzif_version_1() {
+        let data = tzif_block(0, &[(-1000, 1), (1000, 2)], &[-18000, 3600, 7200], 4);
+        assert_eq!(tzif_offset(&data, -2000), Some(-18000));
+        assert_eq!(tzif_offset(&data, -1000), Some(3600));
+        assert_eq!(tzif_offset(&data, 999), Some(3600));
+        assert_eq!(tzif_offset(&data, 1000), Some(7200));
+        // Version 1 data has no footer, so the last transition holds forever.
+        assert_eq!(tzif_offset(&data, utc(2100, 7, 1, 0)), Some(7200));
+    }
+
+    #[test]
+    fn tzif_version_2() {
+        // The version 1 block is skipped in favor of the 64-bit one.
+        let mut data = tzif_block(b'2', &[], &[0], 4);
+        data.extend(tzif_block(b'2', &[(-5_000_000_000, 1), (utc(2000, 1, 1, 0), 2)], &[100, -18000, 3600], 8));
+        data.extend(b"\nEST5EDT,M3.2.0,M11.1.0\n");
+        assert_eq!(tzif_offset(&data, -6_000_000_000), Some(100));
+        assert_eq!(tzif_offset(&data, -4_000_000_000), Some(-18000));
+        // After the last transition the footer rule applies, with daylight saving time.
+        assert_eq!(tzif_offset(&data, utc(2030, 1, 15, 0)), Some(-18000));
+        assert_eq!(tzif_offset(&data, utc(2030, 7, 15, 0)), Some(-14400));
+
+        // Without a footer the last transition holds.
+        let mut data = tzif_block(b'3', &[], &[0], 4);
+        data.extend(tzif_block(b'3', &[(0, 1)], &[0, 19800], 8));
+        data.extend(b"\n\n");
+        assert_eq!(tzif_offset(&data, utc(2030, 7, 15, 0)), Some(19800));
+    }
+
+    #[test]
+    fn tzif_garbled() {
+        assert_eq!(tzif_offset(b"", 0), None);
+        assert_eq!(tzif_offset(b"not a zoneinfo file at all, but long enough to have a header", 0), None);
+        let data = tzif_block(0, &[(0, 1)], &[0, 3600], 4);
+        assert_eq!(tzif_offset(&data[..data.len() - 10], 0), None);
+    }
+
+    #[test]
+    fn missing_or_garbled_localtime() {
+        let dir = std::env::temp_dir().join(format!("wit-util-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(zone_offset(None, &dir.join("missing"), 0), None);
+        std::fs::write(dir.join("garbled"), b"TZif2 garbage").unwrap();
+        assert_eq!(zone_offset(None, &dir.join("garbled"), 0), None);
+        std::fs::write(dir.join("zone"), tzif_block(0, &[], &[-7200], 4)).unwrap();
+        assert_eq!(zone_offset(None, &dir.join("zone"), 0), Some(-7200));
+        // `TZ` takes precedence, as a file or else as a POSIX rule.
+        let tz = format!(":{}", dir.join("zone").display());
+        assert_eq!(zone_offset(Some(&tz), &dir.join("missing"), 0), Some(-7200));
+        assert_eq!(zone_offset(Some("<+0530>-5:30"), &dir.join("zone"), 0), Some(19800));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn posix_rules() {
+        assert_eq!(posix_offset("UTC", 0), Some(0));
+        assert_eq!(posix_offset("EST5", 0), Some(-18000));
+        assert_eq!(posix_offset("<+03>-3", 0), Some(10800));
+        assert_eq!(posix_offset("<-0330>+3:30", 0), Some(-12600));
+        assert_eq!(posix_offset("EST", 0), Some(0));
+        assert_eq!(posix_offset("<+03", 0), None);
+        assert_eq!(posix_offset("EST5EDT,M13.1.0,M11.1.0", 0), None);
+
+        // US rules switch at 02:00 local time, on the second Sunday of March and the
+        // first Sunday of November.
+        let us = "EST5EDT,M3.2.0,M11.1.0";
+        assert_eq!(posix_offset(us, utc(2021, 3, 14, 6 * 3600 + 3599)), Some(-18000));
+        assert_eq!(posix_offset(us, utc(2021, 3, 14, 7 * 3600)), Some(-14400));
+        assert_eq!(posix_offset(us, utc(2021, 11, 7, 5 * 3600 + 3599)), Some(-14400));
+        assert_eq!(posix_offset(us, utc(2021, 11, 7, 6 * 3600)), Some(-18000));
+        // Without dates, daylight saving time follows the US rules.
+        assert_eq!(posix_offset("EST5EDT", utc(2021, 7, 1, 0)), Some(-14400));
+
+        // The last Sunday of March and October, switching back at 03:00.
+        let eu = "CET-1CEST,M3.5.0,M10.5.0/3";
+        assert_eq!(posix_offset(eu, utc(2021, 3, 28, 3599)), Some(3600));
+        assert_eq!(posix_offset(eu, utc(2021, 3, 28, 3600)), Some(7200));
+        assert_eq!(posix_offset(eu, utc(2021, 10, 31, 3599)), Some(7200));
+        assert_eq!(posix_offset(eu, utc(2021, 10, 31, 3600)), Some(3600));
+
+        // In the southern hemisphere daylight saving time spans the new year.
+        let au = "AEST-10AEDT,M10.1.0,M4.1.0/3";
+        assert_eq!(posix_offset(au, utc(2021, 1, 15, 0)), Some(39600));
+        assert_eq!(posix_offset(au, utc(2021, 7, 15, 0)), Some(36000));
+        assert_eq!(posix_offset(au, utc(2021, 12, 15, 0)), Some(39600));
+
+        // Julian days skip February 29, so J60 is always March 1st; plain day numbers
+        // count it, so day 300 of a leap year is October 27th.
+        let julian = "XST-1XDT-3,J60/0,300";
+        assert_eq!(posix_offset(julian, utc(2020, 2, 29, 22 * 3600 + 3599)), Some(3600));
+        assert_eq!(posix_offset(julian, utc(2020, 2, 29, 23 * 3600)), Some(10800));
+        assert_eq!(posix_offset(julian, utc(2021, 2, 28, 23 * 3600)), Some(10800));
+        assert_eq!(posix_offset(julian, utc(2020, 10, 26, 22 * 3600 + 3599)), Some(10800));
+        assert_eq!(posix_offset(julian, utc(2020, 10, 27, 0)), Some(3600));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("now", 1000), Some(1000));
+        assert_eq!(parse_date("@1234", 0), Some(1234));
+        assert_eq!(parse_date("2 days ago", 200000), Some(200000 - 2 * 86400));
+        assert_eq!(parse_date("3.hours.ago", 20000), Some(20000 - 3 * 3600));
+        assert_eq!(parse_date("2021-13", 0), None);
+        // Dates without a zone are in local time.
+        let day = parse_date("2021-06-01", 0).unwrap();
+        assert_eq!(day + local_offset(day).unwrap_or(0), utc(2021, 6, 1, 0));
+        let time = parse_date("2021-06-01 10:30", 0).unwrap();
+        assert_eq!(time - day, 10 * 3600 + 30 * 60);
+    }
+
+    #[test]
+    fn star_and_question_mark() {
+        assert!(wildmatch("*.rs", "main.rs", false));
+        assert!(wildmatch("*", "", false));
+        assert!(!wildmatch("*.rs", "main.rc", false));
+        assert!(wildmatch("ma?n", "main", false));
+        assert!(!wildmatch("ma?n", "man", false));
+        // Without pathname, wildcards cross directory boundaries.
+        assert!(wildmatch("refs/*", "refs/heads/main", false));
+        assert!(wildmatch("a?b", "a/b", false));
+    }
+
+    #[test]
+    fn pathname_mode() {
+        assert!(!wildmatch("refs/*", "refs/heads/main", true));
+        assert!(wildmatch("refs/*/main", "refs/heads/main", true));
+        assert!(!wildmatch("a?b", "a/b", true));
+        assert!(!wildmatch("a[/]b", "a/b", true));
+    }
+
+    #[test]
+    fn double_star() {
+        assert!(wildmatch("**/main", "main", true));
+        assert!(wildmatch("**/main", "refs/heads/main", true));
+        assert!(wildmatch("refs/**", "refs/heads/main", true));
+        assert!(wildmatch("a/**/b", "a/b", true));
+        assert!(wildmatch("a/**/b", "a/x/y/b", true));
+        assert!(!wildmatch("a/**/b", "a/xb", true));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(wildmatch("[abc]", "b", false));
+        assert!(!wildmatch("[abc]", "d", false));
+        assert!(wildmatch("[a-c]x", "cx", false));
+        assert!(wildmatch("[!a-c]", "d", false));
+        assert!(wildmatch("[^a-c]", "d", false));
+        assert!(!wildmatch("[!a-c]", "a", false));
+        assert!(wildmatch("[]]", "]", false));
+        assert!(wildmatch("[a-]", "-", false));
+        // An unclosed class is a literal `[`.
+        assert!(wildmatch("[ab", "[ab", false));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(wildmatch("\\*", "*", false));
+        assert!(!wildmatch("\\*", "x", false));
+        assert!(wildmatch("a\\?", "a?", false));
+        assert!(wildmatch("[\\]]", "]", false));
+    }
+
+    #[test]
+    fn backtracking_is_bounded() {
+        // Each of these takes exponential time when every split is retried.
+        let deep = "a/".repeat(30) + "y";
+        assert!(!wildmatch(&format!("{}x", "**/".repeat(15)), &deep, true));
+        assert!(wildmatch(&format!("{}y", "**/".repeat(15)), &deep, true));
+        let long = "a".repeat(40);
+        assert!(!wildmatch(&format!("{}b", "*a".repeat(20)), &long, false));
+        assert!(!wildmatch(&format!("{}b", "**a".repeat(20)), &long, true));
+    }
+}
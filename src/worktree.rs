@@ -0,0 +1,272 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    repository::Repository,
+    object::{self, WitObject},
+    index::{Index, IndexEntry},
+    tree::{self, Entries},
+    ignore::Ignore,
+    attr::Attributes,
+    convert,
+    error::{WitError, builder::*}
+};
+
+/// Whether modes recorded for worktree files can be trusted, from `core.filemode`.
+pub fn trust_filemode(repo: &Repository) -> bool {
+    repo.conf.get_bool("core.filemode").ok().flatten().unwrap_or(true)
+}
+
+/// `(mode, sha)` of a worktree file as it would be staged, or `None` if it is missing.
+pub fn hash_path(repo: &Repository, attrs: &mut Attributes, path: &str) -> Result<Option<(String, String)>, Box<WitError>> {
+    self::hash_file(repo, attrs, path, false)
+}
+
+/// Like `hash_path`, but the blob is written to the object store as well.
+pub fn store_path(repo: &Repository, attrs: &mut Attributes, path: &str) -> Result<Option<(String, String)>, Box<WitError>> {
+    self::hash_file(repo, attrs, path, true)
+}
+
+fn hash_file(repo: &Repository, attrs: &mut Attributes, path: &str, store: bool) -> Result<Option<(String, String)>, Box<WitError>> {
+    let full = repo.worktree()?.join(path);
+    if fs::symlink_metadata(&full).is_err() || full.is_dir() && !full.is_symlink() {
+        return Ok(None);
+    }
+    let (mode, data) = object::read_worktree(&full, self::trust_filemode(repo))?;
+    let data = match mode {
+        "120000" => data,
+        _ => convert::to_git(repo, attrs, path, data)?
+    };
+    let sha = object::hash_data(data, "blob", if store { Some(repo) } else { None })?;
+    Ok(Some((mode.to_owned(), sha)))
+}
+
+/// Whether the worktree copy of `entry` differs from what is staged.
+pub fn is_modified(repo: &Repository, attrs: &mut Attributes, entry: &IndexEntry) -> Result<bool, Box<WitError>> {
+    let full = repo.worktree()?.join(&entry.file_path);
+    if entry.stat_matches(&full) {
+        return Ok(false);
+    }
+    Ok(match self::hash_path(repo, attrs, &entry.file_path)? {
+        Some((mode, sha)) => sha != entry.hash || (self::trust_filemode(repo) && mode != entry.mode_str()),
+        None => true
+    })
+}
+
+/// Write a blob or gitlink to the worktree, creating leading directories.
+pub fn checkout_file(repo: &Repository, attrs: &mut Attributes, path: &str, mode: &str, sha: &str) -> Result<(), Box<WitError>> {
+    let top = repo.worktree()?;
+    let dest = top.join(path);
+    if let Some(parent) = dest.parent() {
+        // A file may be in the way of a directory the new entry needs.
+        let mut dir = parent.to_path_buf();
+        while dir != top {
+            if fs::symlink_metadata(&dir).map(|meta| !meta.is_dir()).unwrap_or(false) {
+                fs::remove_file(&dir)?;
+            }
+            dir = dir.parent().map(Path::to_path_buf).unwrap_or(top.to_path_buf());
+        }
+        fs::create_dir_all(parent)?;
+    }
+    if mode == "160000" {
+        fs::create_dir_all(&dest)?;
+        return Ok(());
+    }
+    if dest.is_dir() && !dest.is_symlink() {
+        fs::remove_dir_all(&dest)?;
+    }
+    match object::read(repo, sha)? {
+        WitObject::BlobObject(blob) if mode == "120000" => object::write_worktree(&dest, mode, blob.data()),
+        WitObject::BlobObject(blob) => {
+            let data = convert::to_worktree(repo, attrs, path, blob.data().to_vec())?;
+            object::write_worktree(&dest, mode, &data)
+        },
+        _ => Err(malformed_object_err(format!("Object {} is not a blob", sha)))
+    }
+}
+
+/// Delete a worktree file and any directories it leaves empty.
+pub fn remove_file(repo: &Repository, path: &str) -> Result<(), Box<WitError>> {
+    let top = repo.worktree()?;
+    let full = top.join(path);
+    match fs::symlink_metadata(&full) {
+        Ok(meta) if meta.is_dir() => { let _ = fs::remove_dir(&full); },
+        Ok(_) => fs::remove_file(&full)?,
+        Err(_) => ()
+    }
+    let mut dir = full.parent().map(Path::to_path_buf);
+    while let Some(current) = dir {
+        if current == top || fs::remove_dir(&current).is_err() {
+            break;
+        }
+        dir = current.parent().map(Path::to_path_buf);
+    }
+    Ok(())
+}
+
+/// Move the worktree from the files tracked by `from` to `to`, touching only paths
+/// that differ between the two. Unless `force` is set, nothing is written when local
+/// changes or untracked files would be lost; `operation` names the command in that error.
+/// Afterwards `index` gets stat data for every stage 0 entry that is known to be clean.
+pub fn switch(
+    repo: &Repository,
+    from: &Index,
+    to: &Entries,
+    index: &mut Index,
+    force: bool,
+    operation: &str
+) -> Result<(), Box<WitError>> {
+    let current = from.tree_entries();
+    let unmerged = from.conflicts().into_iter().collect::<BTreeSet<String>>();
+    let changed = current.keys()
+        .chain(to.keys())
+        .chain(unmerged.iter())
+        .filter(|path| current.get(*path) != to.get(*path) || unmerged.contains(*path))
+        .cloned()
+        .collect::<BTreeSet<String>>();
+
+    let mut attrs = Attributes::new(repo)?;
+    if !force {
+        let mut dirty = Vec::new();
+        let mut untracked = Vec::new();
+        for path in &changed {
+            let wanted = to.get(path).map(|(mode, sha)| (mode.clone(), sha.clone()));
+            match from.entry(path, 0) {
+                Some(entry) => if self::is_modified(repo, &mut attrs, entry)? && self::hash_path(repo, &mut attrs, path)? != wanted {
+                    dirty.push(path.clone());
+                },
+                None => if wanted.is_some() && !unmerged.contains(path) {
+                    if let Some(found) = self::hash_path(repo, &mut attrs, path)? {
+                        if Some(found) != wanted {
+                            untracked.push(path.clone());
+                        }
+                    }
+                }
+            }
+        }
+        let list = |paths: &Vec<String>| paths.iter().map(|path| format!("\t{}", path)).collect::<Vec<String>>().join("\n");
+        if !dirty.is_empty() {
+            Err(dirty_worktree_err(format!(
+                "Your local changes to the following files would be overwritten by {}:\n{}\nPlease commit your changes or stash them before you {}.",
+                operation, list(&dirty), operation
+            )))?
+        }
+        if !untracked.is_empty() {
+            Err(dirty_worktree_err(format!(
+                "The following untracked working tree files would be overwritten by {}:\n{}\nPlease move or remove them before you {}.",
+                operation, list(&untracked), operation
+            )))?
+        }
+    }
+
+    // Removals first so that files and directories can trade places.
+    for path in changed.iter().filter(|path| !to.contains_key(*path)) {
+        self::remove_file(repo, path)?;
+    }
+    // `.gitattributes` files first: the others are written the way the new ones say.
+    let (attr_files, files): (Vec<&String>, Vec<&String>) = changed.iter()
+        .filter(|path| to.contains_key(*path))
+        .partition(|path| path.rsplit('/').next() == Some(".gitattributes"));
+    for path in &attr_files {
+        let (mode, sha) = &to[*path];
+        self::checkout_file(repo, &mut attrs, path, mode, sha)?;
+    }
+    if !attr_files.is_empty() {
+        attrs = Attributes::new(repo)?;
+    }
+    for path in files {
+        let (mode, sha) = &to[path];
+        self::checkout_file(repo, &mut attrs, path, mode, sha)?;
+    }
+
+    for entry in index.entries_mut().iter_mut().filter(|entry| entry.stage() == 0) {
+        let path = &entry.file_path;
+        let wanted = to.get(path);
+        if wanted != Some(&(entry.mode_str(), entry.hash.clone())) {
+            continue;
+        }
+        if changed.contains(path) {
+            entry.refresh(&repo.worktree()?.join(path))?;
+        } else if let Some(old) = from.entry(path, 0) {
+            entry.copy_stat(old);
+        }
+    }
+    Ok(())
+}
+
+/// Make the index and worktree match `tree`, throwing away staged changes and
+/// conflicts. Worktree changes to paths the tree does not touch are kept.
+pub fn reset(repo: &Repository, tree: &str) -> Result<(), Box<WitError>> {
+    let current = Index::read(repo)?;
+    let mut index = Index::from_tree(repo, tree)?;
+    self::switch(repo, &current, &tree::flatten(repo, tree)?, &mut index, true, "reset")?;
+    index.write(repo)
+}
+
+/// Like `reset`, but local changes to tracked files are thrown away too.
+pub fn hard_reset(repo: &Repository, tree: &str) -> Result<(), Box<WitError>> {
+    let mut current = Index::read(repo)?;
+    // Forget what is staged for modified files so that `switch` rewrites them.
+    let mut attrs = Attributes::new(repo)?;
+    for entry in current.entries_mut().iter_mut().filter(|entry| entry.stage() == 0) {
+        if self::is_modified(repo, &mut attrs, entry)? {
+            entry.hash = String::new();
+        }
+    }
+    let mut index = Index::from_tree(repo, tree)?;
+    self::switch(repo, &current, &tree::flatten(repo, tree)?, &mut index, true, "reset")?;
+    index.write(repo)
+}
+
+/// Turn a path given on the command line, relative to the current directory,
+/// into a path relative to the top of the worktree. `.` at the top becomes empty.
+pub fn pathspec(repo: &Repository, arg: &str) -> Result<String, Box<WitError>> {
+    let cwd = std::env::current_dir()?;
+    let mut ret = Vec::<String>::new();
+    for part in cwd.join(arg).components() {
+        match part {
+            std::path::Component::ParentDir => { ret.pop(); },
+            std::path::Component::CurDir => (),
+            other => ret.push(other.as_os_str().to_str().ok_or(path_conversion_err())?.to_owned())
+        }
+    }
+    let full = Path::new("/").join(ret.join("/"));
+    let top = repo.worktree()?.canonicalize()?;
+    let relative = full.strip_prefix(&top).map_err(
+        |_| io_err(format!("'{}' is outside repository at '{}'", arg, top.display()))
+    )?;
+    Ok(relative.to_str().ok_or(path_conversion_err())?.to_owned())
+}
+
+/// Whether `path` is `spec` itself or lies below it.
+pub fn in_pathspec(path: &str, spec: &str) -> bool {
+    spec.is_empty() || path == spec || path.strip_prefix(spec).map(|rest| rest.starts_with('/')).unwrap_or(false)
+}
+
+/// Files in the worktree that `index` does not track and the ignore rules do not exclude, sorted.
+pub fn untracked(repo: &Repository, index: &Index) -> Result<Vec<String>, Box<WitError>> {
+    fn walk(dir: &Path, prefix: &str, tracked: &BTreeSet<&str>, ignore: &mut Ignore, out: &mut Vec<String>) -> Result<(), Box<WitError>> {
+        for file in fs::read_dir(dir)? {
+            let file = file?;
+            let name = file.file_name().to_str().ok_or(path_conversion_err())?.to_owned();
+            let path = format!("{}{}", prefix, name);
+            let is_dir = file.file_type()?.is_dir();
+            if name == ".git" || tracked.contains(path.as_str()) || ignore.is_ignored(&path, is_dir)? {
+                continue;
+            }
+            if is_dir {
+                walk(&file.path(), &format!("{}/", path), tracked, ignore, out)?;
+            } else {
+                out.push(path);
+            }
+        }
+        Ok(())
+    }
+    let tracked = index.entries().iter().map(|entry| entry.file_path.as_str()).collect::<BTreeSet<&str>>();
+    let mut ignore = Ignore::new(repo)?;
+    let mut ret = Vec::new();
+    walk(repo.worktree()?, "", &tracked, &mut ignore, &mut ret)?;
+    ret.sort();
+    Ok(ret)
+}